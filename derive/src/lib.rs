@@ -0,0 +1,115 @@
+//! The derive macros of `micro-adapton-rs`, which re-exports them. See the documentation there.
+
+extern crate proc_macro;
+
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Error, Fields, Index};
+
+/// Generates a mirror of a struct that holds an aref for each field. See `AdaptonInputs` in
+/// `micro-adapton-rs`.
+#[proc_macro_derive(AdaptonInputs)]
+pub fn derive_adapton_inputs(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    adapton_inputs(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn adapton_inputs(input: DeriveInput) -> Result<TokenStream, Error> {
+    let fields = match &input.data {
+        Data::Struct(data) if !matches!(data.fields, Fields::Unit) => &data.fields,
+        _ => {
+            return Err(Error::new(
+                Span::call_site(),
+                "AdaptonInputs can only be derived for structs with fields",
+            ))
+        }
+    };
+    let krate = quote!(::micro_adapton_rs);
+    let name = &input.ident;
+    let vis = &input.vis;
+    let mirror = format_ident!("{}Inputs", name);
+    let doc = format!("The arefs holding each field of a `{}`.", name);
+
+    // The struct's fields, as they're accessed and as the mirror names them.
+    let members: Vec<TokenStream> = match fields {
+        Fields::Named(named) => named
+            .named
+            .iter()
+            .flat_map(|f| &f.ident)
+            .map(|i| quote!(#i))
+            .collect(),
+        _ => (0..fields.len())
+            .map(|i| {
+                let i = Index::from(i);
+                quote!(#i)
+            })
+            .collect(),
+    };
+    let vises: Vec<_> = fields.iter().map(|f| &f.vis).collect();
+    let loaded = members
+        .iter()
+        .map(|m| quote!(graph.new_aref(#krate::ToInput::to_input(&value.#m))));
+    let (definition, load) = match fields {
+        Fields::Named(_) => (
+            quote!(#vis struct #mirror { #(#vises #members: #krate::ARefID,)* }),
+            quote!(Self { #(#members: #loaded,)* }),
+        ),
+        _ => (
+            quote!(#vis struct #mirror(#(#vises #krate::ARefID),*);),
+            quote!(Self(#(#loaded),*)),
+        ),
+    };
+
+    // The methods take the struct's own generics, along with the graph's scalar and hasher, and
+    // need every field to be convertible to the scalar.
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+    let ty = quote!(#name #ty_generics);
+    let mut generics = input.generics.clone();
+    generics
+        .params
+        .push(parse_quote!(AdaptonScalar: #krate::Scalar));
+    generics
+        .params
+        .push(parse_quote!(AdaptonHasher: #krate::MemoHasher));
+    let predicates = &mut generics.make_where_clause().predicates;
+    for field in fields.iter() {
+        let field_ty = &field.ty;
+        predicates.push(parse_quote!(#field_ty: #krate::ToInput<AdaptonScalar>));
+    }
+    let (method_generics, _, where_clause) = generics.split_for_impl();
+    let graph = quote!(#krate::Graph<AdaptonScalar, AdaptonHasher>);
+
+    Ok(quote! {
+        #[doc = #doc]
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+        #definition
+
+        impl #mirror {
+            /// Creates an aref for each field of `value`.
+            #vis fn load #method_generics(graph: &mut #graph, value: &#ty) -> Self #where_clause {
+                #load
+            }
+
+            /// Updates the arefs of the fields that differ between `old` and `new`, and returns
+            /// how many did.
+            #vis fn update_changed #method_generics(
+                &self,
+                graph: &mut #graph,
+                old: &#ty,
+                new: &#ty,
+            ) -> usize #where_clause {
+                let mut changed = 0;
+                #(
+                    let val = #krate::ToInput::to_input(&new.#members);
+                    if #krate::ToInput::to_input(&old.#members) != val {
+                        graph.update_aref(self.#members, val);
+                        changed += 1;
+                    }
+                )*
+                changed
+            }
+        }
+    })
+}
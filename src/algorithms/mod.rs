@@ -0,0 +1,8 @@
+//! Classic algorithms built out of nodes, as building blocks and as workloads that exercise the
+//! engine the way a real program would.
+
+mod paths;
+mod sort;
+
+pub use paths::{shortest_paths, AShortestPaths};
+pub use sort::{incremental_sort, ASorted};
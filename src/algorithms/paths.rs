@@ -0,0 +1,217 @@
+//! Incremental single-source shortest paths, by Bellman-Ford.
+//!
+//! There's a node per vertex per round of relaxation, holding the length of the shortest path to
+//! the vertex with at most that many edges. Each reads the weights of just the edges into its
+//! vertex, and the previous round's distances to where they come from, and has an exact cutoff,
+//! so updating a weight only re-relaxes the vertices whose distances it actually changed, along
+//! with whatever reads those.
+//!
+//! Which edges go into a vertex is worked out once for every round, by a node per vertex that
+//! finds the next edge into it from a given vertex on, with that vertex as its argument. Only
+//! these read the weights of missing edges. They have an exact cutoff too, so changing the weight
+//! of an edge re-runs them without re-running anything else on their account.
+
+use crate::{AThunkID, AVec, AdaptonError, Cutoff, Graph, Handle};
+use std::rc::Rc;
+
+/// The distances from a source vertex, as returned by `shortest_paths`.
+#[derive(Clone)]
+pub struct AShortestPaths {
+    // The nodes of each round, by vertex. Every shortest path without a negative cycle has fewer
+    // edges than there are vertices, so the last round is only there to look for one.
+    rounds: Vec<Vec<AThunkID>>,
+}
+
+impl AShortestPaths {
+    /// The node computing the distance to `v`.
+    pub fn vertex(&self, v: usize) -> AThunkID {
+        self.settled()[v]
+    }
+
+    /// The length of the shortest path to `v`, or infinity if there isn't one.
+    pub fn distance(&self, graph: &Graph, v: usize) -> Result<f64, AdaptonError> {
+        graph.compute(self.vertex(v), &[])
+    }
+
+    pub fn distances(&self, graph: &Graph) -> Result<Vec<f64>, AdaptonError> {
+        self.settled()
+            .iter()
+            .map(|&v| graph.compute(v, &[]))
+            .collect()
+    }
+
+    /// Whether a negative cycle can be reached from the source, in which case the distances
+    /// aren't shortest paths at all.
+    pub fn has_negative_cycle(&self, graph: &Graph) -> Result<bool, AdaptonError> {
+        let last = self.rounds.last().unwrap();
+        for (&settled, &extra) in self.settled().iter().zip(last) {
+            if graph.compute(extra, &[])? < graph.compute(settled, &[])? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn settled(&self) -> &[AThunkID] {
+        &self.rounds[self.rounds.len() - 2]
+    }
+}
+
+/// Distances from `source` over the weights in `weights`, an n by n matrix in row-major order,
+/// where element `u * n + v` is the weight of the edge from `u` to `v` and infinity means there's
+/// no such edge. The distances follow the weights as they're updated.
+///
+/// Panics if `weights` isn't square or `source` isn't one of its vertices.
+pub fn shortest_paths(graph: &mut Graph, weights: &AVec, source: usize) -> AShortestPaths {
+    let n = (weights.len() as f64).sqrt() as usize;
+    assert_eq!(n * n, weights.len(), "the weights must be a square matrix");
+    assert!(source < n, "no vertex {} among {}", source, n);
+
+    let first: Vec<AThunkID> = (0..n)
+        .map(|v| {
+            graph.new_athunk(move |_: &mut Handle| if v == source { 0.0 } else { f64::INFINITY })
+        })
+        .collect();
+    let incoming: Rc<[AThunkID]> = (0..n)
+        .map(|v| {
+            let weights = weights.clone();
+            let id = graph
+                .new_athunk_with_arity(1, move |h: &mut Handle| next_incoming(h, &weights, n, v));
+            graph.set_node_cutoff(id, Cutoff::Exact);
+            id
+        })
+        .collect();
+    let mut rounds = vec![first];
+    while rounds.len() <= n {
+        let prev = rounds.last().unwrap().clone();
+        let round = (0..n)
+            .map(|v| {
+                let (prev, weights, incoming) = (prev.clone(), weights.clone(), incoming.clone());
+                let id = graph
+                    .new_athunk(move |h: &mut Handle| relax(h, &prev, &weights, incoming[v], v));
+                graph.set_node_cutoff(id, Cutoff::Exact);
+                id
+            })
+            .collect();
+        rounds.push(round);
+    }
+    AShortestPaths { rounds }
+}
+
+// The first vertex from the argument on with an edge into `v`, or `n` if there isn't one.
+fn next_incoming(h: &mut Handle, weights: &AVec, n: usize, v: usize) -> f64 {
+    let start = h.args[0] as usize;
+    for u in (start..n).filter(|&u| u != v) {
+        let w = weights.element(u * n + v);
+        h.add_edge(w);
+        if h.compute(w, &[]).unwrap_or(f64::INFINITY) < f64::INFINITY {
+            return u as f64;
+        }
+    }
+    n as f64
+}
+
+// The shortest distance to `v` through at most one more edge than `prev`'s distances.
+fn relax(h: &mut Handle, prev: &[AThunkID], weights: &AVec, incoming: AThunkID, v: usize) -> f64 {
+    let n = prev.len();
+    let read = |h: &mut Handle, id: AThunkID, args: &[f64], missing: f64| {
+        h.add_edge(id);
+        h.compute(id, args).unwrap_or(missing)
+    };
+    let mut best = read(h, prev[v], &[], f64::INFINITY);
+    let mut u = read(h, incoming, &[0.0], n as f64) as usize;
+    while u < n {
+        let w = read(h, weights.element(u * n + v).id(), &[], f64::INFINITY);
+        best = best.min(read(h, prev[u], &[], f64::INFINITY) + w);
+        u = read(h, incoming, &[(u + 1) as f64], n as f64) as usize;
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::shortest_paths;
+    use crate::{AThunkID, Graph};
+
+    const INF: f64 = f64::INFINITY;
+
+    // Floyd-Warshall, to check against.
+    fn expected(weights: &[f64], n: usize, source: usize) -> Vec<f64> {
+        let mut d = weights.to_vec();
+        for v in 0..n {
+            d[v * n + v] = d[v * n + v].min(0.0);
+        }
+        for k in 0..n {
+            for i in 0..n {
+                for j in 0..n {
+                    d[i * n + j] = d[i * n + j].min(d[i * n + k] + d[k * n + j]);
+                }
+            }
+        }
+        d[source * n..(source + 1) * n].to_vec()
+    }
+
+    #[test]
+    fn shortest_paths_follow_weights() {
+        let n = 5;
+        #[rustfmt::skip]
+        let mut w = vec![
+            INF, 4.0, 1.0, INF, INF,
+            INF, INF, INF, 1.0, INF,
+            INF, 2.0, INF, 5.0, INF,
+            INF, INF, INF, INF, 3.0,
+            INF, INF, INF, INF, INF,
+        ];
+        let mut graph = Graph::new();
+        let weights = graph.new_avec(&w);
+        let paths = shortest_paths(&mut graph, &weights, 0);
+        assert_eq!(Ok(expected(&w, n, 0)), paths.distances(&graph));
+        assert_eq!(Ok(vec![0.0, 3.0, 1.0, 4.0, 7.0]), paths.distances(&graph));
+
+        let total = graph.runs.get();
+        for (i, v) in [(3 * n + 4, 1.0), (2, 5.0), (4 * n, 1.0), (2 * n + 3, INF)] {
+            w[i] = v;
+            graph.update_avec(&weights, &[(i, v)]);
+            let before = graph.runs.get();
+            assert_eq!(Ok(expected(&w, n, 0)), paths.distances(&graph));
+            assert!(graph.runs.get() - before < total);
+        }
+        assert_eq!(Ok(false), paths.has_negative_cycle(&graph));
+
+        // 1 -> 3 -> 4 -> 1 comes to -1.
+        graph.update_avec(&weights, &[(4 * n + 1, -3.0)]);
+        assert_eq!(Ok(true), paths.has_negative_cycle(&graph));
+    }
+
+    #[test]
+    fn one_weight_recomputes() {
+        let n = 5;
+        #[rustfmt::skip]
+        let w = vec![
+            INF, 4.0, 1.0, INF, INF,
+            INF, INF, INF, 1.0, INF,
+            INF, 2.0, INF, 5.0, INF,
+            INF, INF, INF, INF, 3.0,
+            INF, INF, INF, INF, INF,
+        ];
+        let mut graph = Graph::new();
+        let weights = graph.new_avec(&w);
+        let paths = shortest_paths(&mut graph, &weights, 0);
+        assert_eq!(Ok(7.0), paths.distance(&graph, 4));
+        assert_eq!(Ok(false), paths.has_negative_cycle(&graph));
+
+        // The weight of 3 -> 4 is only read by the rounds of vertex 4, and by the node finding the
+        // edges into it, which was created right after the first round. That re-runs its search
+        // from vertex 0 and from vertex 4, but finds the same edges. Nothing reads vertex 4,
+        // which has no edges out of it.
+        let edge = weights.element(3 * n + 4);
+        let incoming = AThunkID(paths.rounds[0][n - 1].0 + 1 + 4);
+        let mut expected = vec![(edge.id(), 1), (incoming, 2)];
+        expected.extend(paths.rounds[1..].iter().map(|round| (round[4], 1)));
+        graph.assert_recomputes(&expected, |g| {
+            g.update_aref(edge, 1.0);
+            assert_eq!(Ok(vec![0.0, 3.0, 1.0, 4.0, 5.0]), paths.distances(g));
+            assert_eq!(Ok(false), paths.has_negative_cycle(g));
+        });
+    }
+}
@@ -0,0 +1,283 @@
+//! Incremental merge sort, after the paper's sorting example.
+//!
+//! The inputs are the leaves of a balanced tree, and each position of each merge above them gets
+//! a node of its own, which finds the `k`th smallest value of the merge's range by binary
+//! searching its two children's positions. Nodes are only created for the positions that are
+//! asked for, under the merge and the position as their name, along with the positions of the
+//! children their search can look at, so asking again hands back the same node. Sorting a
+//! thousand values and reading the smallest creates and runs a node per merge.
+//!
+//! A position only reads the handful of its children's positions its search looked at, and every
+//! position has an exact cutoff, so changing an input only re-runs the searches that read a
+//! position whose value moved. When the input keeps its place in the order, that's the position
+//! it's at in each merge above it, so a change re-runs a few searches of O(log n) comparisons per
+//! level of the tree, rather than one for every position of every merge above the input.
+
+use crate::{AThunkID, AdaptonError, Cutoff, Graph, Handle, MemoHasher};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// The output of `incremental_sort`, holding the inputs' values in ascending order.
+#[derive(Clone)]
+pub struct ASorted {
+    root: Option<SortNode>,
+    merges: Rc<RefCell<Vec<Merge>>>,
+}
+
+impl ASorted {
+    pub fn len(&self) -> usize {
+        self.root.map_or(0, SortNode::len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// The node holding position `k` of the sorted output, created the first time it's asked for.
+    ///
+    /// Panics if `k` is out of bounds.
+    pub fn position<S: MemoHasher>(&self, graph: &mut Graph<f64, S>, k: usize) -> AThunkID {
+        assert!(k < self.len(), "no position {} among {}", k, self.len());
+        position(graph, &self.merges, self.root.unwrap(), k)
+    }
+
+    /// The value at position `k`.
+    ///
+    /// Panics if `k` is out of bounds.
+    pub fn get<S: MemoHasher>(
+        &self,
+        graph: &mut Graph<f64, S>,
+        k: usize,
+    ) -> Result<f64, AdaptonError> {
+        let id = self.position(graph, k);
+        graph.compute(id, &[])
+    }
+
+    pub fn values<S: MemoHasher>(
+        &self,
+        graph: &mut Graph<f64, S>,
+    ) -> Result<Vec<f64>, AdaptonError> {
+        (0..self.len()).map(|k| self.get(graph, k)).collect()
+    }
+}
+
+/// Sorts the values of `inputs`, which are usually arefs, keeping the result sorted as they
+/// change. NaNs end up wherever the comparisons happen to put them. Nothing is added to `graph`
+/// until the result's positions are asked for.
+pub fn incremental_sort<S: MemoHasher>(_graph: &mut Graph<f64, S>, inputs: &[AThunkID]) -> ASorted {
+    let mut merges = Vec::new();
+    ASorted {
+        root: (!inputs.is_empty()).then(|| merge_tree(&mut merges, inputs)),
+        merges: Rc::new(RefCell::new(merges)),
+    }
+}
+
+fn merge_tree(merges: &mut Vec<Merge>, inputs: &[AThunkID]) -> SortNode {
+    if inputs.len() == 1 {
+        return SortNode::Leaf(inputs[0]);
+    }
+    let (left, right) = inputs.split_at(inputs.len() / 2);
+    let (left, right) = (merge_tree(merges, left), merge_tree(merges, right));
+    merges.push(Merge {
+        left,
+        right,
+        positions: HashMap::new(),
+    });
+    SortNode::Tree(merges.len() - 1, left.len() + right.len())
+}
+
+// The node for position `k` of `node`, creating it, and whatever it can read, if it's new.
+fn position<S: MemoHasher>(
+    graph: &mut Graph<f64, S>,
+    merges: &RefCell<Vec<Merge>>,
+    node: SortNode,
+    k: usize,
+) -> AThunkID {
+    let merge = match node {
+        SortNode::Leaf(id) => return id,
+        SortNode::Tree(merge, _) => merge,
+    };
+    let (left, right) = {
+        let merges = merges.borrow();
+        if let Some(&id) = merges[merge].positions.get(&k) {
+            return id;
+        }
+        (merges[merge].left, merges[merge].right)
+    };
+    let (m, n) = (left.len(), right.len());
+
+    // Take i of the smallest values from the left and j from the right, where i is the smallest
+    // count for which the right's largest taken value isn't above the left's smallest untaken
+    // one. The search only ever looks at the left's positions from `lo - 1` up to `hi - 1` and
+    // the right's from `k - hi` up to `k - lo`.
+    let (lo, hi) = ((k + 1).saturating_sub(n), (k + 1).min(m));
+    let (left_from, right_from) = (lo.saturating_sub(1), k.saturating_sub(hi));
+    let lefts: Vec<_> = (left_from..hi)
+        .map(|i| position(graph, merges, left, i))
+        .collect();
+    let rights: Vec<_> = (right_from..=k - lo)
+        .map(|j| position(graph, merges, right, j))
+        .collect();
+    let id = graph.new_athunk(move |h: &mut Handle<f64, S>| {
+        let left = |h: &mut Handle<f64, S>, i: usize| read(h, lefts[i - left_from]);
+        let right = |h: &mut Handle<f64, S>, j: usize| read(h, rights[j - right_from]);
+        let (mut lo, mut hi) = (lo, hi);
+        while lo < hi {
+            let i = (lo + hi) / 2;
+            let j = k + 1 - i;
+            if j > 0 && i < m && right(h, j - 1) > left(h, i) {
+                lo = i + 1;
+            } else {
+                hi = i;
+            }
+        }
+        let (i, j) = (lo, k + 1 - lo);
+        let from_left = if i > 0 {
+            left(h, i - 1)
+        } else {
+            f64::NEG_INFINITY
+        };
+        let from_right = if j > 0 {
+            right(h, j - 1)
+        } else {
+            f64::NEG_INFINITY
+        };
+        from_left.max(from_right)
+    });
+    graph.set_node_cutoff(id, Cutoff::Exact);
+    merges.borrow_mut()[merge].positions.insert(k, id);
+    id
+}
+
+// Only the positions a search actually reads get an edge, so that changes to the others don't
+// dirty it.
+fn read<S: MemoHasher>(h: &mut Handle<f64, S>, id: AThunkID) -> f64 {
+    h.add_edge(id);
+    // The handle remembers the error, so this value is never seen.
+    h.compute(id, &[]).unwrap_or(f64::NAN)
+}
+
+struct Merge {
+    left: SortNode,
+    right: SortNode,
+    // The nodes created so far for the merge's positions, by position.
+    positions: HashMap<usize, AThunkID>,
+}
+
+#[derive(Clone, Copy)]
+enum SortNode {
+    // The inputs themselves, which are computed without arguments.
+    Leaf(AThunkID),
+    // A merge, by its index, and the number of inputs below it.
+    Tree(usize, usize),
+}
+
+impl SortNode {
+    fn len(self) -> usize {
+        match self {
+            SortNode::Leaf(_) => 1,
+            SortNode::Tree(_, len) => len,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::incremental_sort;
+    use crate::Graph;
+
+    #[test]
+    fn incremental_sort_stays_sorted() {
+        let mut graph = Graph::new();
+        let mut vals: Vec<f64> = (0..37).map(|i| ((i * 17) % 37) as f64).collect();
+        let inputs: Vec<_> = vals.iter().map(|&v| graph.new_aref(v)).collect();
+        let ids: Vec<_> = inputs.iter().map(|r| r.id()).collect();
+        let sorted = incremental_sort(&mut graph, &ids);
+
+        let expect = |vals: &[f64]| {
+            let mut vals = vals.to_vec();
+            vals.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            vals
+        };
+        assert_eq!(Ok(expect(&vals)), sorted.values(&mut graph));
+        for (i, v) in [(3, 100.0), (20, -1.0), (36, 18.5), (0, 18.5)] {
+            vals[i] = v;
+            graph.update_aref(inputs[i], v);
+            assert_eq!(Ok(expect(&vals)), sorted.values(&mut graph));
+        }
+
+        assert!(incremental_sort(&mut graph, &[]).is_empty());
+        let one = incremental_sort(&mut graph, &ids[..1]);
+        assert_eq!(Ok(vec![18.5]), one.values(&mut graph));
+    }
+
+    #[test]
+    fn positions_on_demand() {
+        let mut graph = Graph::new();
+        let inputs: Vec<_> = [5.0, 3.0, 9.0, 1.0]
+            .iter()
+            .map(|&v| graph.new_aref(v))
+            .collect();
+        let ids: Vec<_> = inputs.iter().map(|r| r.id()).collect();
+        // Nothing until a position is asked for, and then only the smallest of each merge.
+        let nodes = graph.iter().count();
+        let sorted = incremental_sort(&mut graph, &ids);
+        assert_eq!(nodes, graph.iter().count());
+
+        let smallest = sorted.position(&mut graph, 0);
+        assert_eq!(smallest, sorted.position(&mut graph, 0));
+        assert_eq!(nodes + 3, graph.iter().count());
+        assert_eq!(Ok(1.0), graph.compute(smallest, &[]));
+        let largest = sorted.position(&mut graph, 3);
+        assert_eq!(nodes + 6, graph.iter().count());
+        assert_eq!(Ok(9.0), graph.compute(largest, &[]));
+        assert_eq!(Ok(vec![1.0, 3.0, 5.0, 9.0]), sorted.values(&mut graph));
+    }
+
+    #[test]
+    fn one_change_recomputes() {
+        let mut graph = Graph::new();
+        let inputs: Vec<_> = (0..8).map(|i| graph.new_aref(i as f64 * 10.0)).collect();
+        let ids: Vec<_> = inputs.iter().map(|r| r.id()).collect();
+        let sorted = incremental_sort(&mut graph, &ids);
+        let positions: Vec<_> = (0..8).map(|k| sorted.position(&mut graph, k)).collect();
+        for (k, &p) in positions.iter().enumerate() {
+            assert_eq!(Ok(k as f64 * 10.0), graph.compute(p, &[]));
+        }
+        // Merges are numbered bottom up and left to right, so the ones above the last input are
+        // 4, 5 and the root, 6. Each re-runs the searches of the position the input's value is
+        // at and of the one next to it, which read it too.
+        let merges = sorted.merges.borrow();
+        let mut expected = vec![(ids[7], 1)];
+        for (merge, k) in [(4, 0), (4, 1), (5, 2), (5, 3), (6, 6), (6, 7)] {
+            expected.push((merges[merge].positions[&k], 1));
+        }
+        graph.assert_recomputes(&expected, |g| {
+            g.update_aref(inputs[7], 75.0);
+            for &p in positions.iter() {
+                g.compute(p, &[]).unwrap();
+            }
+        });
+        assert_eq!(Ok(75.0), graph.compute(positions[7], &[]));
+    }
+
+    #[test]
+    fn changes_rerun_few_searches() {
+        for n in [16usize, 64, 256] {
+            let mut graph = Graph::new();
+            let inputs: Vec<_> = (0..n).map(|i| graph.new_aref(i as f64 * 10.0)).collect();
+            let ids: Vec<_> = inputs.iter().map(|r| r.id()).collect();
+            let sorted = incremental_sort(&mut graph, &ids);
+            sorted.values(&mut graph).unwrap();
+            // Far fewer than the n log n positions there are, however many inputs there are.
+            let levels = n.trailing_zeros() as usize;
+            for i in [0, n / 3, n - 1] {
+                let before = graph.runs.get();
+                graph.update_aref(inputs[i], i as f64 * 10.0 + 5.0);
+                sorted.values(&mut graph).unwrap();
+                assert!(graph.runs.get() - before <= levels * levels);
+            }
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Looking for edges that make invalidation do more work than it needs to.
+
+use crate::{AThunkID, Graph, MemoHasher, Scalar};
+use std::collections::{HashMap, HashSet};
+
+/// What `Graph::analyze_edges` found.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EdgeAnalysis {
+    /// Pairs of a node and a node its thunk added an edge to more than once during its last run.
+    /// The graph only keeps one edge, but the thunk is doing work it doesn't need to.
+    pub duplicates: Vec<(AThunkID, AThunkID)>,
+    /// Pairs of a node and a node it has an edge to that it also depends on through another of
+    /// its edges. Dirtying goes through the node twice, once for each path.
+    pub redundant: Vec<(AThunkID, AThunkID)>,
+}
+
+impl<T: Scalar, S: MemoHasher> Graph<T, S> {
+    /// Finds duplicate and redundant edges. Both lists are sorted.
+    pub fn analyze_edges(&self) -> EdgeAnalysis {
+        let mut analysis = EdgeAnalysis::default();
+        let mut below = HashMap::default();
+        for (i, athunk) in self.athunks.iter() {
+            let id = AThunkID(i);
+            let mut duplicates: Vec<AThunkID> = athunk.duplicate_edges.borrow().clone();
+            duplicates.sort();
+            duplicates.dedup();
+            analysis
+                .duplicates
+                .extend(duplicates.into_iter().map(|sub| (id, sub)));
+
+            let subs = athunk.sub_computations.borrow();
+            let mut indirect: HashSet<AThunkID, S> = HashSet::default();
+            for sub in subs.iter() {
+                indirect.extend(self.descendants(sub, &mut below).iter());
+            }
+            let mut redundant: Vec<AThunkID> =
+                subs.iter().filter(|sub| indirect.contains(sub)).collect();
+            redundant.sort();
+            analysis
+                .redundant
+                .extend(redundant.into_iter().map(|sub| (id, sub)));
+        }
+        analysis
+    }
+
+    /// Removes every redundant edge, and returns how many there were. Dirtying still reaches
+    /// every node it did before, through the edges that are left. Nodes whose dependencies
+    /// aren't static get their edges back the next time they're run.
+    pub fn remove_redundant_edges(&mut self) -> usize {
+        let redundant = self.analyze_edges().redundant;
+        for &(id, sub) in redundant.iter() {
+            self.remove_edge(id, sub);
+        }
+        redundant.len()
+    }
+
+    // Everything `id` depends on, not including itself.
+    fn descendants<'a>(
+        &self,
+        id: AThunkID,
+        below: &'a mut HashMap<AThunkID, HashSet<AThunkID, S>, S>,
+    ) -> &'a HashSet<AThunkID, S> {
+        if !below.contains_key(&id) {
+            let mut all = HashSet::default();
+            let subs: Vec<AThunkID> = self.athunks[id.0]
+                .sub_computations
+                .borrow()
+                .iter()
+                .collect();
+            for sub in subs {
+                all.insert(sub);
+                all.extend(self.descendants(sub, below).iter());
+            }
+            below.insert(id, all);
+        }
+        &below[&id]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{EdgeAnalysis, Graph};
+
+    #[test]
+    fn analyze_edges() {
+        let mut graph = Graph::new();
+
+        let r = graph.new_aref(1.0);
+        let a = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap() + 1.0
+        });
+        let b = graph.new_athunk(move |h| {
+            h.add_edges(&[a, r.id(), r.id()]);
+            h.compute(a, &[]).unwrap() + h.compute(r, &[]).unwrap()
+        });
+        assert_eq!(Ok(3.0), graph.compute(b, &[]));
+        assert_eq!(
+            EdgeAnalysis {
+                duplicates: vec![(b, r.id())],
+                redundant: vec![(b, r.id())],
+            },
+            graph.analyze_edges()
+        );
+
+        assert_eq!(1, graph.remove_redundant_edges());
+        assert!(graph.analyze_edges().redundant.is_empty());
+        graph.update_aref(r, 2.0);
+        assert_eq!(Ok(5.0), graph.compute(b, &[]));
+    }
+}
@@ -0,0 +1,145 @@
+//! Everything a thunk is called with, gathered in one place.
+//!
+//! `Handle::call` bundles the node's arguments with the deadline, cancellation and extensions of
+//! the computation it's part of. Its fields are private, so that more can be added to it without
+//! changing the signature of every thunk.
+
+use crate::{AThunkID, AdaptonError, Graph, Handle, MemoHasher, Scalar};
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// A map from types to values, for passing any number of things to thunks through
+/// `Graph::compute_with_extensions`.
+#[derive(Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Box<dyn Any>>,
+}
+
+impl Extensions {
+    pub fn new() -> Self {
+        Extensions::default()
+    }
+
+    /// Adds `value`, returning the previous value of the same type.
+    pub fn insert<E: 'static>(&mut self, value: E) -> Option<E> {
+        self.map
+            .insert(TypeId::of::<E>(), Box::new(value))
+            .and_then(|old| old.downcast().ok())
+            .map(|old| *old)
+    }
+
+    pub fn get<E: 'static>(&self) -> Option<&E> {
+        self.map.get(&TypeId::of::<E>())?.downcast_ref()
+    }
+}
+
+/// A flag that can be set from anywhere, say another thread, to ask thunks to give up early. It's
+/// passed to thunks as an extension, and it's up to them to check it.
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// What a thunk was called with. See `Handle::call`.
+pub struct CallCtx<'a> {
+    pub args: &'a [f64],
+    deadline: Option<Instant>,
+    extensions: Option<&'a Extensions>,
+}
+
+impl<'a> CallCtx<'a> {
+    /// When `Graph::compute_timeout` will give up, if this is part of a call to it.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Whether a `CancelToken` was passed as an extension and has been cancelled since.
+    pub fn is_cancelled(&self) -> bool {
+        self.extension::<CancelToken>()
+            .is_some_and(CancelToken::is_cancelled)
+    }
+
+    pub fn extension<E: 'static>(&self) -> Option<&'a E> {
+        self.extensions?.get()
+    }
+}
+
+impl<T: Scalar, S: MemoHasher> Graph<T, S> {
+    /// Like `compute_with_ctx`, with `extensions` as the context, so that any number of values can
+    /// be passed to thunks and found with `CallCtx::extension`.
+    pub fn compute_with_extensions(
+        &self,
+        id: impl Into<AThunkID>,
+        args: &[f64],
+        extensions: &Extensions,
+    ) -> Result<T, AdaptonError> {
+        self.compute_with_ctx(id, args, extensions)
+    }
+}
+
+impl<'a, T: Scalar, S: MemoHasher> Handle<'a, T, S> {
+    pub fn call(&self) -> CallCtx<'a> {
+        CallCtx {
+            args: self.args,
+            deadline: self.graph.deadline.get().map(|(_, deadline, _)| deadline),
+            extensions: self.ctx::<Extensions>(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CancelToken, Extensions};
+    use crate::Graph;
+    use std::time::Duration;
+
+    #[test]
+    fn call_ctx() {
+        let mut graph = Graph::new();
+        let t = graph.new_athunk(|h| {
+            let call = h.call();
+            if call.is_cancelled() {
+                return -1.0;
+            }
+            assert_eq!(call.deadline().is_some(), call.args[0] == 1.0);
+            let scale = call.extension::<f64>().copied().unwrap_or(1.0);
+            call.args.iter().sum::<f64>() * scale
+        });
+        assert_eq!(Ok(2.0), graph.compute(t, &[2.0]));
+        assert_eq!(
+            Ok(1.0),
+            graph.compute_timeout(t, &[1.0], Duration::from_secs(60))
+        );
+
+        let mut extensions = Extensions::new();
+        assert_eq!(None, extensions.insert(10.0));
+        assert_eq!(Some(10.0), extensions.insert(3.0));
+        assert_eq!(
+            Ok(9.0),
+            graph.compute_with_extensions(t, &[3.0], &extensions)
+        );
+
+        let cancel = CancelToken::new();
+        extensions.insert(cancel.clone());
+        cancel.cancel();
+        assert_eq!(
+            Ok(-1.0),
+            graph.compute_with_extensions(t, &[4.0], &extensions)
+        );
+    }
+}
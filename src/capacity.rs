@@ -0,0 +1,52 @@
+//! Sizing a graph up front, for when it's about to be filled with a known number of nodes.
+
+use crate::{AThunk, Graph, MemoHasher, Scalar};
+use slab::Slab;
+
+impl<T: Scalar, S: MemoHasher> Graph<T, S> {
+    /// A graph with room for `nodes` nodes, each of which starts out with room for `avg_edges`
+    /// edges in either direction and `avg_memo_entries` memoized values, so that loading a large
+    /// graph doesn't keep reallocating as it grows. The hints only affect how much is allocated.
+    pub fn with_capacity(nodes: usize, avg_edges: usize, avg_memo_entries: usize) -> Self {
+        Self {
+            athunks: Slab::with_capacity(nodes),
+            capacity_hints: Some((avg_edges, avg_memo_entries)),
+            ..Self::default()
+        }
+    }
+}
+
+impl<T: Scalar, S: MemoHasher> AThunk<T, S> {
+    pub(crate) fn reserve(&mut self, hints: Option<(usize, usize)>) {
+        if let Some((edges, entries)) = hints {
+            self.sub_computations.get_mut().reserve(edges);
+            self.super_computations.get_mut().reserve(edges);
+            self.result.get_mut().reserve(entries);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::edges::EdgeSet;
+    use crate::Graph;
+
+    #[test]
+    fn with_capacity() {
+        let mut graph: Graph = Graph::with_capacity(1000, 8, 4);
+        assert!(graph.athunks.capacity() >= 1000);
+        let r = graph.new_aref(1.0);
+        let t = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap() + h.arg(0).unwrap()
+        });
+        for i in 0..4 {
+            assert_eq!(Ok(1.0 + i as f64), graph.compute(t, &[i as f64]));
+        }
+        // Eight edges don't fit inline.
+        assert!(matches!(
+            *graph.athunks[r.id().0].super_computations.borrow(),
+            EdgeSet::Sorted(_)
+        ));
+    }
+}
@@ -0,0 +1,382 @@
+//! Incremental lists, along with `amap` and `afilter` over them, and vectors whose elements are
+//! invalidated one at a time.
+//!
+//! A list is an ordered collection of nodes plus a "shape" aref that changes whenever the list's
+//! structure does. Derived lists allocate their per-element nodes nominally, keyed by the source
+//! element, so inserting one element allocates one node and every other element keeps its node and
+//! memoized value.
+
+use crate::{ARefID, AThunkID, AdaptonError, Graph, Handle, KeyHash, MemoHasher, Scalar};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::RangeBounds;
+use std::rc::Rc;
+
+#[derive(Clone)]
+pub struct AList<S = KeyHash> {
+    state: Rc<RefCell<ListState<S>>>,
+}
+
+struct ListState<S> {
+    items: Vec<AThunkID>,
+    shape: ARefID,
+    revision: u64,
+    derived: Vec<Derived<S>>,
+}
+
+type Derived<S> = Box<dyn FnMut(&mut Graph<f64, S>, ListChange)>;
+type ElementFn<S> = Rc<dyn Fn(&mut Handle<f64, S>, f64) -> f64>;
+
+#[derive(Clone, Copy)]
+enum ListChange {
+    Insert(usize, AThunkID),
+    Remove(usize, AThunkID),
+}
+
+impl<S: MemoHasher> Graph<f64, S> {
+    pub fn new_alist(&mut self, items: &[AThunkID]) -> AList<S> {
+        AList {
+            state: Rc::new(RefCell::new(ListState {
+                items: items.to_vec(),
+                shape: self.new_aref(0.0),
+                revision: 0,
+                derived: Vec::new(),
+            })),
+        }
+    }
+
+    /// A list holding `f` of each element of `list`, which follows `list` as it changes. An
+    /// element that's in `list` more than once has one node for all of its positions, and once
+    /// the last of them is removed the node is forgotten, but left in the graph for `retain` to
+    /// remove if it needs to be.
+    pub fn amap(
+        &mut self,
+        list: &AList<S>,
+        f: impl Fn(&mut Handle<f64, S>, f64) -> f64 + 'static,
+    ) -> AList<S> {
+        let f: ElementFn<S> = Rc::new(f);
+        // Each element's node, and how many positions of the list it's at.
+        let mut mapped: HashMap<AThunkID, (AThunkID, usize), S> = HashMap::default();
+        let mut map = move |g: &mut Graph<f64, S>, change: ListChange| match change {
+            ListChange::Insert(_, item) => {
+                let (node, count) = mapped.entry(item).or_insert_with(|| {
+                    let f = f.clone();
+                    let node = g.new_athunk(move |h: &mut Handle<f64, S>| {
+                        h.add_edge(item);
+                        let v = h.compute(item, &[]).unwrap_or(f64::NAN);
+                        f(h, v)
+                    });
+                    (node, 0)
+                });
+                *count += 1;
+                *node
+            }
+            ListChange::Remove(_, item) => {
+                let (node, count) = mapped.get_mut(&item).unwrap();
+                let node = *node;
+                *count -= 1;
+                if *count == 0 {
+                    mapped.remove(&item);
+                }
+                node
+            }
+        };
+
+        let items: Vec<AThunkID> = list
+            .items()
+            .into_iter()
+            .enumerate()
+            .map(|(i, item)| map(self, ListChange::Insert(i, item)))
+            .collect();
+        let out = self.new_alist(&items);
+        let derived = out.clone();
+        list.derive(move |g, change| {
+            let item = map(g, change);
+            match change {
+                ListChange::Insert(i, _) => derived.insert(g, i, item),
+                ListChange::Remove(i, _) => {
+                    derived.remove(g, i);
+                }
+            }
+        });
+        out
+    }
+
+    /// The elements of `list` for which `pred` holds. Each element gets its own predicate node,
+    /// so changing one element only re-runs its own predicate.
+    pub fn afilter(
+        &mut self,
+        list: &AList<S>,
+        pred: impl Fn(&mut Handle<f64, S>, f64) -> bool + 'static,
+    ) -> AFilter<S> {
+        let preds = self.amap(list, move |h, v| if pred(h, v) { 1.0 } else { 0.0 });
+        let shape = preds.shape();
+        let state = preds.state.clone();
+        let count = self.new_athunk(move |h: &mut Handle<f64, S>| {
+            h.add_edge(shape);
+            let _ = h.compute(shape, &[]);
+            let items = state.borrow().items.clone();
+            items
+                .into_iter()
+                .map(|p| {
+                    h.add_edge(p);
+                    h.compute(p, &[]).unwrap_or(0.0)
+                })
+                .sum()
+        });
+        AFilter {
+            source: list.clone(),
+            preds,
+            count,
+        }
+    }
+}
+
+impl<S: MemoHasher> AList<S> {
+    /// An aref that changes whenever elements are inserted or removed, for thunks that depend on
+    /// the list's structure rather than any one element. Like any other node, it must be computed
+    /// and not just added as an edge for its changes to propagate.
+    pub fn shape(&self) -> ARefID {
+        self.state.borrow().shape
+    }
+
+    pub fn items(&self) -> Vec<AThunkID> {
+        self.state.borrow().items.clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.borrow().items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn values(&self, graph: &Graph<f64, S>) -> Result<Vec<f64>, AdaptonError> {
+        self.items()
+            .into_iter()
+            .map(|i| graph.compute(i, &[]))
+            .collect()
+    }
+
+    pub fn push(&self, graph: &mut Graph<f64, S>, item: AThunkID) {
+        let len = self.len();
+        self.insert(graph, len, item);
+    }
+
+    pub fn insert(&self, graph: &mut Graph<f64, S>, index: usize, item: AThunkID) {
+        self.state.borrow_mut().items.insert(index, item);
+        self.changed(graph, ListChange::Insert(index, item));
+    }
+
+    pub fn remove(&self, graph: &mut Graph<f64, S>, index: usize) -> AThunkID {
+        let item = self.state.borrow_mut().items.remove(index);
+        self.changed(graph, ListChange::Remove(index, item));
+        item
+    }
+
+    fn derive(&self, f: impl FnMut(&mut Graph<f64, S>, ListChange) + 'static) {
+        self.state.borrow_mut().derived.push(Box::new(f));
+    }
+
+    fn changed(&self, graph: &mut Graph<f64, S>, change: ListChange) {
+        let (shape, revision) = {
+            let mut state = self.state.borrow_mut();
+            state.revision += 1;
+            (state.shape, state.revision)
+        };
+        graph.update_aref(shape, revision as f64);
+
+        // Derived lists are updated with nothing borrowed, since they may well be derived from
+        // again themselves.
+        let mut derived = std::mem::take(&mut self.state.borrow_mut().derived);
+        for d in derived.iter_mut() {
+            d(graph, change);
+        }
+        self.state.borrow_mut().derived.append(&mut derived);
+    }
+}
+
+/// The result of `Graph::afilter`.
+pub struct AFilter<S = KeyHash> {
+    source: AList<S>,
+    preds: AList<S>,
+    count: AThunkID,
+}
+
+impl<S: MemoHasher> AFilter<S> {
+    /// A node computing how many elements currently pass the filter.
+    pub fn count(&self) -> AThunkID {
+        self.count
+    }
+
+    pub fn items(&self, graph: &Graph<f64, S>) -> Result<Vec<AThunkID>, AdaptonError> {
+        let mut items = Vec::new();
+        for (item, pred) in self.source.items().into_iter().zip(self.preds.items()) {
+            if graph.compute(pred, &[])? != 0.0 {
+                items.push(item);
+            }
+        }
+        Ok(items)
+    }
+
+    pub fn values(&self, graph: &Graph<f64, S>) -> Result<Vec<f64>, AdaptonError> {
+        self.items(graph)?
+            .into_iter()
+            .map(|i| graph.compute(i, &[]))
+            .collect()
+    }
+}
+
+/// A fixed-length vector of values, with an aref per element. Updating some elements only
+/// invalidates the thunks that read those elements, so a thunk reading element 7 isn't re-run
+/// when element 900 changes.
+#[derive(Clone, Debug)]
+pub struct AVec {
+    elements: Rc<[ARefID]>,
+}
+
+impl<T: Scalar, S: MemoHasher> Graph<T, S> {
+    pub fn new_avec(&mut self, vals: &[T]) -> AVec {
+        AVec {
+            elements: vals.iter().map(|&v| self.new_aref(v)).collect(),
+        }
+    }
+
+    /// Sets each `(index, value)` pair, invalidating only the thunks that read those indices.
+    pub fn update_avec(&mut self, vec: &AVec, changes: &[(usize, T)]) {
+        for &(i, val) in changes {
+            self.update_aref(vec.elements[i], val);
+        }
+    }
+}
+
+impl AVec {
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    /// The aref holding element `i`.
+    pub fn element(&self, i: usize) -> ARefID {
+        self.elements[i]
+    }
+
+    /// Adds edges to just the elements in `range` and returns their values.
+    pub fn read<T: Scalar, S: MemoHasher>(
+        &self,
+        h: &mut Handle<T, S>,
+        range: impl RangeBounds<usize>,
+    ) -> Result<Vec<T>, AdaptonError> {
+        let (start, end) = (range.start_bound().cloned(), range.end_bound().cloned());
+        let elements = &self.elements[(start, end)];
+        for &e in elements {
+            h.add_edge(e);
+        }
+        elements.iter().map(|&e| h.compute(e, &[])).collect()
+    }
+
+    pub fn values<T: Scalar, S: MemoHasher>(
+        &self,
+        graph: &Graph<T, S>,
+    ) -> Result<Vec<T>, AdaptonError> {
+        self.elements
+            .iter()
+            .map(|&e| graph.compute(e, &[]))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Graph;
+
+    #[test]
+    fn avec() {
+        let mut graph = Graph::new();
+
+        let vals: Vec<f64> = (0..1000).map(|i| i as f64).collect();
+        let vec = graph.new_avec(&vals);
+        let v = vec.clone();
+        let head = graph.new_athunk(move |h| v.read(h, ..10).unwrap().iter().sum());
+        let v = vec.clone();
+        let tail = graph.new_athunk(move |h| v.read(h, 990..).unwrap().iter().sum());
+        assert_eq!(Ok(45.0), graph.compute(head, &[]));
+        assert_eq!(Ok(9945.0), graph.compute(tail, &[]));
+
+        graph.assert_recomputes(&[(vec.element(995).id(), 1), (tail, 1)], |g| {
+            g.update_avec(&vec, &[(995, 0.0)]);
+            assert_eq!(Ok(45.0), g.compute(head, &[]));
+            assert_eq!(Ok(8950.0), g.compute(tail, &[]));
+        });
+        assert_eq!(Ok(0.0), graph.compute(vec.element(995), &[]));
+        assert_eq!(1000, vec.values(&graph).unwrap().len());
+    }
+
+    #[test]
+    fn amap_and_afilter() {
+        let mut graph = Graph::new();
+
+        let items: Vec<_> = [1.0, 2.0, 3.0]
+            .iter()
+            .map(|&v| graph.new_aref(v).id())
+            .collect();
+        let list = graph.new_alist(&items);
+        let doubled = graph.amap(&list, |_, v| v * 2.0);
+        let big = graph.afilter(&doubled, |_, v| v > 3.0);
+
+        assert_eq!(Ok(vec![2.0, 4.0, 6.0]), doubled.values(&graph));
+        assert_eq!(Ok(vec![4.0, 6.0]), big.values(&graph));
+        assert_eq!(Ok(2.0), graph.compute(big.count(), &[]));
+
+        // Only the new element's map and predicate nodes run, plus the count and the shape it
+        // depends on.
+        let new = graph.new_aref(5.0);
+        let mapped = doubled.items();
+        list.insert(&mut graph, 0, new.id());
+        let expected = [
+            (new.id(), 1),
+            (doubled.items()[0], 1),
+            (big.preds.items()[0], 1),
+            (big.preds.shape().id(), 1),
+            (big.count(), 1),
+        ];
+        graph.assert_recomputes(&expected, |g| {
+            g.compute(big.count(), &[]).unwrap();
+        });
+        assert_eq!(Ok(vec![10.0, 2.0, 4.0, 6.0]), doubled.values(&graph));
+        assert_eq!(&mapped[..], &doubled.items()[1..]);
+        assert_eq!(Ok(vec![10.0, 4.0, 6.0]), big.values(&graph));
+        assert_eq!(Ok(3.0), graph.compute(big.count(), &[]));
+
+        list.remove(&mut graph, 1);
+        assert_eq!(Ok(vec![10.0, 4.0, 6.0]), doubled.values(&graph));
+        assert_eq!(Ok(vec![10.0, 4.0, 6.0]), big.values(&graph));
+    }
+
+    #[test]
+    fn amap_repeated_items() {
+        let mut graph = Graph::new();
+
+        let a = graph.new_aref(1.0).id();
+        let list = graph.new_alist(&[a]);
+        let doubled = graph.amap(&list, |_, v| v * 2.0);
+        let node = doubled.items()[0];
+
+        // Both positions of `a` share its node, which outlives either one of them.
+        list.push(&mut graph, a);
+        assert_eq!(vec![node, node], doubled.items());
+        assert_eq!(Ok(vec![2.0, 2.0]), doubled.values(&graph));
+        list.remove(&mut graph, 0);
+        assert_eq!(vec![node], doubled.items());
+
+        // Once it's gone altogether, putting it back makes a new node.
+        list.remove(&mut graph, 0);
+        list.push(&mut graph, a);
+        assert_ne!(node, doubled.items()[0]);
+        assert_eq!(Ok(vec![2.0]), doubled.values(&graph));
+    }
+}
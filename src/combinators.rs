@@ -0,0 +1,345 @@
+//! Ready-made nodes built out of the public API.
+
+use crate::algorithms::incremental_sort;
+use crate::{ARefID, AThunkID, AdaptonError, Graph, MemoHasher};
+use std::collections::{HashMap, HashSet};
+
+/// The arithmetic a combinator node performs, on the values of other nodes or, once compiled, on
+/// the registers of a plan.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Op<T = AThunkID> {
+    Add(T, T),
+    Sub(T, T),
+    Mul(T, T),
+    Div(T, T),
+    Neg(T),
+}
+
+impl<T: Copy> Op<T> {
+    fn operands(self) -> Vec<T> {
+        match self {
+            Op::Add(a, b) | Op::Sub(a, b) | Op::Mul(a, b) | Op::Div(a, b) => vec![a, b],
+            Op::Neg(a) => vec![a],
+        }
+    }
+
+    pub(crate) fn map<U>(self, mut f: impl FnMut(T) -> U) -> Op<U> {
+        match self {
+            Op::Add(a, b) => Op::Add(f(a), f(b)),
+            Op::Sub(a, b) => Op::Sub(f(a), f(b)),
+            Op::Mul(a, b) => Op::Mul(f(a), f(b)),
+            Op::Div(a, b) => Op::Div(f(a), f(b)),
+            Op::Neg(a) => Op::Neg(f(a)),
+        }
+    }
+
+    fn eval(self, val: impl Fn(T) -> f64) -> f64 {
+        match self {
+            Op::Add(a, b) => val(a) + val(b),
+            Op::Sub(a, b) => val(a) - val(b),
+            Op::Mul(a, b) => val(a) * val(b),
+            Op::Div(a, b) => val(a) / val(b),
+            Op::Neg(a) => -val(a),
+        }
+    }
+
+    // Evaluates the op on dual numbers, i.e. on pairs of a value and its derivative.
+    fn eval_dual(self, val: impl Fn(T) -> (f64, f64)) -> (f64, f64) {
+        match self {
+            Op::Add(a, b) => {
+                let ((x, dx), (y, dy)) = (val(a), val(b));
+                (x + y, dx + dy)
+            }
+            Op::Sub(a, b) => {
+                let ((x, dx), (y, dy)) = (val(a), val(b));
+                (x - y, dx - dy)
+            }
+            Op::Mul(a, b) => {
+                let ((x, dx), (y, dy)) = (val(a), val(b));
+                (x * y, x * dy + y * dx)
+            }
+            Op::Div(a, b) => {
+                let ((x, dx), (y, dy)) = (val(a), val(b));
+                (x / y, (dx * y - x * dy) / (y * y))
+            }
+            Op::Neg(a) => {
+                let (x, dx) = val(a);
+                (-x, -dx)
+            }
+        }
+    }
+}
+
+// A step of a compiled plan, whose result goes in the register with the same index as the step.
+#[derive(Clone, Copy)]
+enum Step {
+    // Loads the value of the plan's `i`th input.
+    Load(usize),
+    Apply(Op<usize>),
+}
+
+impl<S: MemoHasher> Graph<f64, S> {
+    /// A node computing `a + b`. Like the other arithmetic combinators, it records what it
+    /// computes, so that it can be part of a compiled plan.
+    pub fn add(&mut self, a: impl Into<AThunkID>, b: impl Into<AThunkID>) -> AThunkID {
+        self.op_node(Op::Add(a.into(), b.into()))
+    }
+
+    pub fn sub(&mut self, a: impl Into<AThunkID>, b: impl Into<AThunkID>) -> AThunkID {
+        self.op_node(Op::Sub(a.into(), b.into()))
+    }
+
+    pub fn mul(&mut self, a: impl Into<AThunkID>, b: impl Into<AThunkID>) -> AThunkID {
+        self.op_node(Op::Mul(a.into(), b.into()))
+    }
+
+    pub fn div(&mut self, a: impl Into<AThunkID>, b: impl Into<AThunkID>) -> AThunkID {
+        self.op_node(Op::Div(a.into(), b.into()))
+    }
+
+    pub fn neg(&mut self, a: impl Into<AThunkID>) -> AThunkID {
+        self.op_node(Op::Neg(a.into()))
+    }
+
+    // Operands are computed with no arguments, like any other static dependency.
+    fn op_node(&mut self, op: Op) -> AThunkID {
+        let mut i = 0;
+        let by_position = op.map(|_| {
+            i += 1;
+            i - 1
+        });
+        let id =
+            self.new_athunk_with_deps(&op.operands(), move |_, vals| by_position.eval(|i| vals[i]));
+        self.athunks[id.0].op = Some(op);
+        id
+    }
+
+    /// Compiles the arithmetic combinator nodes below `output` into a single node that evaluates
+    /// them as one flat sequence of steps, with no per-node bookkeeping. Whatever isn't an
+    /// arithmetic node becomes an input of the plan, and invalidating any of them invalidates the
+    /// whole plan. The original nodes are left as they were.
+    pub fn compile(&mut self, output: impl Into<AThunkID>) -> AThunkID {
+        let mut steps = Vec::new();
+        let mut inputs = Vec::new();
+        self.compile_node(
+            output.into(),
+            &mut steps,
+            &mut inputs,
+            &mut HashMap::default(),
+        );
+        self.new_athunk_with_deps(&inputs, move |_, vals| {
+            let mut regs = Vec::with_capacity(steps.len());
+            for step in steps.iter() {
+                let v = match *step {
+                    Step::Load(i) => vals[i],
+                    Step::Apply(op) => op.eval(|r| regs[r]),
+                };
+                regs.push(v);
+            }
+            regs[regs.len() - 1]
+        })
+    }
+
+    /// Computes the node's value along with its derivative with respect to `wrt`, by forward-mode
+    /// differentiation through the arithmetic combinator nodes below it. Any other node counts as
+    /// a constant, unless `wrt` is below it, which fails with `NotDifferentiable` since there's no
+    /// telling what its thunk does with it.
+    pub fn compute_with_derivative(
+        &self,
+        id: impl Into<AThunkID>,
+        wrt: ARefID,
+    ) -> Result<(f64, f64), AdaptonError> {
+        self.dual(id.into(), wrt.id(), &mut HashMap::default())
+    }
+
+    fn dual(
+        &self,
+        id: AThunkID,
+        wrt: AThunkID,
+        duals: &mut HashMap<AThunkID, (f64, f64), S>,
+    ) -> Result<(f64, f64), AdaptonError> {
+        if let Some(&d) = duals.get(&id) {
+            return Ok(d);
+        }
+        let athunk = self.athunks.get(id.0).ok_or(AdaptonError::NoSuchNode(id))?;
+        let d = match athunk.op {
+            _ if id == wrt => (self.compute(id, &[])?, 1.0),
+            Some(op) => {
+                let mut operands: HashMap<_, _, S> = HashMap::default();
+                for sub in op.operands() {
+                    operands.insert(sub, self.dual(sub, wrt, duals)?);
+                }
+                op.eval_dual(|sub| operands[&sub])
+            }
+            None => {
+                // Computing the node first makes sure its edges are there to be followed.
+                let v = self.compute(id, &[])?;
+                if self.reaches(id, wrt) {
+                    return Err(AdaptonError::NotDifferentiable(id));
+                }
+                (v, 0.0)
+            }
+        };
+        duals.insert(id, d);
+        Ok(d)
+    }
+
+    // Whether `to` is below `from`, following sub-computations.
+    fn reaches(&self, from: AThunkID, to: AThunkID) -> bool {
+        let mut stack = vec![from];
+        let mut visited: HashSet<_, S> = HashSet::default();
+        while let Some(id) = stack.pop() {
+            if id == to {
+                return true;
+            }
+            if visited.insert(id) {
+                stack.extend(self.athunks[id.0].sub_computations.borrow().iter());
+            }
+        }
+        false
+    }
+
+    // Appends the steps computing `id` and returns the register holding its value. `registers`
+    // makes sure a node shared by several others is only evaluated once.
+    fn compile_node(
+        &self,
+        id: AThunkID,
+        steps: &mut Vec<Step>,
+        inputs: &mut Vec<AThunkID>,
+        registers: &mut HashMap<AThunkID, usize, S>,
+    ) -> usize {
+        if let Some(&r) = registers.get(&id) {
+            return r;
+        }
+        let step = match self.athunks[id.0].op {
+            Some(op) => Step::Apply(op.map(|sub| self.compile_node(sub, steps, inputs, registers))),
+            None => {
+                inputs.push(id);
+                Step::Load(inputs.len() - 1)
+            }
+        };
+        steps.push(step);
+        registers.insert(id, steps.len() - 1);
+        steps.len() - 1
+    }
+
+    /// Returns one node per position of `items` in sorted order, so that the `i`th returned node
+    /// computes the `i`th smallest value. This is `algorithms::incremental_sort` with every
+    /// position's node made up front; when only some positions will be read, use that instead.
+    pub fn sorted(&mut self, items: &[AThunkID]) -> Vec<AThunkID> {
+        let sorted = incremental_sort(self, items);
+        (0..sorted.len())
+            .map(|k| sorted.position(self, k))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AThunkID, AdaptonError, Graph};
+
+    #[test]
+    fn compile() {
+        let mut graph = Graph::new();
+
+        let a = graph.new_aref(3.0);
+        let b = graph.new_aref(1.0);
+        let c = graph.new_aref(4.0);
+        let sum = graph.add(a, b);
+        let diff = graph.sub(a, b);
+        let prod = graph.mul(sum, diff);
+        let quot = graph.div(prod, c);
+        let out = graph.neg(quot);
+        let plan = graph.compile(out);
+
+        assert_eq!(Ok(-2.0), graph.compute(out, &[]));
+        assert_eq!(Ok(-2.0), graph.compute(plan, &[]));
+
+        // Only the inputs and the plan itself run, not the nodes it was compiled from.
+        graph.assert_recomputes(&[(a.id(), 1), (plan, 1)], |g| {
+            g.update_aref(a, 5.0);
+            assert_eq!(Ok(-6.0), g.compute(plan, &[]));
+        });
+        assert_eq!(Ok(-6.0), graph.compute(out, &[]));
+    }
+
+    #[test]
+    fn derivative() {
+        let mut graph = Graph::new();
+
+        let x = graph.new_aref(3.0);
+        let y = graph.new_aref(2.0);
+        // (x * x - y) / y
+        let xx = graph.mul(x, x);
+        let diff = graph.sub(xx, y);
+        let out = graph.div(diff, y);
+
+        assert_eq!(Ok((3.5, 3.0)), graph.compute_with_derivative(out, x));
+        assert_eq!(Ok((3.5, -2.25)), graph.compute_with_derivative(out, y));
+        graph.update_aref(x, 1.0);
+        assert_eq!(Ok((-0.5, 1.0)), graph.compute_with_derivative(out, x));
+
+        let opaque = graph.new_athunk(move |h| {
+            h.add_edge(x);
+            h.compute(x, &[]).unwrap().sin()
+        });
+        let wrapped = graph.neg(opaque);
+        assert_eq!(
+            Err(AdaptonError::NotDifferentiable(opaque)),
+            graph.compute_with_derivative(wrapped, x)
+        );
+        assert_eq!(
+            Ok((-(1.0f64.sin()), 0.0)),
+            graph.compute_with_derivative(wrapped, y)
+        );
+    }
+
+    #[test]
+    fn sorted() {
+        let mut graph = Graph::new();
+
+        let vals = [5.0, 3.0, 9.0, 1.0, 7.0, 3.0, 8.0];
+        let items: Vec<_> = vals.iter().map(|&v| graph.new_aref(v)).collect();
+        let ids: Vec<AThunkID> = items.iter().map(|r| r.id()).collect();
+        let sorted = graph.sorted(&ids);
+        let values = |g: &Graph| -> Vec<f64> {
+            sorted.iter().map(|&s| g.compute(s, &[]).unwrap()).collect()
+        };
+
+        assert_eq!(vec![1.0, 3.0, 3.0, 5.0, 7.0, 8.0, 9.0], values(&graph));
+        graph.update_aref(items[2], 0.0);
+        graph.update_aref(items[3], 6.0);
+        assert_eq!(vec![0.0, 3.0, 3.0, 5.0, 6.0, 7.0, 8.0], values(&graph));
+        graph.update_aref(items[0], f64::NEG_INFINITY);
+        assert_eq!(f64::NEG_INFINITY, values(&graph)[0]);
+    }
+
+    #[test]
+    fn sorted_matches_sort() {
+        let mut graph = Graph::new();
+
+        let mut seed = 7u64;
+        let mut next = move || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            (seed >> 40) as f64
+        };
+        let mut vals: Vec<f64> = (0..50).map(|_| next()).collect();
+        let items: Vec<_> = vals.iter().map(|&v| graph.new_aref(v)).collect();
+        let ids: Vec<AThunkID> = items.iter().map(|r| r.id()).collect();
+        let sorted = graph.sorted(&ids);
+
+        for i in 0..10 {
+            let v = next();
+            graph.update_aref(items[i * 5], v);
+            vals[i * 5] = v;
+
+            let mut expected = vals.clone();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let actual: Vec<f64> = sorted
+                .iter()
+                .map(|&s| graph.compute(s, &[]).unwrap())
+                .collect();
+            assert_eq!(expected, actual);
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! The style of API offered by the `adapton` crate, on top of the thread-local graph from
+//! `implicit`, so that computations written against one can be tried with the other.
+//!
+//! ```
+//! use micro_adapton_rs::compat::{cell, force, set};
+//! use micro_adapton_rs::{get, name, thunk};
+//!
+//! let x = cell(name!(x), 2.0);
+//! let y = cell(name!(y), 3.0);
+//! let sum = thunk![get!(x) + get!(y)];
+//! let doubled = thunk![2.0 * force(&sum)];
+//!
+//! assert_eq!(10.0, force(&doubled));
+//! set(&x, 4.0);
+//! assert_eq!(14.0, force(&doubled));
+//! // Naming a cell again finds the same one.
+//! cell(name!(y), 0.0);
+//! assert_eq!(8.0, force(&doubled));
+//! ```
+//!
+//! Unlike `implicit`, thunks don't add edges themselves: everything forced while a thunk is
+//! running becomes one of its dependencies, like it would with `adapton`.
+
+use crate::implicit::{self, with_graph};
+use crate::{ARefID, AThunkID};
+use std::cell::RefCell;
+
+/// A reference to a cell or a thunk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Art {
+    id: AThunkID,
+    cell: Option<ARefID>,
+}
+
+impl From<Art> for AThunkID {
+    fn from(art: Art) -> Self {
+        art.id
+    }
+}
+
+/// The name of a cell, usually created with `name!`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Name(String);
+
+impl Name {
+    pub fn new(name: impl Into<String>) -> Self {
+        Name(name.into())
+    }
+}
+
+#[macro_export]
+macro_rules! name {
+    ($name:ident) => {
+        $crate::compat::Name::new(stringify!($name))
+    };
+}
+
+/// A thunk computing `$e`, with everything it forces as a dependency.
+#[macro_export]
+macro_rules! thunk {
+    ($e:expr) => {
+        $crate::compat::thunk(move || $e)
+    };
+}
+
+#[macro_export]
+macro_rules! get {
+    ($art:expr) => {
+        $crate::compat::force(&$art)
+    };
+}
+
+/// An unnamed cell.
+#[macro_export]
+macro_rules! cell {
+    ($val:expr) => {
+        $crate::compat::put($val)
+    };
+}
+
+thread_local! {
+    // What each running thunk has forced so far, innermost thunk last.
+    static FORCED: RefCell<Vec<Vec<AThunkID>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The cell called `name`, which is created the first time it's used and set to `val` every
+/// time after that.
+pub fn cell(name: Name, val: f64) -> Art {
+    let id = with_graph(|g| g.aref_entry(name.0).or_insert(val));
+    set_cell(id, val);
+    Art {
+        id: id.id(),
+        cell: Some(id),
+    }
+}
+
+pub fn put(val: f64) -> Art {
+    let id = implicit::aref(val);
+    Art {
+        id: id.id(),
+        cell: Some(id),
+    }
+}
+
+/// Panics if `art` is a thunk rather than a cell.
+pub fn set(art: &Art, val: f64) {
+    set_cell(art.cell.expect("only cells can be set"), val);
+}
+
+fn set_cell(id: ARefID, val: f64) {
+    if implicit::force(id).ok() != Some(val) {
+        implicit::update_aref(id, val);
+    }
+}
+
+pub fn thunk(f: impl Fn() -> f64 + 'static) -> Art {
+    let id = implicit::athunk(move |h| {
+        FORCED.with(|f| f.borrow_mut().push(Vec::new()));
+        let val = f();
+        let forced = FORCED.with(|f| f.borrow_mut().pop()).unwrap_or_default();
+        // Forcing again through the handle is a memo hit, and lets the handle see every
+        // dependency like any other thunk's.
+        for id in forced {
+            h.add_edge(id);
+            let _ = h.compute(id, &[]);
+        }
+        val
+    });
+    Art { id, cell: None }
+}
+
+/// Outside of a thunk, this panics if computing `art` fails. Inside of one, it returns NaN
+/// instead, and the failure is returned from the thunk's own computation.
+pub fn force(art: &Art) -> f64 {
+    let inside = FORCED.with(|f| match f.borrow_mut().last_mut() {
+        Some(forced) => {
+            forced.push(art.id);
+            true
+        }
+        None => false,
+    });
+    match implicit::force(art.id) {
+        Ok(val) => val,
+        Err(_) if inside => f64::NAN,
+        Err(e) => panic!("{}", e),
+    }
+}
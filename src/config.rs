@@ -0,0 +1,288 @@
+//! Every graph-wide option in one place, and every option for a single node.
+
+use crate::memo::Memo;
+use crate::{AThunkID, AdaptonError, Cutoff, Graph, MemoHasher, Scalar, DEFAULT_MAX_RERUNS};
+
+/// How nodes store their memo tables. See `Graph::set_memo_policy`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum MemoPolicy {
+    /// Hash tables, which memoize a new entry in constant time.
+    #[default]
+    Hashed,
+    /// Sorted arrays for every node with a fixed arity, as with `Graph::set_compact_memo`, and
+    /// hash tables for the rest.
+    Compact,
+}
+
+/// The graph-wide options, each of which can also be set on its own with the `Graph` method of
+/// the same name. The default is what a new graph starts with. Repairs by `stabilize` always
+/// happen in the same order, so there's no option for that.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GraphConfig {
+    /// See `Graph::set_cutoff`.
+    pub cutoff: Cutoff,
+    /// See `Graph::set_max_reruns`.
+    pub max_reruns: usize,
+    /// See `Graph::set_max_depth`.
+    pub max_depth: Option<usize>,
+    /// See `Graph::set_max_nodes`.
+    pub max_nodes: Option<usize>,
+    /// See `Graph::set_strict_tracking`.
+    pub strict_tracking: bool,
+    /// See `Graph::set_incremental`.
+    pub incremental: bool,
+    /// See `Graph::set_propagation_reports`.
+    pub propagation_reports: bool,
+    /// See `Graph::set_provenance_tracking`.
+    pub provenance_tracking: bool,
+    /// See `Graph::set_dirtying_budget`.
+    pub dirtying_budget: Option<usize>,
+    /// See `Graph::set_heavy_workers`.
+    pub heavy_workers: usize,
+    /// See `Graph::set_memo_policy`.
+    pub memo_policy: MemoPolicy,
+}
+
+impl Default for GraphConfig {
+    fn default() -> Self {
+        Self {
+            cutoff: Cutoff::Never,
+            max_reruns: DEFAULT_MAX_RERUNS,
+            max_depth: None,
+            max_nodes: None,
+            strict_tracking: false,
+            incremental: true,
+            propagation_reports: false,
+            provenance_tracking: false,
+            dirtying_budget: None,
+            heavy_workers: 0,
+            memo_policy: MemoPolicy::Hashed,
+        }
+    }
+}
+
+/// The options for a single node, which override the graph's where they overlap.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NodeConfig {
+    /// Overrides the graph's cutoff. See `Graph::set_node_cutoff`.
+    pub cutoff: Option<Cutoff>,
+    /// See `Graph::set_compact_memo`.
+    pub compact_memo: bool,
+    /// A volatile node is re-run every time it's computed, as though the graph weren't
+    /// incremental, for thunks that read something the graph can't track. Nodes that depend on
+    /// it still only see a new value when they're re-run themselves.
+    pub volatile: bool,
+    /// Roughly how expensive the node's thunk is to run, in any unit as long as it's the same for
+    /// every node. `trim_caches` throws away cheap values before expensive ones.
+    pub cost: Option<f64>,
+}
+
+impl Graph {
+    pub fn with_config(config: GraphConfig) -> Self {
+        let mut graph = Self::new();
+        graph.set_config(config);
+        graph
+    }
+}
+
+impl<T: Scalar, S: MemoHasher> Graph<T, S> {
+    /// Sets every graph-wide option at once, as though by calling each one's setter.
+    pub fn set_config(&mut self, config: GraphConfig) {
+        self.set_cutoff(config.cutoff);
+        self.set_max_reruns(config.max_reruns);
+        self.set_max_depth(config.max_depth);
+        self.set_max_nodes(config.max_nodes);
+        self.set_strict_tracking(config.strict_tracking);
+        self.set_incremental(config.incremental);
+        if config.propagation_reports != self.propagation_reports {
+            self.set_propagation_reports(config.propagation_reports);
+        }
+        if config.provenance_tracking != self.provenance_tracking {
+            self.set_provenance_tracking(config.provenance_tracking);
+        }
+        self.set_dirtying_budget(config.dirtying_budget);
+        if config.heavy_workers != self.heavy_workers() {
+            self.set_heavy_workers(config.heavy_workers);
+        }
+        if config.memo_policy != self.memo_policy {
+            self.set_memo_policy(config.memo_policy);
+        }
+    }
+
+    /// Stores the memo tables of every node with a fixed arity according to `policy`, including
+    /// nodes created later on, until either this or `configure_node` says otherwise.
+    pub fn set_memo_policy(&mut self, policy: MemoPolicy) {
+        self.memo_policy = policy;
+        let fixed: Vec<AThunkID> = self
+            .athunks
+            .iter()
+            .filter(|(_, athunk)| athunk.arity.is_some())
+            .map(|(i, _)| AThunkID(i))
+            .collect();
+        for id in fixed {
+            match policy {
+                // Every one of them has a fixed arity.
+                MemoPolicy::Compact => self.set_compact_memo(id).unwrap(),
+                MemoPolicy::Hashed => self.set_hashed_memo(id),
+            }
+        }
+    }
+
+    fn set_hashed_memo(&mut self, id: AThunkID) {
+        let athunk = &mut self.athunks[id.0];
+        if !athunk.result.get_mut().is_compact() {
+            return;
+        }
+        for memo in [
+            &mut athunk.result,
+            &mut athunk.stale,
+            &mut athunk.unverified,
+        ] {
+            let mut hashed = Memo::default();
+            hashed.extend(memo.get_mut().take());
+            memo.replace(hashed);
+        }
+    }
+
+    /// Sets every option for a single node at once. Fails with `VariableArity`, without changing
+    /// anything, if `config` asks for compact memo tables for a node without a fixed arity.
+    pub fn configure_node(
+        &mut self,
+        id: impl Into<AThunkID>,
+        config: NodeConfig,
+    ) -> Result<(), AdaptonError> {
+        let id = id.into();
+        if config.compact_memo && self.athunks[id.0].arity.is_none() {
+            return Err(AdaptonError::VariableArity(id));
+        }
+        match config.cutoff {
+            Some(cutoff) => self.set_node_cutoff(id, cutoff),
+            None => self.athunks[id.0].cutoff = None,
+        }
+        if !config.compact_memo {
+            self.set_hashed_memo(id);
+        } else if !self.athunks[id.0].result.get_mut().is_compact() {
+            self.set_compact_memo(id)?;
+        }
+        let athunk = &mut self.athunks[id.0];
+        athunk.volatile = config.volatile;
+        athunk.cost = config.cost;
+        Ok(())
+    }
+
+    pub fn node_config(&self, id: impl Into<AThunkID>) -> NodeConfig {
+        let athunk = &self.athunks[id.into().0];
+        NodeConfig {
+            cutoff: athunk.cutoff,
+            compact_memo: athunk.result.borrow().is_compact(),
+            volatile: athunk.volatile,
+            cost: athunk.cost,
+        }
+    }
+
+    /// The graph-wide options as they're currently set.
+    pub fn config(&self) -> GraphConfig {
+        GraphConfig {
+            cutoff: self.cutoff,
+            max_reruns: self.max_reruns,
+            max_depth: self.max_depth,
+            max_nodes: self.max_nodes,
+            strict_tracking: self.strict_tracking,
+            incremental: self.incremental,
+            propagation_reports: self.propagation_reports,
+            provenance_tracking: self.provenance_tracking,
+            dirtying_budget: self.dirtying_budget,
+            heavy_workers: self.heavy_workers(),
+            memo_policy: self.memo_policy,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AdaptonError, Cutoff, Graph, GraphConfig, MemoPolicy, NodeConfig};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn config() {
+        assert_eq!(GraphConfig::default(), Graph::new().config());
+
+        let config = GraphConfig {
+            cutoff: Cutoff::Exact,
+            max_depth: Some(1),
+            heavy_workers: 1,
+            memo_policy: MemoPolicy::Compact,
+            ..GraphConfig::default()
+        };
+        let mut graph = Graph::with_config(config);
+        assert_eq!(config, graph.config());
+
+        let r = graph.new_aref(1.0);
+        let a = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap_or(0.0)
+        });
+        assert!(matches!(
+            graph.compute(a, &[]),
+            Err(AdaptonError::DepthLimit { .. })
+        ));
+        graph.set_max_depth(None);
+        assert_eq!(Ok(1.0), graph.compute(a, &[]));
+    }
+
+    #[test]
+    fn node_config() {
+        let mut graph = Graph::new();
+
+        let runs = Rc::new(Cell::new(0));
+        let counter = runs.clone();
+        let a = graph.new_athunk_with_arity(1, move |h| {
+            counter.set(counter.get() + 1);
+            h.args[0] * 2.0
+        });
+        let config = NodeConfig {
+            cutoff: Some(Cutoff::Tolerance(0.1)),
+            compact_memo: true,
+            volatile: true,
+            cost: Some(10.0),
+        };
+        graph.configure_node(a, config).unwrap();
+        assert_eq!(config, graph.node_config(a));
+
+        assert_eq!(Ok(2.0), graph.compute(a, &[1.0]));
+        assert_eq!(Ok(2.0), graph.compute(a, &[1.0]));
+        assert_eq!(2, runs.get());
+
+        graph.configure_node(a, NodeConfig::default()).unwrap();
+        assert_eq!(NodeConfig::default(), graph.node_config(a));
+        // The last value a volatile node was computed to is still memoized.
+        assert_eq!(Ok(2.0), graph.compute(a, &[1.0]));
+        assert_eq!(2, runs.get());
+    }
+
+    #[test]
+    fn compact_memo_needs_a_fixed_arity() {
+        let mut graph = Graph::new();
+        let a = graph.new_athunk(|h| h.args.iter().sum());
+        let config = NodeConfig {
+            compact_memo: true,
+            volatile: true,
+            ..NodeConfig::default()
+        };
+        assert_eq!(
+            Err(AdaptonError::VariableArity(a)),
+            graph.configure_node(a, config)
+        );
+        assert_eq!(NodeConfig::default(), graph.node_config(a));
+
+        graph.set_memo_policy(MemoPolicy::Compact);
+        let b = graph.new_athunk_with_arity(2, |h| h.args[0] - h.args[1]);
+        assert!(graph.node_config(b).compact_memo);
+        assert!(!graph.node_config(a).compact_memo);
+        assert_eq!(Ok(1.0), graph.compute(b, &[3.0, 2.0]));
+        graph.set_memo_policy(MemoPolicy::Hashed);
+        assert!(!graph.node_config(b).compact_memo);
+        assert_eq!(Ok(1.0), graph.compute(b, &[3.0, 2.0]));
+    }
+}
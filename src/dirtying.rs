@@ -0,0 +1,183 @@
+//! Spreading the cost of invalidation over the computations that need it.
+//!
+//! Normally `update_aref` dirties everything that depends on the aref before it returns, which
+//! can take a while when that's a large part of a large graph. With a dirtying budget, it stops
+//! after that many nodes and leaves the rest of the way for later. `compute` only dirties the
+//! nodes between where dirtying stopped and the node being computed, leaving their other
+//! dependents for whatever computes them, while `stabilize` finishes the job before looking at any
+//! values. Updates made in between share the work, as dirtying stops at nodes that are already
+//! dirty.
+
+use crate::{AThunkID, Graph, GraphEvent, MemoHasher, Scalar, TraceEvent};
+use std::collections::{HashMap, VecDeque};
+
+impl<T: Scalar, S: MemoHasher> Graph<T, S> {
+    /// Caps how many nodes `update_aref` dirties itself, or with `None`, the default, dirties
+    /// everything right away. Propagation reports only cover the nodes dirtied by the update.
+    pub fn set_dirtying_budget(&mut self, budget: Option<usize>) {
+        self.dirtying_budget = budget;
+        if budget.is_none() {
+            self.finish_dirtying();
+        }
+    }
+
+    // Dirties `id` and what depends on it, breadth first, until `budget` nodes have been dirtied.
+    // Anything left is kept until `finish_dirtying`.
+    pub(crate) fn dirty_bounded(&self, id: AThunkID, budget: usize) {
+        let mut next = VecDeque::from([id]);
+        let mut dirtied = 0;
+        while dirtied < budget {
+            let athunk = match next.pop_front() {
+                Some(id) => &self.athunks[id.0],
+                None => return,
+            };
+            if athunk.invalidate(self.cutoffs_in_use) {
+                lifecycle!(trace, "dirtied {}", self.node_name(athunk.id));
+                self.emit(GraphEvent::Dirtied(athunk.id));
+                self.record(|| TraceEvent::Dirty(athunk.id));
+                dirtied += 1;
+                next.extend(athunk.super_computations.borrow().iter());
+            }
+        }
+        self.undirtied.borrow_mut().extend(next);
+        self.settled.borrow_mut().clear();
+    }
+
+    // Dirties `id` and what it depends on, if they depend on any node dirtying stopped at. Other
+    // nodes that depend on the nodes dirtied here are left for later in their place.
+    pub(crate) fn dirty_demanded(&self, id: AThunkID) {
+        if self.undirtied.borrow().is_empty() {
+            return;
+        }
+        let mut reached = HashMap::default();
+        self.reaches_undirtied(id, &mut reached);
+        for (&id, &reaches) in reached.iter() {
+            if !reaches {
+                self.settled.borrow_mut().insert(id);
+                continue;
+            }
+            let athunk = &self.athunks[id.0];
+            self.undirtied.borrow_mut().remove(&id);
+            if athunk.invalidate(self.cutoffs_in_use) {
+                lifecycle!(trace, "dirtied {}", self.node_name(id));
+                self.emit(GraphEvent::Dirtied(id));
+                self.record(|| TraceEvent::Dirty(id));
+            }
+            let supers = athunk.super_computations.borrow();
+            let rest = supers.iter().filter(|s| reached.get(s) != Some(&true));
+            self.undirtied.borrow_mut().extend(rest);
+        }
+    }
+
+    // Whether `id` is one of the nodes dirtying stopped at, or depends on one through clean nodes.
+    // Nodes that don't are known not to until the next update.
+    fn reaches_undirtied(&self, id: AThunkID, reached: &mut HashMap<AThunkID, bool, S>) -> bool {
+        if let Some(&reaches) = reached.get(&id) {
+            return reaches;
+        }
+        let athunk = match self.athunks.get(id.0) {
+            Some(athunk) => athunk,
+            None => return false,
+        };
+        if self.undirtied.borrow().contains(&id) {
+            reached.insert(id, true);
+            return true;
+        }
+        // A dirty node's dependents have already been dirtied, or are waiting to be.
+        if athunk.disabled || !athunk.clean.get() || self.settled.borrow().contains(&id) {
+            return false;
+        }
+        reached.insert(id, false);
+        let subs: Vec<AThunkID> = athunk.sub_computations.borrow().iter().collect();
+        let reaches = subs.into_iter().any(|s| self.reaches_undirtied(s, reached));
+        reached.insert(id, reaches);
+        reaches
+    }
+
+    // Dirties everything left behind by `dirty_bounded`.
+    pub(crate) fn finish_dirtying(&self) {
+        if self.undirtied.borrow().is_empty() {
+            return;
+        }
+        for id in self.undirtied.take() {
+            self.dirty(id, None);
+        }
+        self.settled.borrow_mut().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Graph;
+
+    #[test]
+    fn dirtying_budget() {
+        let mut graph = Graph::new();
+        graph.set_dirtying_budget(Some(2));
+
+        let r = graph.new_aref(1.0);
+        let mut chain = vec![r.id()];
+        for _ in 0..10 {
+            let prev = *chain.last().unwrap();
+            chain.push(graph.new_athunk(move |h| {
+                h.add_edge(prev);
+                h.compute(prev, &[]).unwrap() + 1.0
+            }));
+        }
+        let last = *chain.last().unwrap();
+        assert_eq!(Ok(11.0), graph.compute(last, &[]));
+
+        graph.update_aref(r, 2.0);
+        assert_eq!(2, graph.iter().filter(|(_, n)| !n.clean).count());
+        assert_eq!(11, graph.dirty_count());
+        graph.update_aref(r, 3.0);
+        assert_eq!(Ok(13.0), graph.compute(last, &[]));
+
+        graph.update_aref(r, 4.0);
+        assert_eq!(11, graph.stabilize().unwrap().len());
+        assert_eq!(Ok(14.0), graph.compute(last, &[]));
+    }
+
+    #[test]
+    fn dirtying_demanded_nodes() {
+        let mut graph = Graph::new();
+        graph.set_dirtying_budget(Some(1));
+
+        let r = graph.new_aref(1.0);
+        let chains: Vec<Vec<_>> = (0..2)
+            .map(|_| {
+                let mut chain = vec![r.id()];
+                for _ in 0..3 {
+                    let prev = *chain.last().unwrap();
+                    chain.push(graph.new_athunk_with_deps(&[prev], |_, vals| vals[0] + 1.0));
+                }
+                chain.split_off(1)
+            })
+            .collect();
+        for chain in chains.iter() {
+            assert_eq!(Ok(4.0), graph.compute(chain[2], &[]));
+        }
+
+        graph.update_aref(r, 2.0);
+        let expected: Vec<_> = chains[0]
+            .iter()
+            .chain([&r.id()])
+            .map(|&id| (id, 1))
+            .collect();
+        graph.assert_recomputes(&expected, |graph| {
+            assert_eq!(Ok(5.0), graph.compute(chains[0][2], &[]));
+        });
+        // The other chain's nodes haven't been looked at, let alone dirtied.
+        let clean = graph
+            .iter()
+            .filter(|(id, n)| chains[1].contains(id) && n.clean);
+        assert_eq!(3, clean.count());
+
+        let expected: Vec<_> = chains[1].iter().map(|&id| (id, 1)).collect();
+        graph.assert_recomputes(&expected, |graph| {
+            assert_eq!(Ok(4.0), graph.compute(chains[1][1], &[]));
+            assert_eq!(1, graph.stabilize().unwrap().len());
+        });
+        assert_eq!(Ok(5.0), graph.compute(chains[1][2], &[]));
+    }
+}
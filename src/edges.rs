@@ -0,0 +1,264 @@
+//! Edge sets that change how they're stored as they grow.
+//!
+//! Most nodes only have a handful of edges in either direction, which are kept inline without any
+//! allocation. Past that they're kept as a sorted vector, and once a node has enough edges that a
+//! bit per node in the graph would take less room, as a bitset. Sets don't go back to a smaller
+//! representation when edges are removed, since nodes tend to keep roughly the same edges from one
+//! run to the next.
+
+use crate::AThunkID;
+use std::iter::FromIterator;
+
+// How many edges are kept inline.
+const INLINE: usize = 4;
+
+// How many edges a set needs before a bitset is considered at all.
+const DENSE: usize = 64;
+
+#[derive(Clone)]
+pub(crate) enum EdgeSet {
+    Inline(usize, [usize; INLINE]),
+    Sorted(Vec<usize>),
+    Bits { words: Vec<u64>, len: usize },
+}
+
+impl Default for EdgeSet {
+    fn default() -> Self {
+        EdgeSet::Inline(0, [0; INLINE])
+    }
+}
+
+impl EdgeSet {
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            EdgeSet::Inline(len, _) | EdgeSet::Bits { len, .. } => *len,
+            EdgeSet::Sorted(ids) => ids.len(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn contains(&self, id: &AThunkID) -> bool {
+        let i = id.0;
+        match self {
+            EdgeSet::Inline(len, ids) => ids[..*len].contains(&i),
+            EdgeSet::Sorted(ids) => ids.binary_search(&i).is_ok(),
+            EdgeSet::Bits { words, .. } => words
+                .get(i / 64)
+                .is_some_and(|word| word & (1 << (i % 64)) != 0),
+        }
+    }
+
+    /// Returns whether the edge is new.
+    pub(crate) fn insert(&mut self, id: AThunkID) -> bool {
+        let i = id.0;
+        match self {
+            EdgeSet::Inline(len, ids) => {
+                if ids[..*len].contains(&i) {
+                    return false;
+                }
+                if *len < INLINE {
+                    ids[*len] = i;
+                    *len += 1;
+                    return true;
+                }
+                let mut sorted = ids.to_vec();
+                sorted.push(i);
+                sorted.sort_unstable();
+                *self = EdgeSet::Sorted(sorted);
+            }
+            EdgeSet::Sorted(ids) => match ids.binary_search(&i) {
+                Ok(_) => return false,
+                Err(at) => ids.insert(at, i),
+            },
+            EdgeSet::Bits { words, len } => {
+                if words.len() <= i / 64 {
+                    words.resize(i / 64 + 1, 0);
+                }
+                let bit = 1 << (i % 64);
+                if words[i / 64] & bit != 0 {
+                    return false;
+                }
+                words[i / 64] |= bit;
+                *len += 1;
+                return true;
+            }
+        }
+        self.densify();
+        true
+    }
+
+    pub(crate) fn remove(&mut self, id: &AThunkID) -> bool {
+        let i = id.0;
+        match self {
+            EdgeSet::Inline(len, ids) => match ids[..*len].iter().position(|&x| x == i) {
+                Some(at) => {
+                    ids.copy_within(at + 1..*len, at);
+                    *len -= 1;
+                    true
+                }
+                None => false,
+            },
+            EdgeSet::Sorted(ids) => match ids.binary_search(&i) {
+                Ok(at) => {
+                    ids.remove(at);
+                    true
+                }
+                Err(_) => false,
+            },
+            EdgeSet::Bits { words, len } => {
+                let bit = 1 << (i % 64);
+                match words.get_mut(i / 64) {
+                    Some(word) if *word & bit != 0 => {
+                        *word &= !bit;
+                        *len -= 1;
+                        true
+                    }
+                    _ => false,
+                }
+            }
+        }
+    }
+
+    /// The edges in order of their nodes' IDs.
+    pub(crate) fn iter(&self) -> Box<dyn Iterator<Item = AThunkID> + '_> {
+        match self {
+            EdgeSet::Inline(len, ids) => {
+                let mut sorted = *ids;
+                sorted[..*len].sort_unstable();
+                Box::new((0..*len).map(move |at| AThunkID(sorted[at])))
+            }
+            EdgeSet::Sorted(ids) => Box::new(ids.iter().map(|&i| AThunkID(i))),
+            EdgeSet::Bits { words, .. } => {
+                Box::new(words.iter().enumerate().flat_map(|(w, &word)| {
+                    (0..64)
+                        .filter(move |b| word & (1 << b) != 0)
+                        .map(move |b| AThunkID(w * 64 + b))
+                }))
+            }
+        }
+    }
+
+    pub(crate) fn drain(&mut self) -> std::vec::IntoIter<AThunkID> {
+        std::mem::take(self).into_iter()
+    }
+
+    /// Removes every edge, keeping whatever the set has allocated.
+    pub(crate) fn clear(&mut self) {
+        match self {
+            EdgeSet::Inline(len, _) => *len = 0,
+            EdgeSet::Sorted(ids) => ids.clear(),
+            EdgeSet::Bits { words, len } => {
+                words.iter_mut().for_each(|word| *word = 0);
+                *len = 0;
+            }
+        }
+    }
+
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        match self {
+            EdgeSet::Inline(len, ids) if *len + additional > INLINE => {
+                let mut sorted = Vec::with_capacity(*len + additional);
+                sorted.extend_from_slice(&ids[..*len]);
+                sorted.sort_unstable();
+                *self = EdgeSet::Sorted(sorted);
+            }
+            EdgeSet::Sorted(ids) => ids.reserve(additional),
+            _ => {}
+        }
+    }
+
+    pub(crate) fn retain(&mut self, mut keep: impl FnMut(&AThunkID) -> bool) {
+        let removed: Vec<AThunkID> = self.iter().filter(|id| !keep(id)).collect();
+        for id in removed {
+            self.remove(&id);
+        }
+    }
+
+    pub(crate) fn is_subset<S>(&self, other: &std::collections::HashSet<AThunkID, S>) -> bool
+    where
+        S: std::hash::BuildHasher,
+    {
+        self.iter().all(|id| other.contains(&id))
+    }
+
+    // Switches a sorted vector to a bitset once the bitset would be smaller.
+    fn densify(&mut self) {
+        if let EdgeSet::Sorted(ids) = self {
+            let words = ids.last().map_or(0, |max| max / 64 + 1);
+            if ids.len() >= DENSE && words < ids.len() {
+                let mut bits = vec![0u64; words];
+                for &i in ids.iter() {
+                    bits[i / 64] |= 1 << (i % 64);
+                }
+                let len = ids.len();
+                *self = EdgeSet::Bits { words: bits, len };
+            }
+        }
+    }
+}
+
+impl IntoIterator for EdgeSet {
+    type Item = AThunkID;
+    type IntoIter = std::vec::IntoIter<AThunkID>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter().collect::<Vec<_>>().into_iter()
+    }
+}
+
+impl Extend<AThunkID> for EdgeSet {
+    fn extend<I: IntoIterator<Item = AThunkID>>(&mut self, ids: I) {
+        for id in ids {
+            self.insert(id);
+        }
+    }
+}
+
+impl FromIterator<AThunkID> for EdgeSet {
+    fn from_iter<I: IntoIterator<Item = AThunkID>>(ids: I) -> Self {
+        let mut set = EdgeSet::default();
+        set.extend(ids);
+        set
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EdgeSet;
+    use crate::AThunkID;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn edge_sets() {
+        let mut set = EdgeSet::default();
+        let mut expected = BTreeSet::new();
+        let mut seed = 7u64;
+        for step in 0..2000 {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            let id = AThunkID((seed >> 33) as usize % 300);
+            if step % 4 == 0 {
+                assert_eq!(expected.remove(&id), set.remove(&id));
+            } else {
+                assert_eq!(expected.insert(id), set.insert(id));
+            }
+            assert_eq!(expected.len(), set.len());
+            assert_eq!(expected.contains(&id), set.contains(&id));
+            if step == 2 {
+                assert!(matches!(set, EdgeSet::Inline(..)));
+            }
+        }
+        assert!(matches!(set, EdgeSet::Bits { .. }));
+        assert_eq!(
+            expected.iter().copied().collect::<Vec<_>>(),
+            set.iter().collect::<Vec<_>>()
+        );
+
+        let sparse: EdgeSet = (0..100).map(|i| AThunkID(i * 1000)).collect();
+        assert!(matches!(sparse, EdgeSet::Sorted(_)));
+        set.retain(|id| id.0 % 2 == 0);
+        assert!(set.iter().all(|id| id.0 % 2 == 0));
+    }
+}
@@ -0,0 +1,337 @@
+use crate::{AThunkID, Graph, MemoHasher, Scalar};
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdaptonError {
+    /// Something the graph keeps track of was needed while it was already in use, such as when an
+    /// observer's callback stabilizes the graph it's observing. `node` is whichever node was using
+    /// it, along with its label, and `during` is what was being attempted.
+    BorrowConflict {
+        node: Option<AThunkID>,
+        label: Option<String>,
+        during: &'static str,
+    },
+    /// The ID doesn't refer to a node in this graph.
+    NoSuchNode(AThunkID),
+    /// The node was retired with `Graph::disable`.
+    Disabled(AThunkID),
+    /// The aref was made with `Graph::new_pending`, and hasn't been fulfilled yet.
+    Pending(AThunkID),
+    /// The node was poisoned, either with `Graph::poison` or by its thunk.
+    Poisoned(AThunkID),
+    /// With poison propagation on, `id` failed because `source`, which it depends on, is
+    /// poisoned.
+    UpstreamError {
+        id: AThunkID,
+        source: AThunkID,
+    },
+    /// The node was demanded while it was already being computed, either because the graph has a
+    /// cycle or because a thunk got hold of the graph some other way and computed it again.
+    /// `path` is the chain of demands that led back to the node, ending with `demanded_by` and
+    /// the node itself.
+    Cycle {
+        id: AThunkID,
+        demanded_by: AThunkID,
+        path: Vec<AThunkID>,
+    },
+    /// The node was still being invalidated by its own sub-computations after `reruns`
+    /// re-executions.
+    RerunLimit {
+        id: AThunkID,
+        reruns: usize,
+    },
+    /// In strict tracking mode, `id` computed `sub_id` without adding an edge to it first.
+    UntrackedCompute {
+        id: AThunkID,
+        sub_id: AThunkID,
+    },
+    /// In strict tracking mode, `id` added an edge to `sub_id` but never computed it.
+    UnusedEdge {
+        id: AThunkID,
+        sub_id: AThunkID,
+    },
+    Arity(ArityError),
+    /// The node doesn't have a fixed arity, which storing its memo tables compactly needs.
+    VariableArity(AThunkID),
+    /// The node can't be differentiated through, because it isn't an arithmetic combinator.
+    NotDifferentiable(AThunkID),
+    /// The deadline of `Graph::compute_timeout` passed before `id` could be run, after
+    /// `recomputed` other thunks had been run in `elapsed`.
+    Timeout {
+        id: AThunkID,
+        recomputed: usize,
+        elapsed: Duration,
+    },
+    /// Running the last node of `path` would have gone over the graph's maximum depth. The path is
+    /// the chain of demands that led there, outermost first.
+    DepthLimit {
+        path: Vec<AThunkID>,
+    },
+    /// Computing the last node of `path` would have created more than `limit` node instances.
+    NodeLimit {
+        limit: usize,
+        path: Vec<AThunkID>,
+    },
+    /// The heavy node's function panicked on a worker thread, with `message` if the panic had
+    /// one. The node is left dirty.
+    HeavyPanicked {
+        id: AThunkID,
+        message: Option<String>,
+    },
+}
+
+impl fmt::Display for AdaptonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AdaptonError::BorrowConflict {
+                node,
+                label,
+                during,
+            } => {
+                write!(f, "can't {} while ", during)?;
+                match (node, label) {
+                    (Some(id), Some(label)) => write!(f, "{} ({:?}) is using it", id, label),
+                    (Some(id), None) => write!(f, "{} is using it", id),
+                    _ => write!(f, "it's already in use"),
+                }
+            }
+            AdaptonError::NoSuchNode(id) => write!(f, "{} doesn't exist", id),
+            AdaptonError::Disabled(id) => write!(f, "{} is disabled", id),
+            AdaptonError::Pending(id) => write!(f, "{} doesn't have a value yet", id),
+            AdaptonError::Poisoned(id) => write!(f, "{} is poisoned", id),
+            AdaptonError::UpstreamError { id, source } => {
+                write!(f, "{} depends on {}, which is poisoned", id, source)
+            }
+            AdaptonError::Cycle {
+                id,
+                demanded_by,
+                path,
+            } => write!(
+                f,
+                "{} was demanded by {} while it was being computed, via {:?}",
+                id, demanded_by, path
+            ),
+            AdaptonError::RerunLimit { id, reruns } => write!(
+                f,
+                "{} was still dirty after being re-run {} times",
+                id, reruns
+            ),
+            AdaptonError::UntrackedCompute { id, sub_id } => {
+                write!(f, "{} computed {} without adding an edge to it", id, sub_id)
+            }
+            AdaptonError::UnusedEdge { id, sub_id } => write!(
+                f,
+                "{} added an edge to {} but never computed it",
+                id, sub_id
+            ),
+            AdaptonError::Arity(e) => e.fmt(f),
+            AdaptonError::VariableArity(id) => write!(f, "{} doesn't have a fixed arity", id),
+            AdaptonError::NotDifferentiable(id) => write!(f, "{} can't be differentiated", id),
+            AdaptonError::Timeout {
+                id,
+                recomputed,
+                elapsed,
+            } => write!(
+                f,
+                "timed out before running {}, after running {} thunks in {:?}",
+                id, recomputed, elapsed
+            ),
+            AdaptonError::DepthLimit { path } => {
+                write!(f, "maximum depth exceeded via {:?}", path)
+            }
+            AdaptonError::NodeLimit { limit, path } => write!(
+                f,
+                "more than {} node instances needed via {:?}",
+                limit, path
+            ),
+            AdaptonError::HeavyPanicked { id, message } => match message {
+                Some(message) => write!(f, "{} panicked on a worker: {}", id, message),
+                None => write!(f, "{} panicked on a worker", id),
+            },
+        }
+    }
+}
+
+impl Error for AdaptonError {}
+
+impl AdaptonError {
+    /// Every node the error mentions, in the order it mentions them, each only once.
+    pub fn nodes(&self) -> Vec<AThunkID> {
+        let mut nodes = match self {
+            AdaptonError::BorrowConflict { node, .. } => node.iter().copied().collect(),
+            AdaptonError::NoSuchNode(id)
+            | AdaptonError::Disabled(id)
+            | AdaptonError::Pending(id)
+            | AdaptonError::Poisoned(id)
+            | AdaptonError::NotDifferentiable(id)
+            | AdaptonError::VariableArity(id)
+            | AdaptonError::RerunLimit { id, .. }
+            | AdaptonError::Timeout { id, .. }
+            | AdaptonError::HeavyPanicked { id, .. } => vec![*id],
+            AdaptonError::Arity(e) => vec![e.id],
+            AdaptonError::UpstreamError { id, source: sub_id }
+            | AdaptonError::UntrackedCompute { id, sub_id }
+            | AdaptonError::UnusedEdge { id, sub_id } => vec![*id, *sub_id],
+            AdaptonError::Cycle {
+                id,
+                demanded_by,
+                path,
+            } => [*id, *demanded_by].iter().chain(path).copied().collect(),
+            AdaptonError::DepthLimit { path } | AdaptonError::NodeLimit { path, .. } => {
+                path.clone()
+            }
+        };
+        let mut seen = Vec::new();
+        nodes.retain(|id| {
+            let new = !seen.contains(id);
+            seen.push(*id);
+            new
+        });
+        nodes
+    }
+}
+
+impl<T: Scalar, S: MemoHasher> Graph<T, S> {
+    /// The error's message, followed by a line for each node it mentions that has a label or an
+    /// annotation, for errors that would otherwise only give slab indices.
+    pub fn explain_error(&self, error: &AdaptonError) -> String {
+        let mut explained = error.to_string();
+        for id in error.nodes() {
+            let athunk = match self.athunks.get(id.0) {
+                Some(athunk) if athunk.label.is_some() || athunk.annotation.is_some() => athunk,
+                _ => continue,
+            };
+            explained.push_str(&format!("\n  {}", self.node_name(id)));
+            if let Some(annotation) = &athunk.annotation {
+                explained.push_str(&format!(": {}", annotation));
+            }
+        }
+        explained
+    }
+}
+
+impl From<ArityError> for AdaptonError {
+    fn from(e: ArityError) -> Self {
+        AdaptonError::Arity(e)
+    }
+}
+
+/// A node was given the wrong number of arguments. When returned from `Handle::arg`, `expected`
+/// is the least number of arguments that would have worked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArityError {
+    pub id: AThunkID,
+    pub expected: usize,
+    pub found: usize,
+}
+
+impl fmt::Display for ArityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} expected {} arguments but was given {}",
+            self.id, self.expected, self.found
+        )
+    }
+}
+
+impl Error for ArityError {}
+
+/// A formula given to `Graph::new_formula` couldn't be compiled. Positions are byte offsets into
+/// the formula.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormulaError {
+    /// Something other than `expected` was found at `position`, which is the formula's length if
+    /// it ended too soon.
+    Syntax {
+        position: usize,
+        expected: &'static str,
+    },
+    /// `name` is neither bound nor a function.
+    UnknownName { position: usize, name: String },
+    /// The function `name` was called with `found` arguments, which it doesn't take.
+    WrongArguments {
+        position: usize,
+        name: String,
+        found: usize,
+    },
+    /// The formula nests more than `MAX_FORMULA_DEPTH` deep, which it first does at `position`.
+    TooDeep { position: usize },
+}
+
+impl fmt::Display for FormulaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FormulaError::Syntax { position, expected } => {
+                write!(f, "expected {} at {}", expected, position)
+            }
+            FormulaError::UnknownName { position, name } => {
+                write!(f, "unknown name {:?} at {}", name, position)
+            }
+            FormulaError::WrongArguments {
+                position,
+                name,
+                found,
+            } => write!(
+                f,
+                "{} can't take {} arguments, at {}",
+                name, found, position
+            ),
+            FormulaError::TooDeep { position } => {
+                write!(f, "nested too deeply at {}", position)
+            }
+        }
+    }
+}
+
+impl Error for FormulaError {}
+
+/// A spec given to `Graph::from_json` couldn't be loaded. Positions are byte offsets into the spec.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpecError {
+    /// The spec isn't valid JSON: something other than `expected` was found at `position`.
+    Json {
+        position: usize,
+        expected: &'static str,
+    },
+    /// The spec nests arrays and objects more than `MAX_SPEC_DEPTH` deep, which it first does at
+    /// `position`.
+    TooDeep { position: usize },
+    /// The value at `path`, such as `inputs.price`, isn't `expected`.
+    Schema {
+        path: String,
+        expected: &'static str,
+    },
+    /// Two inputs or nodes share a label.
+    DuplicateLabel(String),
+    /// The formula of the node labeled `node` couldn't be compiled. Only inputs and nodes that come
+    /// before it in the spec are bound.
+    Formula { node: String, error: FormulaError },
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpecError::Json { position, expected } => {
+                write!(f, "invalid JSON: expected {} at {}", expected, position)
+            }
+            SpecError::TooDeep { position } => write!(f, "nested too deeply at {}", position),
+            SpecError::Schema { path, expected } => write!(f, "{} should be {}", path, expected),
+            SpecError::DuplicateLabel(label) => write!(f, "{:?} is defined more than once", label),
+            SpecError::Formula { node, error } => {
+                write!(f, "in the formula for {:?}: {}", node, error)
+            }
+        }
+    }
+}
+
+impl Error for SpecError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SpecError::Formula { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
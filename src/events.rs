@@ -0,0 +1,73 @@
+//! A live feed of what the graph is doing, for anything that wants to follow along from outside.
+
+use crate::{AThunkID, Graph};
+use std::sync::mpsc::{self, Receiver};
+
+/// Something that happened to a node, sent to every receiver returned by `Graph::events`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GraphEvent {
+    Created(AThunkID),
+    Dirtied(AThunkID),
+    /// The node's thunk was run.
+    Recomputed(AThunkID),
+    /// The node's thunk was run and came up with a different value from the one it had before
+    /// it was dirtied.
+    Changed(AThunkID),
+}
+
+impl<T, S> Graph<T, S> {
+    /// A new receiver for every event from now on. Events are only sent while there's a receiver
+    /// to send them to, and a receiver that's dropped stops being sent anything.
+    pub fn events(&self) -> Receiver<GraphEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.event_senders.borrow_mut().push(tx);
+        rx
+    }
+
+    pub(crate) fn emit(&self, event: GraphEvent) {
+        let mut senders = self.event_senders.borrow_mut();
+        if !senders.is_empty() {
+            senders.retain(|tx| tx.send(event).is_ok());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Graph, GraphEvent};
+
+    #[test]
+    fn events() {
+        let mut graph = Graph::new();
+        let events = graph.events();
+
+        let r = graph.new_aref(1.0);
+        let a = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            (h.compute(r, &[]).unwrap() > 0.0) as u8 as f64
+        });
+        graph.compute(a, &[]).unwrap();
+        graph.update_aref(r, 2.0);
+        graph.compute(a, &[]).unwrap();
+
+        let r = r.id();
+        assert_eq!(
+            vec![
+                GraphEvent::Created(r),
+                GraphEvent::Created(a),
+                GraphEvent::Recomputed(a),
+                GraphEvent::Recomputed(r),
+                GraphEvent::Dirtied(r),
+                GraphEvent::Dirtied(a),
+                GraphEvent::Recomputed(a),
+                GraphEvent::Recomputed(r),
+                GraphEvent::Changed(r),
+            ],
+            events.try_iter().collect::<Vec<_>>()
+        );
+
+        drop(events);
+        graph.new_aref(0.0);
+        assert!(graph.event_senders.borrow().is_empty());
+    }
+}
@@ -0,0 +1,382 @@
+//! Compiling arithmetic formulas, written as strings at runtime, into nodes.
+//!
+//! A formula is made of numbers, names bound to nodes, `+`, `-`, `*`, `/`, unary `-`, parentheses
+//! and calls to `abs`, `sqrt`, `min` and `max`, with the usual precedence: `(a + b) / 2`,
+//! `max(0, -x * 1.5e3)`.
+//!
+//! Formulas are parsed and evaluated recursively, so they can't nest more than
+//! `MAX_FORMULA_DEPTH` deep, counting each parenthesis, call, unary `-` and operator in a chain
+//! such as `a + b + c` as a level.
+
+use crate::{AThunkID, FormulaError, Graph, MemoHasher};
+
+/// How deeply a formula can nest.
+pub const MAX_FORMULA_DEPTH: usize = 256;
+
+enum Expr {
+    Num(f64),
+    // An index into the formula's dependencies.
+    Var(usize),
+    Neg(Box<Expr>),
+    Bin(u8, Box<Expr>, Box<Expr>),
+    Call(Func, Vec<Expr>),
+}
+
+#[derive(Clone, Copy)]
+enum Func {
+    Abs,
+    Sqrt,
+    Min,
+    Max,
+}
+
+impl Expr {
+    fn eval(&self, vals: &[f64]) -> f64 {
+        match self {
+            Expr::Num(n) => *n,
+            Expr::Var(i) => vals[*i],
+            Expr::Neg(e) => -e.eval(vals),
+            Expr::Bin(op, a, b) => {
+                let (a, b) = (a.eval(vals), b.eval(vals));
+                match op {
+                    b'+' => a + b,
+                    b'-' => a - b,
+                    b'*' => a * b,
+                    _ => a / b,
+                }
+            }
+            Expr::Call(func, args) => {
+                let mut args = args.iter().map(|e| e.eval(vals));
+                match func {
+                    Func::Abs => args.next().unwrap().abs(),
+                    Func::Sqrt => args.next().unwrap().sqrt(),
+                    Func::Min => args.fold(f64::INFINITY, f64::min),
+                    Func::Max => args.fold(f64::NEG_INFINITY, f64::max),
+                }
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    src: &'a [u8],
+    pos: usize,
+    bindings: &'a [(&'a str, AThunkID)],
+    deps: Vec<AThunkID>,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    // Skips whitespace and returns the next byte, if there is one.
+    fn peek(&mut self) -> Option<u8> {
+        while self.src.get(self.pos).is_some_and(u8::is_ascii_whitespace) {
+            self.pos += 1;
+        }
+        self.src.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        let found = self.peek() == Some(c);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, c: u8, expected: &'static str) -> Result<(), FormulaError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(expected))
+        }
+    }
+
+    fn error(&mut self, expected: &'static str) -> FormulaError {
+        self.peek();
+        FormulaError::Syntax {
+            position: self.pos,
+            expected,
+        }
+    }
+
+    // Goes a level deeper, failing past `MAX_FORMULA_DEPTH`. Callers put the depth back once
+    // they're done.
+    fn nest(&mut self) -> Result<(), FormulaError> {
+        self.depth += 1;
+        if self.depth > MAX_FORMULA_DEPTH {
+            self.peek();
+            return Err(FormulaError::TooDeep { position: self.pos });
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self) -> Result<Expr, FormulaError> {
+        let depth = self.depth;
+        self.nest()?;
+        let mut lhs = self.term()?;
+        while let Some(op @ (b'+' | b'-')) = self.peek() {
+            self.pos += 1;
+            self.nest()?;
+            lhs = Expr::Bin(op, Box::new(lhs), Box::new(self.term()?));
+        }
+        self.depth = depth;
+        Ok(lhs)
+    }
+
+    fn term(&mut self) -> Result<Expr, FormulaError> {
+        let depth = self.depth;
+        let mut lhs = self.unary()?;
+        while let Some(op @ (b'*' | b'/')) = self.peek() {
+            self.pos += 1;
+            self.nest()?;
+            lhs = Expr::Bin(op, Box::new(lhs), Box::new(self.unary()?));
+        }
+        self.depth = depth;
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, FormulaError> {
+        if self.eat(b'-') {
+            let depth = self.depth;
+            self.nest()?;
+            let e = Expr::Neg(Box::new(self.unary()?));
+            self.depth = depth;
+            return Ok(e);
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, FormulaError> {
+        match self.peek() {
+            Some(b'(') => {
+                self.pos += 1;
+                let e = self.expr()?;
+                self.expect(b')', "`)`")?;
+                Ok(e)
+            }
+            Some(c) if c.is_ascii_digit() || c == b'.' => self.number(),
+            Some(c) if c.is_ascii_alphabetic() || c == b'_' => self.name(),
+            _ => Err(self.error("a number, name or `(`")),
+        }
+    }
+
+    fn number(&mut self) -> Result<Expr, FormulaError> {
+        let start = self.pos;
+        let mut end = start;
+        while let Some(&c) = self.src.get(end) {
+            let exponent_sign =
+                (c == b'+' || c == b'-') && matches!(self.src[end - 1], b'e' | b'E');
+            if !(c.is_ascii_digit() || c == b'.' || c == b'e' || c == b'E' || exponent_sign) {
+                break;
+            }
+            end += 1;
+        }
+        // Everything scanned is ASCII.
+        let text = std::str::from_utf8(&self.src[start..end]).unwrap();
+        match text.parse() {
+            Ok(n) => {
+                self.pos = end;
+                Ok(Expr::Num(n))
+            }
+            Err(_) => Err(FormulaError::Syntax {
+                position: start,
+                expected: "a number",
+            }),
+        }
+    }
+
+    fn name(&mut self) -> Result<Expr, FormulaError> {
+        let start = self.pos;
+        while self
+            .src
+            .get(self.pos)
+            .is_some_and(|&c| c.is_ascii_alphanumeric() || c == b'_')
+        {
+            self.pos += 1;
+        }
+        let name = std::str::from_utf8(&self.src[start..self.pos]).unwrap();
+        if self.eat(b'(') {
+            return self.call(name, start);
+        }
+        let &(_, id) = self
+            .bindings
+            .iter()
+            .find(|(bound, _)| *bound == name)
+            .ok_or_else(|| FormulaError::UnknownName {
+                position: start,
+                name: name.to_string(),
+            })?;
+        let i = match self.deps.iter().position(|&d| d == id) {
+            Some(i) => i,
+            None => {
+                self.deps.push(id);
+                self.deps.len() - 1
+            }
+        };
+        Ok(Expr::Var(i))
+    }
+
+    // Parses the arguments of a call to `name`, whose opening parenthesis has been eaten.
+    fn call(&mut self, name: &str, position: usize) -> Result<Expr, FormulaError> {
+        let func = match name {
+            "abs" => Func::Abs,
+            "sqrt" => Func::Sqrt,
+            "min" => Func::Min,
+            "max" => Func::Max,
+            _ => {
+                return Err(FormulaError::UnknownName {
+                    position,
+                    name: name.to_string(),
+                })
+            }
+        };
+        let mut args = Vec::new();
+        if !self.eat(b')') {
+            loop {
+                args.push(self.expr()?);
+                if self.eat(b')') {
+                    break;
+                }
+                self.expect(b',', "`,` or `)`")?;
+            }
+        }
+        let fits = match func {
+            Func::Abs | Func::Sqrt => args.len() == 1,
+            Func::Min | Func::Max => !args.is_empty(),
+        };
+        if !fits {
+            return Err(FormulaError::WrongArguments {
+                position,
+                name: name.to_string(),
+                found: args.len(),
+            });
+        }
+        Ok(Expr::Call(func, args))
+    }
+}
+
+impl<S: MemoHasher> Graph<f64, S> {
+    /// Compiles `formula` into a node with edges to the nodes its names are bound to in
+    /// `bindings`, so that computations can be defined by whoever is using a program rather than
+    /// by the program itself. See the module documentation for what a formula can hold.
+    pub fn new_formula(
+        &mut self,
+        formula: &str,
+        bindings: &[(&str, AThunkID)],
+    ) -> Result<AThunkID, FormulaError> {
+        let mut parser = Parser {
+            src: formula.as_bytes(),
+            pos: 0,
+            bindings,
+            deps: Vec::new(),
+            depth: 0,
+        };
+        let expr = parser.expr()?;
+        if parser.peek().is_some() {
+            return Err(parser.error("an operator or the end of the formula"));
+        }
+        Ok(self.new_athunk_with_deps(&parser.deps, move |_, vals| expr.eval(vals)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MAX_FORMULA_DEPTH;
+    use crate::{FormulaError, Graph};
+
+    #[test]
+    fn formulas() {
+        let mut graph = Graph::new();
+        let a = graph.new_aref(3.0);
+        let b = graph.new_aref(5.0);
+        let bindings = [("a", a.id()), ("b", b.id())];
+
+        let mean = graph.new_formula("(a + b) / 2", &bindings).unwrap();
+        assert_eq!(Ok(4.0), graph.compute(mean, &[]));
+        graph.update_aref(a, 7.0);
+        assert_eq!(Ok(6.0), graph.compute(mean, &[]));
+
+        let cases = [
+            ("a - b - 1", 1.0),
+            ("-a * -2 + b * 3", 29.0),
+            ("max(0, a - 10) + min(a, b, 1.5e1)", 5.0),
+            ("sqrt(abs(-b * 5)) * .5e1", 25.0),
+            ("2 * (b/5 + a)", 16.0),
+        ];
+        for &(formula, expected) in cases.iter() {
+            let f = graph.new_formula(formula, &bindings).unwrap();
+            assert_eq!(Ok(expected), graph.compute(f, &[]), "{}", formula);
+        }
+
+        let f = graph.new_formula("a * a + a", &bindings).unwrap();
+        assert_eq!(1, graph.athunks[f.0].sub_computations.borrow().len());
+        assert_eq!(Ok(56.0), graph.compute(f, &[]));
+    }
+
+    #[test]
+    fn formula_errors() {
+        let mut graph = Graph::new();
+        let a = graph.new_aref(1.0);
+        let bindings = [("a", a.id())];
+        let error = |graph: &mut Graph, formula| graph.new_formula(formula, &bindings).unwrap_err();
+
+        assert_eq!(
+            FormulaError::Syntax {
+                position: 4,
+                expected: "a number, name or `(`"
+            },
+            error(&mut graph, "a + ")
+        );
+        assert_eq!(
+            FormulaError::Syntax {
+                position: 7,
+                expected: "`)`"
+            },
+            error(&mut graph, "(a + 1 a")
+        );
+        assert_eq!(
+            FormulaError::Syntax {
+                position: 2,
+                expected: "an operator or the end of the formula"
+            },
+            error(&mut graph, "a a")
+        );
+        assert_eq!(
+            FormulaError::UnknownName {
+                position: 4,
+                name: "b".to_string()
+            },
+            error(&mut graph, "a + b")
+        );
+        assert_eq!(
+            FormulaError::WrongArguments {
+                position: 0,
+                name: "abs".to_string(),
+                found: 2
+            },
+            error(&mut graph, "abs(a, 1)")
+        );
+        let nested = "(".repeat(MAX_FORMULA_DEPTH) + "a" + &")".repeat(MAX_FORMULA_DEPTH);
+        assert_eq!(
+            FormulaError::TooDeep { position: 256 },
+            error(&mut graph, &nested)
+        );
+        assert!(graph
+            .new_formula(&nested[1..nested.len() - 1], &bindings)
+            .is_ok());
+        let negated = "-".repeat(100_000) + "a";
+        assert!(matches!(
+            error(&mut graph, &negated),
+            FormulaError::TooDeep { .. }
+        ));
+        let chain = vec!["a"; MAX_FORMULA_DEPTH + 1].join(" + ");
+        assert!(matches!(
+            error(&mut graph, &chain),
+            FormulaError::TooDeep { .. }
+        ));
+        assert_eq!(
+            "unknown name \"b\" at 4",
+            error(&mut graph, "a + b").to_string()
+        );
+    }
+}
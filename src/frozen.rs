@@ -0,0 +1,176 @@
+//! A graph whose structure can no longer change.
+
+use crate::{
+    ARefID, AThunkID, AdaptonError, Graph, GraphEvent, KeyHash, MemoHasher, Scalar, TraceEvent,
+};
+
+/// A graph that only supports updating arefs and computing nodes, created by `Graph::freeze`.
+///
+/// Thunks keep their edges between runs, so a run neither tears down nor rebuilds edge sets, and
+/// invalidation follows flat arrays of the nodes laid out in dependency order, rather than walking
+/// each node's edge set. Nodes are still computed through their thunks, the same way as in a
+/// `Graph`. Propagation reports aren't recorded.
+pub struct FrozenGraph<T = f64, S = KeyHash> {
+    graph: Graph<T, S>,
+    // The position of each node (by slab index) in dependency order.
+    position: Vec<usize>,
+    // The node at each position.
+    order: Vec<AThunkID>,
+    // The positions of the nodes that depend on the node at position `p` are
+    // `supers[offsets[p]..offsets[p + 1]]`.
+    offsets: Vec<usize>,
+    supers: Vec<usize>,
+}
+
+impl<T: Scalar, S: MemoHasher> Graph<T, S> {
+    /// Fixes the graph's structure, in exchange for cheaper updates and recomputations. Thunks
+    /// must only compute nodes they already had edges to, since any new edges are ignored. The
+    /// exception is a node that had never been run, which adds its edges on its first run.
+    pub fn freeze(mut self) -> FrozenGraph<T, S> {
+        self.frozen = true;
+        let mut frozen = FrozenGraph {
+            graph: self,
+            position: Vec::new(),
+            order: Vec::new(),
+            offsets: Vec::new(),
+            supers: Vec::new(),
+        };
+        frozen.lay_out();
+        frozen
+    }
+}
+
+impl<T: Scalar, S: MemoHasher> FrozenGraph<T, S> {
+    // Lays the graph's nodes and their supers out in dependency order.
+    fn lay_out(&mut self) {
+        self.graph.frozen_edges_added.set(false);
+        let graph = &self.graph;
+        // Post-order over sub-computations, so each node comes after everything it depends on.
+        let mut order = Vec::with_capacity(graph.athunks.len());
+        let mut visited = vec![false; graph.athunks.capacity()];
+        for (i, _) in graph.athunks.iter() {
+            let mut stack = vec![(AThunkID(i), false)];
+            while let Some((id, subs_done)) = stack.pop() {
+                if subs_done {
+                    order.push(id);
+                    continue;
+                }
+                if visited[id.0] {
+                    continue;
+                }
+                visited[id.0] = true;
+                stack.push((id, true));
+                let athunk = &graph.athunks[id.0];
+                for s in athunk.sub_computations.borrow().iter() {
+                    if !visited[s.0] {
+                        stack.push((s, false));
+                    }
+                }
+            }
+        }
+
+        let mut position = vec![usize::MAX; graph.athunks.capacity()];
+        for (p, id) in order.iter().enumerate() {
+            position[id.0] = p;
+        }
+        let mut offsets = Vec::with_capacity(order.len() + 1);
+        let mut supers = Vec::new();
+        for id in order.iter() {
+            offsets.push(supers.len());
+            let athunk = &graph.athunks[id.0];
+            let mut s: Vec<usize> = athunk
+                .super_computations
+                .borrow()
+                .iter()
+                .map(|s| position[s.0])
+                .collect();
+            s.sort_unstable();
+            supers.extend(s);
+        }
+        offsets.push(supers.len());
+
+        self.position = position;
+        self.order = order;
+        self.offsets = offsets;
+        self.supers = supers;
+    }
+
+    pub fn compute(&self, id: impl Into<AThunkID>, args: &[f64]) -> Result<T, AdaptonError> {
+        self.graph.compute(id, args)
+    }
+
+    pub fn update_aref(&mut self, id: ARefID, val: T) {
+        if !self.graph.replace_aref(id, val) {
+            return;
+        }
+        if self.graph.frozen_edges_added.get() {
+            self.lay_out();
+        }
+        let mut stack = vec![self.position[id.id().0]];
+        while let Some(p) = stack.pop() {
+            if self.graph.athunks[self.order[p].0].invalidate(self.graph.cutoffs_in_use) {
+                self.graph.emit(GraphEvent::Dirtied(self.order[p]));
+                self.graph.record(|| TraceEvent::Dirty(self.order[p]));
+                stack.extend_from_slice(&self.supers[self.offsets[p]..self.offsets[p + 1]]);
+            }
+        }
+        self.graph.athunks[id.id().0].forfeit_unverified();
+    }
+
+    /// Gives back the graph, whose thunks can add and remove edges again.
+    pub fn thaw(mut self) -> Graph<T, S> {
+        self.graph.frozen = false;
+        self.graph
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Graph;
+
+    #[test]
+    fn freeze() {
+        let mut graph = Graph::new();
+
+        let r1 = graph.new_aref(1.0);
+        let r2 = graph.new_aref(2.0);
+        let a = graph.new_athunk(move |h| {
+            h.add_edge(r1);
+            h.add_edge(r2);
+            h.compute(r1, &[]).unwrap() + h.compute(r2, &[]).unwrap() * h.args[0]
+        });
+        let b = graph.new_athunk(move |h| {
+            h.add_edge(a);
+            h.compute(a, &[2.0]).unwrap() * 10.0
+        });
+        assert_eq!(Ok(50.0), graph.compute(b, &[]));
+        assert_eq!(Ok(3.0), graph.compute(a, &[1.0]));
+
+        let mut frozen = graph.freeze();
+        for i in 0..3 {
+            let v = i as f64;
+            frozen.update_aref(r2, v);
+            assert_eq!(Ok(10.0 + 20.0 * v), frozen.compute(b, &[]));
+            assert_eq!(Ok(1.0 + v), frozen.compute(a, &[1.0]));
+        }
+
+        let mut graph = frozen.thaw();
+        graph.update_aref(r1, 0.0);
+        assert_eq!(Ok(40.0), graph.compute(b, &[]));
+    }
+
+    #[test]
+    fn nodes_first_run_after_freezing_get_edges() {
+        let mut graph = Graph::new();
+        let r = graph.new_aref(1.0);
+        let double = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap() * 2.0
+        });
+
+        let mut frozen = graph.freeze();
+        assert_eq!(Ok(2.0), frozen.compute(double, &[]));
+        frozen.update_aref(r, 5.0);
+        assert_eq!(Ok(10.0), frozen.compute(double, &[]));
+    }
+}
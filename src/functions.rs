@@ -0,0 +1,72 @@
+//! Thunks that are written once and shared by any number of nodes.
+//!
+//! A node usually owns its thunk, along with anything the closure captured. For graphs made up
+//! of thousands of nodes computing the same formula on different inputs, that's thousands of
+//! copies of the same closure. A registered function is stored once instead, and each node made
+//! from it only holds on to its own parameters.
+
+use crate::{AThunkID, Graph, Handle, MemoHasher, Scalar};
+
+/// A function registered with `Graph::register_fn`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FnID(usize);
+
+pub(crate) type SharedFn<T, S> = Box<dyn Fn(&mut Handle<T, S>, &[f64]) -> T>;
+
+impl<T: Scalar, S: MemoHasher> Graph<T, S> {
+    /// Registers `f` under `name`, for nodes to be made from with `new_athunk_from`. Besides the
+    /// handle, `f` is given the parameters of whichever node it's computing. Registering another
+    /// function under the same name leaves nodes made from the old one as they are.
+    pub fn register_fn(
+        &mut self,
+        name: impl Into<String>,
+        f: impl Fn(&mut Handle<T, S>, &[f64]) -> T + 'static,
+    ) -> FnID {
+        let id = FnID(self.fns.len());
+        self.fns.push(Box::new(f));
+        self.fn_names.insert(name.into(), id);
+        id
+    }
+
+    pub fn fn_named(&self, name: &str) -> Option<FnID> {
+        self.fn_names.get(name).copied()
+    }
+
+    /// A node whose thunk is the registered function `f`, called with `params`.
+    pub fn new_athunk_from(&mut self, f: FnID, params: &[f64]) -> AThunkID {
+        // A closure that doesn't capture anything doesn't need an allocation.
+        let id = self.new_athunk(|_: &mut Handle<T, S>| T::default());
+        self.athunks[id.0].shared_fn = Some((f, params.into()));
+        id
+    }
+
+    pub(crate) fn call_fn(&self, f: FnID, handle: &mut Handle<T, S>, params: &[f64]) -> T {
+        (self.fns[f.0])(handle, params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Graph;
+
+    #[test]
+    fn register_fn() {
+        let mut graph = Graph::new();
+
+        let rate = graph.new_aref(0.2);
+        let vat = graph.register_fn("vat", move |h, params| {
+            h.add_edge(rate);
+            params[0] * h.compute(rate, &[]).unwrap()
+        });
+        assert_eq!(Some(vat), graph.fn_named("vat"));
+        let prices: Vec<_> = (1..=3)
+            .map(|i| graph.new_athunk_from(vat, &[i as f64 * 10.0]))
+            .collect();
+        for (i, &price) in prices.iter().enumerate() {
+            assert_eq!(Ok((i + 1) as f64 * 2.0), graph.compute(price, &[]));
+        }
+
+        graph.update_aref(rate, 0.1);
+        assert_eq!(Ok(3.0), graph.compute(prices[2], &[]));
+    }
+}
@@ -0,0 +1,115 @@
+//! The hasher behind memo tables and the graph's other internal maps.
+//!
+//! A graph's second type parameter is the hasher for its memo tables, which are keyed by the
+//! arguments nodes are computed with, and for every map it keeps of nodes. It defaults to
+//! `KeyHash`: with the `fast-hash` feature, which is on by default, that's the multiply-and-rotate
+//! hash used by rustc, since SipHash dominates the cost of lookups in large graphs. Without it,
+//! it's the standard library's default, which is slower but resistant to collisions being forced
+//! by whoever chooses the arguments. `Graph::<f64, RandomState>::default()` gets that resistance
+//! whichever way the feature is set.
+
+use std::hash::BuildHasher;
+
+/// What a graph's memo tables can be hashed with.
+pub trait MemoHasher: BuildHasher + Default + Clone + 'static {}
+
+impl<S: BuildHasher + Default + Clone + 'static> MemoHasher for S {}
+
+/// The hasher graphs use unless told otherwise.
+#[cfg(not(feature = "fast-hash"))]
+pub type KeyHash = std::collections::hash_map::RandomState;
+
+#[cfg(feature = "fast-hash")]
+pub use fast::{FxHasher, KeyHash};
+
+#[cfg(feature = "fast-hash")]
+mod fast {
+    use std::hash::{BuildHasherDefault, Hasher};
+
+    const SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+
+    /// The hasher graphs use unless told otherwise.
+    pub type KeyHash = BuildHasherDefault<FxHasher>;
+
+    /// The multiply-and-rotate hash used by rustc, which is fast but easy to force collisions
+    /// out of.
+    #[derive(Default)]
+    pub struct FxHasher {
+        hash: u64,
+    }
+
+    impl FxHasher {
+        fn add(&mut self, word: u64) {
+            self.hash = (self.hash.rotate_left(5) ^ word).wrapping_mul(SEED);
+        }
+    }
+
+    impl Hasher for FxHasher {
+        fn write(&mut self, bytes: &[u8]) {
+            for chunk in bytes.chunks(8) {
+                let mut word = [0; 8];
+                word[..chunk.len()].copy_from_slice(chunk);
+                self.add(u64::from_le_bytes(word));
+            }
+        }
+
+        fn write_u64(&mut self, i: u64) {
+            self.add(i);
+        }
+
+        fn write_usize(&mut self, i: usize) {
+            self.add(i as u64);
+        }
+
+        fn finish(&self) -> u64 {
+            self.hash
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::FxHasher;
+        use std::hash::{Hash, Hasher};
+
+        fn hash(value: impl Hash, mut hasher: impl Hasher) -> u64 {
+            value.hash(&mut hasher);
+            hasher.finish()
+        }
+
+        #[test]
+        fn fx_hasher() {
+            let mut top: Vec<u64> = (0..1000u64)
+                .map(|i| hash(&[i, 1][..], FxHasher::default()) >> 57)
+                .collect();
+            top.sort_unstable();
+            top.dedup();
+            // The top seven bits pick out candidates within a group, so they had better vary.
+            assert!(top.len() > 100);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Graph, Handle};
+    use std::collections::hash_map::RandomState;
+
+    #[test]
+    fn std_hashed_graph() {
+        let mut graph = Graph::<f64, RandomState>::default();
+
+        let r = graph.new_aref(2.0);
+        let square = graph.new_athunk(move |h: &mut Handle<f64, RandomState>| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap() * h.args[0]
+        });
+        assert_eq!(Ok(6.0), graph.compute(square, &[3.0]));
+        graph.assert_recomputes(&[(r.id(), 1), (square, 1)], |g| {
+            g.update_aref(r, 4.0);
+            assert_eq!(Ok(12.0), g.compute(square, &[3.0]));
+        });
+        graph.update_aref(r, 5.0);
+        assert!(graph.stabilize().unwrap().contains(&square));
+        assert_eq!(Ok(15.0), graph.compute(square, &[3.0]));
+    }
+}
@@ -0,0 +1,447 @@
+//! Nodes whose thunks are expensive enough to be worth running on other threads.
+//!
+//! A heavy node's thunk is a plain function of its arguments and its dependencies' values, so that
+//! it can be sent to a worker. `stabilize` starts heavy nodes on the worker pool as soon as their
+//! dependencies have been repaired, carries on repairing everything that doesn't depend on them,
+//! and only waits for a heavy node's value once something needs it. A heavy node that depends on
+//! other heavy nodes still being computed is started by the worker that finishes the last of them,
+//! so whole chains of heavy nodes are repaired in dependency order without the graph's thread
+//! waiting on any of them, and the pool's idle workers steal from the busy ones.
+
+use crate::pool::{panic_message, Outcome, Slot};
+use crate::{AThunkID, AdaptonError, Graph, MemoHasher, Scalar};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+
+pub use crate::pool::ThreadPool;
+
+type Launch<T> = Box<dyn Fn(&ThreadPool, Vec<f64>, Vec<Dep<T>>) -> Arc<Slot<T>>>;
+type HeavyFn = Arc<dyn Fn(&[f64], &[f64]) -> f64 + Send + Sync>;
+
+pub(crate) struct Heavy<T> {
+    pub(crate) deps: Vec<AThunkID>,
+    launch: Launch<T>,
+}
+
+// A dependency's value, or where it will end up if the pool is still computing it.
+enum Dep<T> {
+    Ready(T),
+    Later(Arc<Slot<T>>),
+}
+
+// A heavy node's values that are being computed by the pool, with the values they replace.
+pub(crate) type InFlight<T> = Vec<(Vec<f64>, T, Arc<Slot<T>>)>;
+
+// Every heavy node being computed by the pool, by its position in the repair order, so that
+// they're waited for in dependency order.
+pub(crate) type Launched<T> = BTreeMap<usize, (AThunkID, InFlight<T>)>;
+
+/// The heavy nodes one `stabilize` has sent to the pool. However it returns, the values that
+/// arrived but were never memoized are cleared, so that a later computation can't take them for
+/// its own, and whatever is still in flight is discarded: the jobs carry on, but only fill slots
+/// that nothing reads, and the nodes they were for are still stale.
+pub(crate) struct HeavyJobs<'a, T, S> {
+    graph: &'a Graph<T, S>,
+    pub(crate) launched: Launched<T>,
+}
+
+impl<'a, T, S> HeavyJobs<'a, T, S> {
+    pub(crate) fn new(graph: &'a Graph<T, S>) -> Self {
+        HeavyJobs {
+            graph,
+            launched: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T, S> Drop for HeavyJobs<'_, T, S> {
+    fn drop(&mut self) {
+        self.graph.heavy_results.borrow_mut().clear();
+    }
+}
+
+// Calls `f` with the dependencies' values once the last of them is there, or with the first
+// panic among them.
+fn gather(deps: Vec<Dep<f64>>, f: impl FnOnce(Outcome<Vec<f64>>) + Send + 'static) {
+    let mut vals = Vec::with_capacity(deps.len());
+    let mut later = Vec::new();
+    for (i, dep) in deps.into_iter().enumerate() {
+        match dep {
+            Dep::Ready(v) => vals.push(v),
+            Dep::Later(slot) => {
+                vals.push(f64::NAN);
+                later.push((i, slot));
+            }
+        }
+    }
+    if later.is_empty() {
+        return f(Ok(vals));
+    }
+    type Then = Box<dyn FnOnce(Outcome<Vec<f64>>) + Send>;
+    let f: Then = Box::new(f);
+    let state = Arc::new(Mutex::new((vals, later.len(), Some(f))));
+    for (i, slot) in later {
+        let state = state.clone();
+        slot.then(move |outcome| {
+            let ready = {
+                let mut state = state.lock().unwrap();
+                match outcome {
+                    Ok(v) => {
+                        state.0[i] = *v;
+                        state.1 -= 1;
+                        if state.1 == 0 {
+                            let vals = std::mem::take(&mut state.0);
+                            state.2.take().map(|f| (f, Ok(vals)))
+                        } else {
+                            None
+                        }
+                    }
+                    Err(message) => state.2.take().map(|f| (f, Err(message.clone()))),
+                }
+            };
+            if let Some((f, vals)) = ready {
+                f(vals);
+            }
+        });
+    }
+}
+
+impl<S: MemoHasher> Graph<f64, S> {
+    /// Like `new_athunk_with_deps`, but `f` is only given the node's arguments and its
+    /// dependencies' values, so that `stabilize` can run it on a worker thread.
+    pub fn new_heavy_athunk(
+        &mut self,
+        deps: &[AThunkID],
+        f: impl Fn(&[f64], &[f64]) -> f64 + Send + Sync + 'static,
+    ) -> AThunkID {
+        let f: HeavyFn = Arc::new(f);
+        let inline = f.clone();
+        let id = self.new_athunk_with_deps(deps, move |h, vals| {
+            let key = (h.id, h.args.iter().map(|a| a.to_bits()).collect());
+            match h.graph.heavy_results.borrow_mut().remove(&key) {
+                Some(v) => v,
+                None => inline(h.args, vals),
+            }
+        });
+        let launch = move |pool: &ThreadPool, args: Vec<f64>, deps: Vec<Dep<f64>>| {
+            let slot = Slot::new();
+            let (f, queues, filled) = (f.clone(), pool.queues().clone(), slot.clone());
+            // Run by whichever thread provides the last dependency, which is a worker if it was
+            // still being computed, so the job goes on that worker's own queue.
+            gather(deps, move |vals| match vals {
+                Ok(vals) => queues.spawn(move || {
+                    let outcome = panic::catch_unwind(AssertUnwindSafe(|| f(&args, &vals)));
+                    filled.fill(outcome.map_err(panic_message));
+                }),
+                Err(message) => filled.fill(Err(message)),
+            });
+            slot
+        };
+        self.heavy.insert(
+            id,
+            Heavy {
+                deps: deps.to_vec(),
+                launch: Box::new(launch),
+            },
+        );
+        id
+    }
+}
+
+impl<T: Scalar, S: MemoHasher> Graph<T, S> {
+    /// Starts `workers` threads for `stabilize` to run heavy nodes on. Without any, heavy nodes are
+    /// run like any other.
+    pub fn set_heavy_workers(&mut self, workers: usize) {
+        self.pool = if workers > 0 {
+            Some(Arc::new(ThreadPool::new(workers)))
+        } else {
+            None
+        };
+    }
+
+    /// Runs heavy nodes on `pool`, which can be shared with other graphs, say to keep the total
+    /// number of threads down when there are many of them.
+    #[cfg(feature = "threads-lite")]
+    pub fn set_thread_pool(&mut self, pool: Arc<ThreadPool>) {
+        self.pool = Some(pool);
+    }
+
+    pub fn heavy_workers(&self) -> usize {
+        self.pool.as_ref().map_or(0, |pool| pool.workers())
+    }
+
+    // Starts computing every stale entry of a heavy node on the pool, and returns whether it did,
+    // which it doesn't if there is no pool or the node isn't heavy. Dependencies that are heavy
+    // nodes the pool is still computing are left to it, and any others are computed first.
+    pub(crate) fn launch_heavy(
+        &self,
+        id: AThunkID,
+        entries: &[(Vec<f64>, T)],
+        position: &HashMap<AThunkID, usize, S>,
+        launched: &mut Launched<T>,
+        changed: &mut HashSet<AThunkID>,
+    ) -> Result<bool, AdaptonError> {
+        let (pool, heavy) = match (&self.pool, self.heavy.get(&id)) {
+            (Some(pool), Some(heavy)) => (pool, heavy),
+            _ => return Ok(false),
+        };
+        let mut deps = Vec::with_capacity(heavy.deps.len());
+        for &d in heavy.deps.iter() {
+            let slot = position
+                .get(&d)
+                .and_then(|p| launched.get(p))
+                .and_then(|(_, jobs)| jobs.iter().find(|(args, _, _)| args.is_empty()))
+                .map(|(_, _, slot)| slot.clone());
+            if let Some(slot) = slot {
+                deps.push(Dep::Later(slot));
+                continue;
+            }
+            // Dependencies come first in the repair order, so they're otherwise up to date once
+            // any of their own entries the pool is computing are in.
+            self.join_heavy(d, position, launched, changed)?;
+            deps.push(Dep::Ready(self.compute(d, &[])?));
+        }
+        let mut deps = Some(deps);
+        let in_flight = entries
+            .iter()
+            .enumerate()
+            .map(|(i, (args, old))| {
+                // Dependencies still being computed can only be shared by reading their slots.
+                let deps = if i + 1 == entries.len() {
+                    deps.take().unwrap()
+                } else {
+                    deps.as_ref().unwrap().iter().map(Dep::share).collect()
+                };
+                let slot = (heavy.launch)(pool, args.clone(), deps);
+                (args.clone(), *old, slot)
+            })
+            .collect();
+        launched.insert(position[&id], (id, in_flight));
+        Ok(true)
+    }
+
+    // Waits for a heavy node's values, if the pool is computing them, and memoizes them, noting
+    // whether they changed. Any heavy nodes it depends on that are in flight are waited for first,
+    // so that its thunk finds their values memoized.
+    pub(crate) fn join_heavy(
+        &self,
+        id: AThunkID,
+        position: &HashMap<AThunkID, usize, S>,
+        launched: &mut Launched<T>,
+        changed: &mut HashSet<AThunkID>,
+    ) -> Result<(), AdaptonError> {
+        let in_flight = match position.get(&id).and_then(|p| launched.remove(p)) {
+            Some((_, in_flight)) => in_flight,
+            None => return Ok(()),
+        };
+        for &d in self.heavy[&id].deps.iter() {
+            self.join_heavy(d, position, launched, changed)?;
+        }
+        for (args, old, slot) in in_flight {
+            // Every job fills its slot, since workers catch panics.
+            match slot.wait() {
+                Ok(v) => {
+                    let key = (id, args.iter().map(|a| a.to_bits()).collect());
+                    self.heavy_results.borrow_mut().insert(key, v);
+                }
+                Err(message) => return Err(AdaptonError::HeavyPanicked { id, message }),
+            }
+            if self.compute(id, &args)? != old {
+                changed.insert(id);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: Scalar> Dep<T> {
+    fn share(&self) -> Self {
+        match self {
+            Dep::Ready(v) => Dep::Ready(*v),
+            Dep::Later(slot) => Dep::Later(slot.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AdaptonError, Graph};
+    use std::collections::HashSet;
+    use std::sync::mpsc;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn heavy_nodes() {
+        let mut graph = Graph::new();
+        graph.set_heavy_workers(2);
+
+        let threads = Arc::new(Mutex::new(Vec::new()));
+        let r = graph.new_aref(1.0);
+        let heavy: Vec<_> = (0..4)
+            .map(|i| {
+                let threads = threads.clone();
+                graph.new_heavy_athunk(&[r.id()], move |args, vals| {
+                    threads.lock().unwrap().push(thread::current().id());
+                    vals[0] * i as f64 + args.iter().sum::<f64>()
+                })
+            })
+            .collect();
+        let total = graph.new_athunk_with_deps(&heavy[..2], |_, vals| vals.iter().sum());
+        for &h in heavy[2..].iter() {
+            graph.compute(h, &[1.0]).unwrap();
+        }
+        assert_eq!(Ok(1.0), graph.compute(total, &[]));
+        // Outside of `stabilize`, heavy nodes are run like any other.
+        assert!(threads
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|&t| t == thread::current().id()));
+
+        threads.lock().unwrap().clear();
+        graph.update_aref(r, 2.0);
+        let changed = graph.stabilize().unwrap();
+        // `heavy[0]` is always 0.
+        let expected: HashSet<_> = heavy[1..].iter().copied().chain([r.id(), total]).collect();
+        assert_eq!(expected, changed);
+        assert_eq!(Ok(2.0), graph.compute(total, &[]));
+        assert_eq!(Ok(7.0), graph.compute(heavy[3], &[1.0]));
+        let threads = threads.lock().unwrap();
+        assert_eq!(4, threads.len());
+        assert!(!threads.contains(&thread::current().id()));
+    }
+
+    #[test]
+    fn heavy_panics() {
+        let mut graph = Graph::new();
+        graph.set_heavy_workers(1);
+        let r = graph.new_aref(1.0);
+        let h = graph.new_heavy_athunk(&[r.id()], |_, vals| {
+            assert!(vals[0] < 2.0, "too big");
+            vals[0]
+        });
+        assert_eq!(Ok(1.0), graph.compute(h, &[]));
+
+        // More panics than there are workers, which all survive them.
+        for v in 2..5 {
+            graph.update_aref(r, v as f64);
+            assert_eq!(
+                Err(AdaptonError::HeavyPanicked {
+                    id: h,
+                    message: Some("too big".to_string())
+                }),
+                graph.stabilize()
+            );
+        }
+        graph.update_aref(r, 0.5);
+        assert!(graph.stabilize().unwrap().contains(&h));
+        assert_eq!(Ok(0.5), graph.compute(h, &[]));
+    }
+
+    #[test]
+    fn heavy_panics_leave_nothing_behind() {
+        let mut graph = Graph::new();
+        graph.set_heavy_workers(2);
+        let r = graph.new_aref(1.0);
+        let bad = graph.new_heavy_athunk(&[r.id()], |_, vals| {
+            assert!(vals[0] != 2.0, "two");
+            vals[0]
+        });
+        let good = graph.new_heavy_athunk(&[r.id()], |_, vals| vals[0] * 10.0);
+        let total = graph.new_athunk_with_deps(&[bad, good], |_, vals| vals[0] + vals[1]);
+        assert_eq!(Ok(11.0), graph.compute(total, &[]));
+
+        // `total` waits for `bad` first, so `good` is still in flight when `stabilize` gives up.
+        graph.update_aref(r, 2.0);
+        assert!(matches!(
+            graph.stabilize(),
+            Err(AdaptonError::HeavyPanicked { id, .. }) if id == bad
+        ));
+        assert!(graph.heavy_results.borrow().is_empty());
+        assert!(graph.dirty_nodes().contains(&good));
+        assert_eq!(Ok(20.0), graph.compute(good, &[]));
+
+        graph.update_aref(r, 3.0);
+        let changed = graph.stabilize().unwrap();
+        let expected: HashSet<_> = [r.id(), bad, good, total].iter().copied().collect();
+        assert_eq!(expected, changed);
+        assert_eq!(Ok(30.0), graph.compute(good, &[]));
+        assert_eq!(Ok(33.0), graph.compute(total, &[]));
+        assert!(graph.heavy_results.borrow().is_empty());
+    }
+
+    #[test]
+    fn heavy_chains() {
+        let mut graph = Graph::new();
+        graph.set_heavy_workers(2);
+        let (tx, rx) = mpsc::channel();
+        let rx = Mutex::new(rx);
+        let r = graph.new_aref(1.0);
+        // `a` can't finish until `n` has run, so `stabilize` mustn't wait for it, or for `b`,
+        // which depends on it, before moving on to `n`.
+        let a = graph.new_heavy_athunk(&[r.id()], move |_, vals| {
+            if vals[0] > 1.0 {
+                rx.lock()
+                    .unwrap()
+                    .recv_timeout(Duration::from_secs(5))
+                    .unwrap();
+            }
+            vals[0] * 2.0
+        });
+        let b = graph.new_heavy_athunk(&[a], |_, vals| vals[0] + 1.0);
+        let n = graph.new_athunk_with_deps(&[r.id()], move |_, vals| {
+            if vals[0] > 1.0 {
+                tx.send(()).unwrap();
+            }
+            -vals[0]
+        });
+        assert_eq!(Ok(3.0), graph.compute(b, &[]));
+        assert_eq!(Ok(-1.0), graph.compute(n, &[]));
+
+        graph.update_aref(r, 2.0);
+        let changed = graph.stabilize().unwrap();
+        let expected: HashSet<_> = [r.id(), a, b, n].iter().copied().collect();
+        assert_eq!(expected, changed);
+        assert_eq!(Ok(5.0), graph.compute(b, &[]));
+    }
+
+    #[cfg(feature = "threads-lite")]
+    #[test]
+    fn pools_have_a_worker() {
+        let pool = Arc::new(crate::ThreadPool::new(0));
+        assert_eq!(1, pool.workers());
+        let mut graph = Graph::new();
+        graph.set_thread_pool(pool);
+        let r = graph.new_aref(1.0);
+        let h = graph.new_heavy_athunk(&[r.id()], |_, vals| vals[0] + 1.0);
+        graph.compute(h, &[]).unwrap();
+        graph.update_aref(r, 2.0);
+        assert!(graph.stabilize().unwrap().contains(&h));
+        assert_eq!(Ok(3.0), graph.compute(h, &[]));
+    }
+
+    #[cfg(feature = "threads-lite")]
+    #[test]
+    fn shared_thread_pool() {
+        let pool = Arc::new(crate::ThreadPool::new(2));
+        let graphs: Vec<Graph> = (0..2)
+            .map(|_| {
+                let mut graph = Graph::new();
+                graph.set_thread_pool(pool.clone());
+                graph
+            })
+            .collect();
+        for mut graph in graphs {
+            assert_eq!(2, graph.heavy_workers());
+            let r = graph.new_aref(1.0);
+            let h = graph.new_heavy_athunk(&[r.id()], |_, vals| vals[0] * 3.0);
+            assert_eq!(Ok(3.0), graph.compute(h, &[]));
+            graph.update_aref(r, 2.0);
+            assert!(graph.stabilize().unwrap().contains(&h));
+            assert_eq!(Ok(6.0), graph.compute(h, &[]));
+        }
+    }
+}
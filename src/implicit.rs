@@ -0,0 +1,93 @@
+//! A thread-local default `Graph` with free functions, so small programs and doctests don't need
+//! to pass a `&mut Graph` around.
+//!
+//! ```
+//! use micro_adapton_rs::implicit::{aref, athunk, force, update_aref};
+//!
+//! let r = aref(2.0);
+//! let a = athunk(move |h| {
+//!     h.add_edge(r);
+//!     h.compute(r, &[]).unwrap() * 10.0
+//! });
+//!
+//! assert_eq!(Ok(20.0), force(a));
+//! update_aref(r, 3.0);
+//! assert_eq!(Ok(30.0), force(a));
+//! ```
+//!
+//! The graph is borrowed for the duration of every call, so thunks may call `force` but must not
+//! create nodes or update arefs through these functions while they are running.
+
+use crate::{ARefID, AThunkID, AdaptonError, Graph, IntoThunk};
+use std::cell::RefCell;
+
+thread_local! {
+    static GRAPH: RefCell<Graph> = RefCell::new(Graph::new());
+}
+
+/// Runs `f` with exclusive access to this thread's graph.
+///
+/// Panics if called from a thunk, naming the nodes being computed at the time.
+pub fn with_graph<R>(f: impl FnOnce(&mut Graph) -> R) -> R {
+    GRAPH.with(|g| match g.try_borrow_mut() {
+        Ok(mut g) => f(&mut g),
+        Err(_) => match g.try_borrow() {
+            Ok(g) => panic!(
+                "the implicit graph can't be changed while it's being computed, via {:?}",
+                g.demand_stack.borrow()
+            ),
+            Err(_) => panic!("with_graph can't be called from within with_graph"),
+        },
+    })
+}
+
+pub fn aref(val: f64) -> ARefID {
+    with_graph(|g| g.new_aref(val))
+}
+
+pub fn athunk(thunk: impl IntoThunk) -> AThunkID {
+    with_graph(|g| g.new_athunk(thunk))
+}
+
+pub fn update_aref(id: ARefID, val: f64) {
+    with_graph(|g| g.update_aref(id, val))
+}
+
+pub fn force(id: impl Into<AThunkID>) -> Result<f64, AdaptonError> {
+    force_with(id, &[])
+}
+
+/// Like `force`, with arguments. Fails with a `BorrowConflict` if called from within
+/// `with_graph`, which has the graph to itself.
+pub fn force_with(id: impl Into<AThunkID>, args: &[f64]) -> Result<f64, AdaptonError> {
+    let id = id.into();
+    GRAPH.with(|g| match g.try_borrow() {
+        Ok(g) => g.compute(id, args),
+        Err(_) => Err(AdaptonError::BorrowConflict {
+            node: None,
+            label: None,
+            during: "force the implicit graph",
+        }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{aref, force, with_graph};
+    use crate::AdaptonError;
+
+    #[test]
+    fn force_within_with_graph() {
+        let r = aref(1.0);
+        let err = with_graph(|_| force(r)).unwrap_err();
+        assert!(matches!(
+            err,
+            AdaptonError::BorrowConflict { node: None, .. }
+        ));
+        assert_eq!(
+            "can't force the implicit graph while it's already in use",
+            err.to_string()
+        );
+        assert_eq!(Ok(1.0), force(r));
+    }
+}
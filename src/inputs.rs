@@ -0,0 +1,382 @@
+//! Feeding arefs in bulk, from channels, and from structs.
+
+use crate::{ARefID, AThunkID, AdaptonError, Graph, MemoHasher, Scalar};
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+
+/// A value that can be held by an aref of a graph of `T`s, as every field of a struct deriving
+/// `AdaptonInputs` has to be. Any scalar is one for graphs of its own type, and `f32`, the
+/// integers and `bool` are for graphs of `f64`s. Integers of 64 bits or more are rounded to the
+/// nearest `f64`.
+///
+/// Other types, such as enums, can be made inputs by implementing this for them.
+pub trait ToInput<T = f64> {
+    fn to_input(&self) -> T;
+}
+
+impl<T: Scalar> ToInput<T> for T {
+    fn to_input(&self) -> T {
+        *self
+    }
+}
+
+macro_rules! to_input {
+    ($($ty:ty),*; $($lossy:ty),*) => {
+        $(
+            impl ToInput for $ty {
+                fn to_input(&self) -> f64 {
+                    f64::from(*self)
+                }
+            }
+        )*
+        $(
+            impl ToInput for $lossy {
+                fn to_input(&self) -> f64 {
+                    *self as f64
+                }
+            }
+        )*
+    };
+}
+
+to_input!(f32, i8, i16, i32, u8, u16, u32, bool; i64, u64, i128, u128, isize, usize);
+
+impl<T: Scalar, S: MemoHasher> Graph<T, S> {
+    /// Applies a batch of updates and stabilizes the graph, returning the nodes whose values
+    /// changed. Only the last update to each aref counts, and since dirtying stops at nodes that
+    /// are already dirty, each node is dirtied at most once however many of the updates it
+    /// depends on. For replaying a long series of updates, such as market data, a batch at a time.
+    pub fn apply_updates(
+        &mut self,
+        updates: impl IntoIterator<Item = (ARefID, T)>,
+    ) -> Result<HashSet<AThunkID>, AdaptonError> {
+        let mut order = Vec::new();
+        let mut last: HashMap<_, _, S> = HashMap::default();
+        for (id, val) in updates {
+            if last.insert(id, val).is_none() {
+                order.push(id);
+            }
+        }
+        for id in order {
+            self.update_aref(id, last[&id]);
+        }
+        self.stabilize()
+    }
+
+    /// Binds the aref to a channel, which a background thread drains as values are sent to it.
+    /// Bursts are coalesced, so only the last value to arrive before the next `poll_inputs` is
+    /// kept, and nothing is applied until then. The binding lasts until every sender has been
+    /// dropped, or the graph has.
+    pub fn bind_input(&mut self, id: ARefID, receiver: Receiver<T>)
+    where
+        T: Send,
+    {
+        let binding = {
+            let mut pending = self.inputs.pending.lock().unwrap();
+            pending.targets.push(Some(id));
+            pending.bound += 1;
+            pending.targets.len() - 1
+        };
+        let arrivals = Arc::downgrade(&self.inputs);
+        thread::spawn(move || drain(binding, receiver, arrivals));
+    }
+
+    /// Updates every bound aref that values have arrived for since the last call, with the last
+    /// of them, and returns how many were updated. Nothing is recomputed until the graph is next
+    /// computed or stabilized.
+    pub fn poll_inputs(&mut self) -> usize {
+        let updates = {
+            let mut pending = self.inputs.pending.lock().unwrap();
+            pending.index.clear();
+            std::mem::take(&mut pending.values)
+        };
+        for &(id, val) in updates.iter() {
+            self.update_aref(id, val);
+        }
+        updates.len()
+    }
+
+    /// How many channels are still bound, because some of their senders are still around.
+    pub fn bound_inputs(&self) -> usize {
+        self.inputs.pending.lock().unwrap().bound
+    }
+}
+
+// The values that have arrived on bound channels since the last `poll_inputs`.
+pub(crate) struct Arrivals<T> {
+    pending: Mutex<Pending<T>>,
+}
+
+struct Pending<T> {
+    // The aref each binding updates, by binding, or `None` if it's been removed.
+    targets: Vec<Option<ARefID>>,
+    // The last value for each aref, in the order their first values arrived.
+    values: Vec<(ARefID, T)>,
+    index: HashMap<ARefID, usize>,
+    bound: usize,
+}
+
+impl<T> Default for Arrivals<T> {
+    fn default() -> Self {
+        Arrivals {
+            pending: Mutex::new(Pending {
+                targets: Vec::new(),
+                values: Vec::new(),
+                index: HashMap::new(),
+                bound: 0,
+            }),
+        }
+    }
+}
+
+impl<T> Arrivals<T> {
+    // Changes the arefs that bindings update, and that values have arrived for, dropping any
+    // `f` returns `None` for.
+    pub(crate) fn remap(&self, mut f: impl FnMut(ARefID) -> Option<ARefID>) {
+        let mut pending = self.pending.lock().unwrap();
+        let pending = &mut *pending;
+        for target in pending.targets.iter_mut() {
+            *target = target.and_then(&mut f);
+        }
+        let values = std::mem::take(&mut pending.values);
+        pending.values = values
+            .into_iter()
+            .filter_map(|(id, val)| Some((f(id)?, val)))
+            .collect();
+        pending.index = pending
+            .values
+            .iter()
+            .enumerate()
+            .map(|(i, (id, _))| (*id, i))
+            .collect();
+    }
+}
+
+// Holds on to the graph's arrivals only weakly, so that dropping the graph stops it at the next
+// value.
+fn drain<T>(binding: usize, receiver: Receiver<T>, arrivals: Weak<Arrivals<T>>) {
+    while let Ok(val) = receiver.recv() {
+        let arrivals = match arrivals.upgrade() {
+            Some(arrivals) => arrivals,
+            None => return,
+        };
+        let mut pending = arrivals.pending.lock().unwrap();
+        let pending = &mut *pending;
+        let id = match pending.targets[binding] {
+            Some(id) => id,
+            None => continue,
+        };
+        match pending.index.get(&id) {
+            Some(&i) => pending.values[i].1 = val,
+            None => {
+                pending.index.insert(id, pending.values.len());
+                pending.values.push((id, val));
+            }
+        }
+    }
+    if let Some(arrivals) = arrivals.upgrade() {
+        arrivals.pending.lock().unwrap().bound -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Graph;
+    use std::cell::Cell;
+    use std::collections::HashSet;
+    use std::rc::Rc;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn bind_input() {
+        let mut graph = Graph::new();
+
+        let r = graph.new_aref(0.0);
+        let runs = Rc::new(Cell::new(0));
+        let counter = runs.clone();
+        let a = graph.new_athunk(move |h| {
+            counter.set(counter.get() + 1);
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap() * 2.0
+        });
+        assert_eq!(Ok(0.0), graph.compute(a, &[]));
+
+        let (tx, rx) = mpsc::channel();
+        graph.bind_input(r, rx);
+        assert_eq!(1, graph.bound_inputs());
+        assert_eq!(0, graph.poll_inputs());
+        thread::spawn(move || {
+            for i in 1..=10 {
+                tx.send(i as f64).unwrap();
+            }
+        })
+        .join()
+        .unwrap();
+
+        // The sender is gone, so the binding is too once everything it sent has been drained.
+        while graph.bound_inputs() > 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(1, graph.poll_inputs());
+        assert_eq!(Ok(20.0), graph.compute(a, &[]));
+        assert_eq!(2, runs.get());
+        assert_eq!(0, graph.poll_inputs());
+    }
+
+    #[test]
+    fn inputs_drain_in_the_background() {
+        let mut graph = Graph::new();
+        let refs: Vec<_> = (0..3).map(|_| graph.new_aref(0.0)).collect();
+        let mut senders = Vec::new();
+        for &r in refs.iter() {
+            let (tx, rx) = mpsc::channel();
+            graph.bind_input(r, rx);
+            senders.push(tx);
+        }
+        // Only the first two ever get anything, and the second is removed before it's polled.
+        for i in 1..=100 {
+            senders[0].send(i as f64).unwrap();
+            senders[1].send(-i as f64).unwrap();
+        }
+        while graph.inputs.pending.lock().unwrap().values.len() < 2 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        graph.retain(|id, _| id != refs[1].id());
+        let last = senders[0].clone();
+        drop(senders);
+        last.send(1000.0).unwrap();
+        drop(last);
+        while graph.bound_inputs() > 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(1, graph.poll_inputs());
+        assert_eq!(Ok(1000.0), graph.compute(refs[0], &[]));
+    }
+
+    #[test]
+    fn apply_updates() {
+        let mut graph = Graph::new();
+
+        let bid = graph.new_aref(1.0);
+        let ask = graph.new_aref(2.0);
+        let spread = graph.new_athunk_with_deps(&[bid.id(), ask.id()], |_, v| v[1] - v[0]);
+        let mid = graph.new_athunk_with_deps(&[bid.id(), ask.id()], |_, v| (v[0] + v[1]) / 2.0);
+        assert_eq!(Ok(1.0), graph.compute(spread, &[]));
+        assert_eq!(Ok(1.5), graph.compute(mid, &[]));
+
+        let ticks = vec![(bid, 1.5), (ask, 2.5), (bid, 2.0), (ask, 3.0)];
+        graph.assert_recomputes(
+            &[(bid.id(), 1), (ask.id(), 1), (spread, 1), (mid, 1)],
+            |g| {
+                let changed = g.apply_updates(ticks).unwrap();
+                let expected: HashSet<_> = vec![bid.id(), ask.id(), mid].into_iter().collect();
+                assert_eq!(expected, changed);
+            },
+        );
+        assert_eq!(Ok(2.5), graph.compute(mid, &[]));
+    }
+
+    #[cfg(feature = "derive")]
+    mod derived {
+        use crate::{AdaptonInputs, Graph, Handle, ToInput};
+
+        #[derive(AdaptonInputs)]
+        struct Flags {
+            enabled: bool,
+            level: i32,
+            scale: f32,
+        }
+
+        #[test]
+        fn struct_inputs() {
+            let mut graph = Graph::new();
+            let old = Flags {
+                enabled: false,
+                level: 2,
+                scale: 0.5,
+            };
+            let inputs = FlagsInputs::load(&mut graph, &old);
+            let level = graph
+                .new_athunk_with_deps(&[inputs.enabled.id(), inputs.level.id()], |_, v| {
+                    v[0] * v[1]
+                });
+            assert_eq!(Ok(0.0), graph.compute(level, &[]));
+
+            let new = Flags {
+                enabled: true,
+                ..old
+            };
+            assert_eq!(1, inputs.update_changed(&mut graph, &old, &new));
+            assert_eq!(Ok(2.0), graph.compute(level, &[]));
+            assert_eq!(0, inputs.update_changed(&mut graph, &new, &new));
+            assert_eq!(Ok(0.5), graph.compute(inputs.scale.id(), &[]));
+        }
+
+        // Neither `Copy` nor convertible with `From`.
+        enum Side {
+            Buy,
+            Sell,
+        }
+
+        impl ToInput for Side {
+            fn to_input(&self) -> f64 {
+                match self {
+                    Side::Buy => 1.0,
+                    Side::Sell => -1.0,
+                }
+            }
+        }
+
+        #[derive(AdaptonInputs)]
+        struct Order<Q> {
+            side: Side,
+            quantity: Q,
+        }
+
+        #[derive(AdaptonInputs)]
+        struct Quote<P>(P, P);
+
+        #[test]
+        fn generic_and_tuple_inputs() {
+            let mut graph = Graph::new();
+            let old = Order {
+                side: Side::Buy,
+                quantity: 3u8,
+            };
+            let order = OrderInputs::load(&mut graph, &old);
+            let deps = [order.side.id(), order.quantity.id()];
+            let position = graph.new_athunk_with_deps(&deps, |_, v| v[0] * v[1]);
+            assert_eq!(Ok(3.0), graph.compute(position, &[]));
+            let new = Order {
+                side: Side::Sell,
+                ..old
+            };
+            assert_eq!(1, order.update_changed(&mut graph, &old, &new));
+            assert_eq!(Ok(-3.0), graph.compute(position, &[]));
+
+            let quote = Quote(9.5, 10.5);
+            let inputs = QuoteInputs::load(&mut graph, &quote);
+            assert_eq!(Ok(10.5), graph.compute(inputs.1, &[]));
+            assert_eq!(
+                2,
+                inputs.update_changed(&mut graph, &quote, &Quote(9.0, 11.0))
+            );
+            assert_eq!(Ok(9.0), graph.compute(inputs.0, &[]));
+
+            // The arefs hold whatever the graph's scalar is.
+            let mut graph: Graph<f32> = Graph::default();
+            let inputs = QuoteInputs::load(&mut graph, &Quote(1.0, 2.0));
+            let (bid, ask) = (inputs.0, inputs.1);
+            let mid = graph.new_athunk(move |h: &mut Handle<f32>| {
+                h.add_edge(bid);
+                h.add_edge(ask);
+                (h.compute(bid, &[]).unwrap() + h.compute(ask, &[]).unwrap()) / 2.0
+            });
+            assert_eq!(Ok(1.5), graph.compute(mid, &[]));
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//! Interning argument vectors, so that the reads and sources nodes record can refer to arguments
+//! by a small ID rather than each holding and hashing a copy of them.
+
+use crate::hashing::KeyHash;
+use crate::memo::{self, ArgKey};
+use crate::{AThunkID, AdaptonError, Graph, MemoHasher, Scalar};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+/// An argument vector interned by `Graph::intern_args`. IDs are only meaningful to the graph that
+/// handed them out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ArgsId(u32);
+
+#[derive(Default)]
+pub(crate) struct Interner<S = KeyHash> {
+    ids: HashMap<ArgKey, ArgsId, S>,
+    keys: Vec<ArgKey>,
+}
+
+impl<S: MemoHasher> Interner<S> {
+    pub(crate) fn intern(&mut self, key: &[u64]) -> ArgsId {
+        if let Some(&id) = self.ids.get(key) {
+            return id;
+        }
+        let id =
+            ArgsId(u32::try_from(self.keys.len()).expect("too many interned argument vectors"));
+        let key = ArgKey::from_bits(key);
+        self.keys.push(key.clone());
+        self.ids.insert(key, id);
+        id
+    }
+
+    pub(crate) fn key(&self, id: ArgsId) -> &ArgKey {
+        &self.keys[id.0 as usize]
+    }
+}
+
+impl<T: Scalar, S: MemoHasher> Graph<T, S> {
+    /// The ID of `args`, which is the same every time the same arguments are interned. Every
+    /// argument vector a node reads with is interned too, and they're all kept for as long as the
+    /// graph is.
+    pub fn intern_args(&self, args: &[f64]) -> ArgsId {
+        memo::with_key(args, |key| self.interner.borrow_mut().intern(key))
+    }
+
+    /// The arguments `id` was interned from.
+    pub fn interned_args(&self, id: ArgsId) -> Vec<f64> {
+        self.interner
+            .borrow()
+            .key(id)
+            .iter()
+            .map(|&b| f64::from_bits(b))
+            .collect()
+    }
+
+    /// Like `compute`, with the arguments given by their interned ID.
+    pub fn compute_interned(
+        &self,
+        id: impl Into<AThunkID>,
+        args: ArgsId,
+    ) -> Result<T, AdaptonError> {
+        self.compute(id, &self.interned_args(args))
+    }
+
+    pub(crate) fn intern_key(&self, key: &[u64]) -> ArgsId {
+        self.interner.borrow_mut().intern(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Cutoff, Graph};
+
+    #[test]
+    fn interned_args() {
+        let mut graph = Graph::new();
+        let a = graph.intern_args(&[1.0, 2.0]);
+        let b = graph.intern_args(&[1.0, 2.0, 3.0, 4.0]);
+        assert_ne!(a, b);
+        assert_eq!(a, graph.intern_args(&[1.0, 2.0]));
+        assert_eq!(vec![1.0, 2.0, 3.0, 4.0], graph.interned_args(b));
+
+        // Nodes record what they read by interned ID.
+        graph.set_cutoff(Cutoff::Exact);
+        let r = graph.new_aref(1.0);
+        let scaled = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap() * h.args.iter().sum::<f64>()
+        });
+        let readers: Vec<_> = (0..10)
+            .map(|_| {
+                graph.new_athunk(move |h| {
+                    h.add_edge(scaled);
+                    h.compute(scaled, &[1.0, 2.0]).unwrap()
+                })
+            })
+            .collect();
+        for &reader in readers.iter() {
+            assert_eq!(Ok(3.0), graph.compute(reader, &[]));
+        }
+        assert_eq!(Ok(3.0), graph.compute_interned(scaled, a));
+        assert_eq!(3, graph.interner.borrow().keys.len());
+    }
+}
@@ -0,0 +1,495 @@
+//! Loading graphs from JSON specs, so that a pipeline can be reconfigured without recompiling.
+//!
+//! A spec is an object with two optional members. `inputs` maps labels to the initial values of
+//! arefs, which are also registered under their labels like `aref_entry` does. `nodes` maps labels
+//! to formulas, as taken by `new_formula`, which can name any input and any node that comes before
+//! them in the spec:
+//!
+//! ```json
+//! {
+//!     "inputs": { "price": 10, "quantity": 3, "discount": 0.1 },
+//!     "nodes": {
+//!         "subtotal": "price * quantity",
+//!         "total": "subtotal * (1 - discount)"
+//!     }
+//! }
+//! ```
+//!
+//! Labels are shared between inputs and nodes. JSON is parsed here rather than with a dependency,
+//! since specs are small and only need plain objects, strings and numbers. The parser recurses
+//! into arrays and objects, so they can't nest more than `MAX_SPEC_DEPTH` deep.
+
+use crate::{ARefID, AThunkID, Graph, SpecError};
+use std::collections::HashMap;
+
+/// How deeply the arrays and objects of a spec can nest.
+pub const MAX_SPEC_DEPTH: usize = 128;
+
+/// The IDs of the inputs and nodes in a spec, by label.
+#[derive(Debug, Clone, Default)]
+pub struct Labels {
+    inputs: HashMap<String, ARefID>,
+    nodes: HashMap<String, AThunkID>,
+}
+
+impl Labels {
+    pub fn input(&self, label: &str) -> Option<ARefID> {
+        self.inputs.get(label).copied()
+    }
+
+    pub fn node(&self, label: &str) -> Option<AThunkID> {
+        self.nodes.get(label).copied()
+    }
+
+    /// The ID of the input or node with `label`.
+    pub fn get(&self, label: &str) -> Option<AThunkID> {
+        self.input(label)
+            .map(ARefID::id)
+            .or_else(|| self.node(label))
+    }
+}
+
+impl Graph {
+    /// A graph built from a JSON spec, as described in the module documentation.
+    ///
+    /// ```
+    /// use micro_adapton_rs::Graph;
+    ///
+    /// let spec = r#"{ "inputs": { "a": 3, "b": 5 }, "nodes": { "mean": "(a + b) / 2" } }"#;
+    /// let (mut graph, labels) = Graph::from_json(spec).unwrap();
+    /// let mean = labels.node("mean").unwrap();
+    /// assert_eq!(Ok(4.0), graph.compute(mean, &[]));
+    /// graph.update_aref(labels.input("b").unwrap(), 7.0);
+    /// assert_eq!(Ok(5.0), graph.compute(mean, &[]));
+    /// ```
+    pub fn from_json(spec: &str) -> Result<(Graph, Labels), SpecError> {
+        let mut parser = Parser {
+            src: spec.as_bytes(),
+            pos: 0,
+            depth: 0,
+        };
+        let root = parser.value()?;
+        if parser.peek().is_some() {
+            return Err(parser.error("the end of the spec"));
+        }
+
+        let mut graph = Graph::new();
+        let mut labels = Labels::default();
+        let mut bindings: Vec<(String, AThunkID)> = Vec::new();
+        for (key, value) in schema_object(root, "the spec")? {
+            match key.as_str() {
+                "inputs" => {
+                    for (label, value) in schema_object(value, "inputs")? {
+                        let val = match value {
+                            Json::Num(n) => n,
+                            _ => return Err(schema(format!("inputs.{}", label), "a number")),
+                        };
+                        check_unique(&bindings, &label)?;
+                        let aref = graph.aref_entry(label.as_str()).or_insert(val);
+                        bindings.push((label.clone(), aref.id()));
+                        labels.inputs.insert(label, aref);
+                    }
+                }
+                "nodes" => {
+                    for (label, value) in schema_object(value, "nodes")? {
+                        let formula = match value {
+                            Json::Str(s) => s,
+                            _ => {
+                                return Err(schema(format!("nodes.{}", label), "a formula string"))
+                            }
+                        };
+                        check_unique(&bindings, &label)?;
+                        let bound: Vec<(&str, AThunkID)> =
+                            bindings.iter().map(|(l, id)| (l.as_str(), *id)).collect();
+                        let id = graph.new_formula(&formula, &bound).map_err(|error| {
+                            SpecError::Formula {
+                                node: label.clone(),
+                                error,
+                            }
+                        })?;
+                        bindings.push((label.clone(), id));
+                        labels.nodes.insert(label, id);
+                    }
+                }
+                _ => return Err(schema(key, "absent, only inputs and nodes are allowed")),
+            }
+        }
+        Ok((graph, labels))
+    }
+}
+
+fn schema(path: String, expected: &'static str) -> SpecError {
+    SpecError::Schema { path, expected }
+}
+
+fn schema_object(value: Json, path: &str) -> Result<Vec<(String, Json)>, SpecError> {
+    match value {
+        Json::Object(members) => Ok(members),
+        _ => Err(schema(path.to_string(), "an object")),
+    }
+}
+
+fn check_unique(bindings: &[(String, AThunkID)], label: &str) -> Result<(), SpecError> {
+    if bindings.iter().any(|(l, _)| l == label) {
+        return Err(SpecError::DuplicateLabel(label.to_string()));
+    }
+    Ok(())
+}
+
+// A parsed JSON value. Objects keep their members in order, since nodes can only name what comes
+// before them.
+enum Json {
+    Null,
+    Bool,
+    Num(f64),
+    Str(String),
+    Array,
+    Object(Vec<(String, Json)>),
+}
+
+struct Parser<'a> {
+    src: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    // Skips whitespace and returns the next byte, if there is one.
+    fn peek(&mut self) -> Option<u8> {
+        while self.src.get(self.pos).is_some_and(u8::is_ascii_whitespace) {
+            self.pos += 1;
+        }
+        self.src.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        let found = self.peek() == Some(c);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, c: u8, expected: &'static str) -> Result<(), SpecError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(expected))
+        }
+    }
+
+    fn error(&mut self, expected: &'static str) -> SpecError {
+        self.peek();
+        SpecError::Json {
+            position: self.pos,
+            expected,
+        }
+    }
+
+    // Like `error`, but for the middle of a token, where whitespace isn't skipped.
+    fn error_here(&self, expected: &'static str) -> SpecError {
+        SpecError::Json {
+            position: self.pos,
+            expected,
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, SpecError> {
+        let c = self.peek();
+        if matches!(c, Some(b'{' | b'[')) {
+            if self.depth == MAX_SPEC_DEPTH {
+                return Err(SpecError::TooDeep { position: self.pos });
+            }
+            self.depth += 1;
+            let value = self.container();
+            self.depth -= 1;
+            return value;
+        }
+        match c {
+            Some(b'"') => Ok(Json::Str(self.string()?)),
+            Some(c) if c == b'-' || c.is_ascii_digit() => self.number(),
+            _ => {
+                for (word, value) in [
+                    ("null", Json::Null),
+                    ("true", Json::Bool),
+                    ("false", Json::Bool),
+                ] {
+                    if self.src[self.pos..].starts_with(word.as_bytes()) {
+                        self.pos += word.len();
+                        return Ok(value);
+                    }
+                }
+                Err(self.error("a value"))
+            }
+        }
+    }
+
+    // Parses an object or array, whose opening bracket is next.
+    fn container(&mut self) -> Result<Json, SpecError> {
+        match self.peek() {
+            Some(b'{') => {
+                self.pos += 1;
+                let mut members = Vec::new();
+                if !self.eat(b'}') {
+                    loop {
+                        if self.peek() != Some(b'"') {
+                            return Err(self.error("a string"));
+                        }
+                        let key = self.string()?;
+                        self.expect(b':', "`:`")?;
+                        members.push((key, self.value()?));
+                        if self.eat(b'}') {
+                            break;
+                        }
+                        self.expect(b',', "`,` or `}`")?;
+                    }
+                }
+                Ok(Json::Object(members))
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                if !self.eat(b']') {
+                    loop {
+                        self.value()?;
+                        if self.eat(b']') {
+                            break;
+                        }
+                        self.expect(b',', "`,` or `]`")?;
+                    }
+                }
+                Ok(Json::Array)
+            }
+            _ => unreachable!("only called on an opening bracket"),
+        }
+    }
+
+    // Parses a number, which has to be written the way JSON allows: no leading zeros or plus
+    // sign, and digits on both sides of a decimal point.
+    fn number(&mut self) -> Result<Json, SpecError> {
+        let start = self.pos;
+        self.eat_byte(b'-');
+        if !self.eat_byte(b'0') && self.digits() == 0 {
+            return Err(self.error_here("a digit"));
+        }
+        if self.eat_byte(b'.') && self.digits() == 0 {
+            return Err(self.error_here("a digit"));
+        }
+        if self.eat_byte(b'e') || self.eat_byte(b'E') {
+            if !self.eat_byte(b'+') {
+                self.eat_byte(b'-');
+            }
+            if self.digits() == 0 {
+                return Err(self.error_here("a digit"));
+            }
+        }
+        // Everything scanned is ASCII, and valid as a float.
+        let n = std::str::from_utf8(&self.src[start..self.pos])
+            .unwrap()
+            .parse()
+            .unwrap();
+        Ok(Json::Num(n))
+    }
+
+    // Like `eat`, but without skipping whitespace first.
+    fn eat_byte(&mut self, c: u8) -> bool {
+        let found = self.src.get(self.pos) == Some(&c);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    // Skips the digits that are next, and returns how many there were.
+    fn digits(&mut self) -> usize {
+        let start = self.pos;
+        while self.src.get(self.pos).is_some_and(u8::is_ascii_digit) {
+            self.pos += 1;
+        }
+        self.pos - start
+    }
+
+    // Parses a string, whose opening quote is next.
+    fn string(&mut self) -> Result<String, SpecError> {
+        self.pos += 1;
+        let mut bytes = Vec::new();
+        loop {
+            let c = match self.src.get(self.pos) {
+                Some(&c) => c,
+                None => return Err(self.error("`\"`")),
+            };
+            self.pos += 1;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    let escape = self.src.get(self.pos).copied();
+                    self.pos += 1;
+                    let c = match escape {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => self.unicode_escape()?,
+                        _ => {
+                            self.pos -= 1;
+                            return Err(self.error("an escape"));
+                        }
+                    };
+                    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                c if c < 0x20 => {
+                    self.pos -= 1;
+                    return Err(self.error_here("an escape instead of a control character"));
+                }
+                _ => bytes.push(c),
+            }
+        }
+        // The spec is a `str`, and strings only end on a quote, so they're valid UTF-8.
+        Ok(String::from_utf8(bytes).unwrap())
+    }
+
+    // Parses the rest of a `\u` escape, and the low surrogate after it if it's a high one.
+    fn unicode_escape(&mut self) -> Result<char, SpecError> {
+        let invalid = SpecError::Json {
+            position: self.pos - 2,
+            expected: "a valid unicode escape",
+        };
+        let high = self.hex4().ok_or_else(|| invalid.clone())?;
+        let code = if (0xd800..0xdc00).contains(&high) {
+            if !self.src[self.pos..].starts_with(b"\\u") {
+                return Err(invalid);
+            }
+            self.pos += 2;
+            match self.hex4() {
+                Some(low) if (0xdc00..0xe000).contains(&low) => {
+                    0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+                }
+                _ => return Err(invalid),
+            }
+        } else {
+            high
+        };
+        std::char::from_u32(code).ok_or(invalid)
+    }
+
+    fn hex4(&mut self) -> Option<u32> {
+        let digits = self.src.get(self.pos..self.pos + 4)?;
+        let code = u32::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+        self.pos += 4;
+        Some(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MAX_SPEC_DEPTH;
+    use crate::{FormulaError, Graph, SpecError};
+
+    #[test]
+    fn from_json() {
+        let spec = r#"
+            {
+                "inputs": { "price": 10, "quantity": 3, "discount": 1e-1, "café 😀": -2 },
+                "nodes": {
+                    "subtotal": "price * quantity",
+                    "total": "subtotal * (1 - discount)"
+                },
+                "inputs": {}
+            }
+        "#;
+        let (mut graph, labels) = Graph::from_json(spec).unwrap();
+        let total = labels.node("total").unwrap();
+        assert_eq!(Ok(27.0), graph.compute(total, &[]));
+        graph.update_aref(labels.input("price").unwrap(), 20.0);
+        assert_eq!(Ok(54.0), graph.compute(total, &[]));
+        assert_eq!(labels.input("quantity"), graph.aref_named("quantity"));
+        let cafe = labels.get("caf\u{e9} \u{1f600}").unwrap();
+        assert_eq!(Ok(-2.0), graph.compute(cafe, &[]));
+        assert_eq!(None, labels.get("missing"));
+
+        let error = |spec| Graph::from_json(spec).map(|_| ()).unwrap_err();
+        assert_eq!(
+            SpecError::Json {
+                position: 14,
+                expected: "`,` or `}`"
+            },
+            error(r#"{"inputs": {} "nodes": {}}"#)
+        );
+        assert_eq!(
+            SpecError::Schema {
+                path: "inputs.a".to_string(),
+                expected: "a number"
+            },
+            error(r#"{"inputs": {"a": "1"}}"#)
+        );
+        assert_eq!(
+            SpecError::DuplicateLabel("a".to_string()),
+            error(r#"{"inputs": {"a": 1}, "nodes": {"a": "1"}}"#)
+        );
+        // Nodes can't name nodes after them.
+        assert_eq!(
+            SpecError::Formula {
+                node: "a".to_string(),
+                error: FormulaError::UnknownName {
+                    position: 0,
+                    name: "b".to_string()
+                }
+            },
+            error(r#"{"nodes": {"a": "b", "b": "1"}}"#)
+        );
+    }
+
+    #[test]
+    fn invalid_json() {
+        let error = |spec: &str| Graph::from_json(spec).map(|_| ()).unwrap_err();
+        let json = |position, expected| SpecError::Json { position, expected };
+
+        // Leading zeros end the number, so whatever follows them is out of place.
+        let out_of_place = "`,` or `}`";
+        for (spec, position, expected) in [
+            (r#"{"inputs": {"a": 01}}"#, 18, out_of_place),
+            (r#"{"inputs": {"a": -01}}"#, 19, out_of_place),
+            (r#"{"inputs": {"a": 1.}}"#, 19, "a digit"),
+            (r#"{"inputs": {"a": 1.e5}}"#, 19, "a digit"),
+            (r#"{"inputs": {"a": 1e}}"#, 19, "a digit"),
+            (r#"{"inputs": {"a": 1e+}}"#, 20, "a digit"),
+            (r#"{"inputs": {"a": -}}"#, 18, "a digit"),
+            (r#"{"inputs": {"a": - 1}}"#, 18, "a digit"),
+        ] {
+            assert_eq!(json(position, expected), error(spec), "{}", spec);
+        }
+        assert_eq!(json(17, "a value"), error(r#"{"inputs": {"a": +1}}"#));
+        assert_eq!(json(17, "a value"), error(r#"{"inputs": {"a": .5}}"#));
+        assert_eq!(json(0, "a value"), error("tru"));
+        assert_eq!(
+            json(13, "an escape instead of a control character"),
+            error("{\"nodes\": {\"a\n\": \"1\"}}")
+        );
+        assert_eq!(json(12, "a string"), error(r#"{"inputs": {1: 2}}"#));
+        assert_eq!(json(3, "the end of the spec"), error("{} {}"));
+
+        let nested = |depth| "[".repeat(depth) + &"]".repeat(depth);
+        assert_eq!(
+            SpecError::Schema {
+                path: "the spec".to_string(),
+                expected: "an object"
+            },
+            error(&nested(MAX_SPEC_DEPTH))
+        );
+        assert_eq!(
+            SpecError::TooDeep {
+                position: MAX_SPEC_DEPTH
+            },
+            error(&nested(MAX_SPEC_DEPTH + 1))
+        );
+        assert_eq!(
+            SpecError::TooDeep {
+                position: MAX_SPEC_DEPTH
+            },
+            error(&"[".repeat(100_000))
+        );
+    }
+}
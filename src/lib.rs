@@ -1,193 +1,2716 @@
+// So that the code `AdaptonInputs` generates, which names this crate, compiles in here too.
+extern crate self as micro_adapton_rs;
+
 use slab::Slab;
-use std::cell::RefCell;
-use std::collections::{HashMap, HashSet};
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use combinators::Op;
+use edges::EdgeSet;
+use functions::SharedFn;
+use heavy::{Heavy, HeavyJobs};
+use interner::Interner;
+use memo::{ArgKey, Memo};
+use progress::Progress;
+use shared::ContentKey;
+
+// First, so that its macro can be used by every other module.
+#[macro_use]
+mod lifecycle;
+
+pub mod algorithms;
+mod analysis;
+mod call;
+mod capacity;
+mod collections;
+mod combinators;
+pub mod compat;
+mod config;
+mod dirtying;
+mod edges;
+mod error;
+mod events;
+mod formula;
+mod frozen;
+mod functions;
+mod hashing;
+mod heavy;
+pub mod implicit;
+mod inputs;
+mod interner;
+mod json;
+mod loader;
+#[cfg(feature = "ndarray")]
+mod matrix;
+mod memo;
+mod memory;
+mod nodes;
+mod partition;
+mod pending;
+mod poison;
+mod pool;
+mod prefetch;
+mod profile;
+mod progress;
+mod provenance;
+mod recording;
+mod report;
+mod resources;
+mod scalar;
+mod shared;
+mod stabilizer;
+mod stochastic;
+pub mod testing;
+mod text;
+
+pub use analysis::EdgeAnalysis;
+pub use call::{CallCtx, CancelToken, Extensions};
+pub use collections::{AFilter, AList, AVec};
+pub use config::{GraphConfig, MemoPolicy, NodeConfig};
+pub use error::{AdaptonError, ArityError, FormulaError, SpecError};
+pub use events::GraphEvent;
+pub use formula::MAX_FORMULA_DEPTH;
+pub use frozen::FrozenGraph;
+pub use functions::FnID;
+#[cfg(feature = "fast-hash")]
+pub use hashing::FxHasher;
+pub use hashing::{KeyHash, MemoHasher};
+#[cfg(feature = "threads-lite")]
+pub use heavy::ThreadPool;
+pub use inputs::ToInput;
+pub use interner::ArgsId;
+pub use json::{Labels, MAX_SPEC_DEPTH};
+pub use loader::Loader;
+#[cfg(feature = "ndarray")]
+pub use matrix::AMatrix;
+/// Derives, for a struct, a mirror of it named after it with an `Inputs` suffix, that holds an
+/// aref for each field, so that application state can be kept in sync with a graph without
+/// writing out every field by hand. Named, tuple and generic structs are supported, and a field
+/// can be of any type that implements `ToInput` for the graph's scalar.
+///
+/// ```
+/// use micro_adapton_rs::{AdaptonInputs, Graph};
+///
+/// #[derive(AdaptonInputs)]
+/// pub struct Order {
+///     pub price: f64,
+///     pub quantity: u32,
+/// }
+///
+/// let mut graph = Graph::new();
+/// let order = Order { price: 10.0, quantity: 3 };
+/// let inputs = OrderInputs::load(&mut graph, &order);
+/// let deps = [inputs.price.id(), inputs.quantity.id()];
+/// let total = graph.new_athunk_with_deps(&deps, |_, vals| vals[0] * vals[1]);
+/// assert_eq!(Ok(30.0), graph.compute(total, &[]));
+///
+/// let new = Order { quantity: 4, ..order };
+/// assert_eq!(1, inputs.update_changed(&mut graph, &order, &new));
+/// assert_eq!(Ok(40.0), graph.compute(total, &[]));
+/// ```
+#[cfg(feature = "derive")]
+pub use micro_adapton_rs_derive::AdaptonInputs;
+pub use nodes::{IdRemap, NodeInfo};
+pub use partition::Partition;
+pub use recording::TraceEvent;
+pub use report::{ComputeReport, PropagationReport};
+pub use scalar::Scalar;
+pub use stabilizer::Stabilizer;
+pub use stochastic::Rng;
+pub use text::AText;
 
 // If anyone is reading this in the future, this is my first time using RefCell and my first time
 // working with Adaption so there could be some large flaws in here. :)
 
-#[derive(Default)]
-pub struct Graph {
-    athunks: Slab<RefCell<AThunk>>,
+pub struct Graph<T = f64, S = KeyHash> {
+    athunks: Slab<AThunk<T, S>>,
+    // How many edges and memo entries to make room for in each new node.
+    capacity_hints: Option<(usize, usize)>,
+    max_reruns: usize,
+    strict_tracking: bool,
+    named_arefs: HashMap<String, ARefID>,
+    propagation_reports: bool,
+    last_propagation_report: Option<PropagationReport>,
+    cutoff: Cutoff,
+    // Set once any cutoff other than `Cutoff::Never` has been configured, since there's no point
+    // paying for the bookkeeping before then.
+    cutoffs_in_use: bool,
+    observers: RefCell<Vec<Observer<T>>>,
+    // The node whose observer is being called.
+    notifying: Cell<Option<AThunkID>>,
+    max_depth: Option<usize>,
+    max_nodes: Option<usize>,
+    // The nodes currently being run, outermost first.
+    demand_stack: RefCell<Vec<AThunkID>>,
+    // How many (node, arguments) pairs have a value, whether it's up to date or stale.
+    instances: Cell<usize>,
+    // Set while the graph is owned by a `FrozenGraph`, and its edges can't change.
+    frozen: bool,
+    // Set when a node that was first run after the graph was frozen added edges, which the frozen
+    // graph's layout doesn't have yet.
+    frozen_edges_added: Cell<bool>,
+    provenance_tracking: bool,
+    seed: u64,
+    // An aref that stochastic nodes depend on, which changes with the seed.
+    seed_node: Option<ARefID>,
+    // How many stochastic nodes have been created, which numbers each one's random stream.
+    stochastic_nodes: u64,
+    // Set during `compute_timeout`, to when it started, when it has to finish by, and how many
+    // thunks had been run when it started.
+    deadline: Cell<Option<(Instant, Instant, usize)>>,
+    // How many times a thunk has been run, and a node has been computed, ever.
+    runs: Cell<usize>,
+    visits: Cell<usize>,
+    shared_results: RefCell<HashMap<ContentKey, T, S>>,
+    incremental: bool,
+    keyed_nodes: HashMap<u64, AThunkID, S>,
+    env: Option<Box<dyn Any>>,
+    resources: Extensions,
+    interner: RefCell<Interner<S>>,
+    revision: u64,
+    // Every compaction so far, for translating the IDs captured by thunks created before them.
+    remaps: Vec<IdRemap>,
+    heavy: HashMap<AThunkID, Heavy<T>, S>,
+    // Values computed by the heavy node pool, waiting to be picked up by their nodes' thunks.
+    heavy_results: RefCell<HashMap<(AThunkID, Vec<u64>), T, S>>,
+    pool: Option<Arc<heavy::ThreadPool>>,
+    progress: Option<Progress>,
+    dirtying_budget: Option<usize>,
+    memo_policy: MemoPolicy,
+    // Nodes that an update ran out of budget before dirtying.
+    undirtied: RefCell<BTreeSet<AThunkID>>,
+    // Nodes that don't depend on any of `undirtied`, as far as `dirty_demanded` has looked.
+    settled: RefCell<HashSet<AThunkID, S>>,
+    // How long the thunks run by the thunk currently running have taken, in total.
+    nested_time: Cell<Duration>,
+    // How long thunks have taken by themselves, by the stack of thunks they were run under, while
+    // profiling.
+    profile: Option<RefCell<HashMap<Vec<AThunkID>, Duration, S>>>,
+    prefetches: RefCell<VecDeque<(AThunkID, Vec<f64>)>>,
+    // Set while recording, to everything recorded so far.
+    recording: Option<RefCell<Vec<TraceEvent>>>,
+    fns: Vec<SharedFn<T, S>>,
+    fn_names: HashMap<String, FnID>,
+    poison_propagation: bool,
+    event_senders: RefCell<Vec<Sender<GraphEvent>>>,
+    // What has arrived on the channels bound with `bind_input`, drained by their own threads.
+    inputs: Arc<inputs::Arrivals<T>>,
+    // What the nodes removed by `clear` had allocated, for the nodes created after.
+    spares: Vec<nodes::Spare<T, S>>,
+}
+
+pub type Thunk<T = f64, S = KeyHash> = Box<dyn Fn(&mut Handle<T, S>) -> T>;
+
+/// Anything that can be used as a thunk, so that closures can be passed without boxing them first.
+/// A `Thunk` still works for when the closure's type has been erased.
+pub trait IntoThunk<T = f64, S = KeyHash>: Fn(&mut Handle<T, S>) -> T + Sized + 'static {
+    fn into_thunk(self) -> Thunk<T, S> {
+        Box::new(self)
+    }
+}
+
+impl<T, S, F: Fn(&mut Handle<T, S>) -> T + 'static> IntoThunk<T, S> for F {}
+
+/// Decides when a node that was re-executed counts as unchanged. Nodes that only read unchanged
+/// values are then marked clean again without being re-executed themselves, which stops
+/// invalidation from cascading any further.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cutoff {
+    /// Any re-execution counts as a change. This is the default.
+    Never,
+    /// Only a different value counts as a change.
+    Exact,
+    /// Only a value at least `eps` away from the old one counts as a change, so that noise from
+    /// floating point doesn't invalidate numerically stable results.
+    Tolerance(f64),
 }
 
-pub type Thunk = Box<dyn Fn(&mut Handle) -> f64>;
+/// How many times a node is re-run when its own sub-computations keep invalidating it, unless
+/// changed with `Graph::set_max_reruns`.
+pub const DEFAULT_MAX_RERUNS: usize = 8;
 
 impl Graph {
     pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Graphs of scalars other than `f64`, or with a memo table hasher other than `KeyHash`, are
+/// created with `Graph::default`.
+impl<T: Scalar, S: MemoHasher> Default for Graph<T, S> {
+    fn default() -> Self {
         Self {
             athunks: Slab::new(),
+            capacity_hints: None,
+            max_reruns: DEFAULT_MAX_RERUNS,
+            strict_tracking: false,
+            named_arefs: HashMap::new(),
+            propagation_reports: false,
+            last_propagation_report: None,
+            cutoff: Cutoff::Never,
+            cutoffs_in_use: false,
+            observers: RefCell::new(Vec::new()),
+            notifying: Cell::new(None),
+            max_depth: None,
+            max_nodes: None,
+            demand_stack: RefCell::new(Vec::new()),
+            instances: Cell::new(0),
+            frozen: false,
+            frozen_edges_added: Cell::new(false),
+            provenance_tracking: false,
+            seed: 0,
+            seed_node: None,
+            stochastic_nodes: 0,
+            deadline: Cell::new(None),
+            runs: Cell::new(0),
+            visits: Cell::new(0),
+            shared_results: RefCell::new(HashMap::default()),
+            incremental: true,
+            keyed_nodes: HashMap::default(),
+            env: None,
+            resources: Extensions::new(),
+            interner: RefCell::new(Interner::default()),
+            revision: 0,
+            remaps: Vec::new(),
+            heavy: HashMap::default(),
+            heavy_results: RefCell::new(HashMap::default()),
+            pool: None,
+            progress: None,
+            dirtying_budget: None,
+            memo_policy: MemoPolicy::Hashed,
+            undirtied: RefCell::new(BTreeSet::new()),
+            settled: RefCell::new(HashSet::default()),
+            nested_time: Cell::new(Duration::ZERO),
+            profile: None,
+            recording: None,
+            prefetches: RefCell::new(VecDeque::new()),
+            fns: Vec::new(),
+            fn_names: HashMap::new(),
+            poison_propagation: false,
+            event_senders: RefCell::new(Vec::new()),
+            inputs: Arc::default(),
+            spares: Vec::new(),
+        }
+    }
+}
+
+impl<T: Scalar, S: MemoHasher> Graph<T, S> {
+    pub fn set_max_reruns(&mut self, max_reruns: usize) {
+        self.max_reruns = max_reruns;
+    }
+
+    /// Fails any computation that would have more than `max_depth` thunks running at once with a
+    /// `DepthLimit` error, instead of letting runaway recursion overflow the stack.
+    pub fn set_max_depth(&mut self, max_depth: Option<usize>) {
+        self.max_depth = max_depth;
+    }
+
+    /// Caps the number of node instances, i.e. distinct pairs of node and arguments with a value.
+    /// Thunks can't allocate nodes of their own, so instances are how a graph grows while it's
+    /// being computed. A computation that would go over the cap fails with a `NodeLimit` error.
+    pub fn set_max_nodes(&mut self, max_nodes: Option<usize>) {
+        self.max_nodes = max_nodes;
+    }
+
+    /// In strict tracking mode, a thunk that computes a node it has no edge to, or that adds an
+    /// edge to a node it never computes, fails with an error naming both nodes. Either mistake
+    /// silently breaks invalidation otherwise, so this is worth turning on while debugging.
+    pub fn set_strict_tracking(&mut self, strict: bool) {
+        self.strict_tracking = strict;
+    }
+
+    /// With incrementality turned off, every node is re-run every time it's computed, ignoring
+    /// both its memo tables and any cutoff. Nothing else about the graph behaves differently, so
+    /// this is a way to measure how much incrementality is actually saving on a real workload.
+    pub fn set_incremental(&mut self, incremental: bool) {
+        self.incremental = incremental;
+    }
+
+    /// Gives the graph an environment that every thunk can borrow from with `Handle::env`, for
+    /// configuration and lookup tables that would otherwise have to be moved or `Rc`ed into each
+    /// closure. Replacing the environment clears every cache, since any value could depend on it.
+    pub fn set_env<E: 'static>(&mut self, env: E) {
+        if self.env.is_some() {
+            self.clear_caches();
+            self.revision += 1;
+        }
+        self.env = Some(Box::new(env));
+    }
+
+    /// The environment, if one of type `E` was set.
+    pub fn env<E: 'static>(&self) -> Option<&E> {
+        self.env.as_ref()?.downcast_ref()
+    }
+
+    /// Sets the cutoff used by every node that doesn't have its own.
+    pub fn set_cutoff(&mut self, cutoff: Cutoff) {
+        self.cutoff = cutoff;
+        self.cutoffs_in_use |= cutoff != Cutoff::Never;
+    }
+
+    /// Overrides the graph's cutoff for a single node.
+    pub fn set_node_cutoff(&mut self, id: impl Into<AThunkID>, cutoff: Cutoff) {
+        self.athunks[id.into().0].cutoff = Some(cutoff);
+        self.cutoffs_in_use |= cutoff != Cutoff::Never;
+    }
+
+    /// Stores the node's memo tables as sorted arrays instead of hash tables, which takes far less
+    /// memory per entry but makes memoizing a new entry linear in the number already memoized.
+    /// Worth it for nodes computed with a great many different arguments. Only nodes of a fixed
+    /// arity can be stored this way, and for any other node this fails with `VariableArity`.
+    pub fn set_compact_memo(&mut self, id: impl Into<AThunkID>) -> Result<(), AdaptonError> {
+        let id = id.into();
+        let athunk = &mut self.athunks[id.0];
+        let arity = athunk.arity.ok_or(AdaptonError::VariableArity(id))?;
+        for memo in [
+            &mut athunk.result,
+            &mut athunk.stale,
+            &mut athunk.unverified,
+        ] {
+            let mut compact = Memo::compact(arity);
+            compact.extend(memo.get_mut().take());
+            memo.replace(compact);
+        }
+        Ok(())
+    }
+
+    /// Record a `PropagationReport` for every dirtying pass, to help track down over-invalidation.
+    pub fn set_propagation_reports(&mut self, enabled: bool) {
+        self.propagation_reports = enabled;
+        if !enabled {
+            self.last_propagation_report = None;
         }
     }
 
-    pub fn new_athunk(&mut self, thunk: Thunk) -> AThunkID {
+    pub fn last_propagation_report(&self) -> Option<&PropagationReport> {
+        self.last_propagation_report.as_ref()
+    }
+
+    /// Gives the node a name to be known by in logs and diagnostics. Arefs created with
+    /// `aref_entry` are labelled with their name.
+    pub fn set_label(&mut self, id: impl Into<AThunkID>, label: impl Into<String>) {
+        self.athunks[id.into().0].label = Some(label.into());
+    }
+
+    pub fn label(&self, id: impl Into<AThunkID>) -> Option<&str> {
+        self.athunks[id.into().0].label.as_deref()
+    }
+
+    /// Describes the node, in as many words as it takes, for `Debug` dumps of the graph and for
+    /// `explain_error`.
+    pub fn annotate(&mut self, id: impl Into<AThunkID>, annotation: impl Into<String>) {
+        self.athunks[id.into().0].annotation = Some(annotation.into());
+    }
+
+    pub fn annotation(&self, id: impl Into<AThunkID>) -> Option<&str> {
+        self.athunks[id.into().0].annotation.as_deref()
+    }
+
+    pub fn new_athunk(&mut self, thunk: impl IntoThunk<T, S>) -> AThunkID {
         let entry = self.athunks.vacant_entry();
         let id = AThunkID(entry.key());
-        let athunk = RefCell::new(AThunk::new(id, thunk));
+        let mut athunk = AThunk::new(id, thunk.into_thunk());
+        athunk.epoch = self.remaps.len();
+        if let Some(spare) = self.spares.pop() {
+            athunk.reuse(spare);
+        }
+        athunk.reserve(self.capacity_hints);
         entry.insert(athunk);
+        lifecycle!(debug, "created {}", self.node_name(id));
+        self.emit(GraphEvent::Created(id));
+        id
+    }
+
+    /// Like `new_athunk`, but the node can also be found by `key`, which unlike its ID doesn't
+    /// depend on the order nodes were created in. That makes keys the thing to store when saving
+    /// anything about a graph that has to be matched back up with a graph built by another run.
+    ///
+    /// Panics if `key` already belongs to a node.
+    pub fn new_athunk_keyed(&mut self, key: u64, thunk: impl IntoThunk<T, S>) -> AThunkID {
+        assert!(
+            !self.keyed_nodes.contains_key(&key),
+            "a node with key {} already exists",
+            key
+        );
+        let id = self.new_athunk(thunk);
+        self.athunks[id.0].key = Some(key);
+        self.keyed_nodes.insert(key, id);
+        id
+    }
+
+    pub fn node_by_key(&self, key: u64) -> Option<AThunkID> {
+        self.keyed_nodes.get(&key).copied()
+    }
+
+    /// The key the node was created with by `new_athunk_keyed`.
+    pub fn key(&self, id: impl Into<AThunkID>) -> Option<u64> {
+        self.athunks.get(id.into().0)?.key
+    }
+
+    /// Like `new_athunk`, but computing the thunk with anything other than `arity` arguments fails
+    /// with an `ArityError` instead of running it.
+    pub fn new_athunk_with_arity(&mut self, arity: usize, thunk: impl IntoThunk<T, S>) -> AThunkID {
+        let id = self.new_athunk(thunk);
+        self.athunks[id.0].arity = Some(arity);
+        if self.memo_policy == MemoPolicy::Compact {
+            // The node has just been given a fixed arity.
+            self.set_compact_memo(id).unwrap();
+        }
+        id
+    }
+
+    /// A fixed-arity thunk whose arguments arrive as an array, so that `compute_n` can check the
+    /// argument count at compile time.
+    pub fn new_athunk_n<const N: usize>(
+        &mut self,
+        thunk: impl Fn(&mut Handle<T, S>, [f64; N]) -> T + 'static,
+    ) -> FixedAThunkID<N> {
+        let thunk = move |h: &mut Handle<T, S>| {
+            // The arity was checked before the thunk was run.
+            let args = <[f64; N]>::try_from(h.args).unwrap();
+            thunk(h, args)
+        };
+        FixedAThunkID(self.new_athunk_with_arity(N, thunk))
+    }
+
+    /// A thunk whose dependencies are known up front. The edges are added once, here, and the
+    /// thunk is handed the dependencies' values (computed with no arguments) in the same order as
+    /// `deps`. Any extra edges the thunk adds itself are kept too.
+    pub fn new_athunk_with_deps(
+        &mut self,
+        deps: &[AThunkID],
+        thunk: impl Fn(&mut Handle<T, S>, &[T]) -> T + 'static,
+    ) -> AThunkID {
+        let deps = deps.to_vec();
+        for d in deps.iter() {
+            assert!(self.athunks.contains(d.0), "no such node {:?}", d);
+        }
+        let sub_computations: Edges = deps.iter().copied().collect();
+        let id = self.new_athunk(move |h: &mut Handle<T, S>| {
+            let vals: Result<Vec<T>, AdaptonError> =
+                deps.iter().map(|&d| h.compute(d, &[])).collect();
+            match vals {
+                Ok(vals) => thunk(h, &vals),
+                // The handle remembers the error, so this value is never seen.
+                Err(_) => T::default(),
+            }
+        });
+
+        for s in sub_computations.iter() {
+            self.athunks[s.0].super_computations.borrow_mut().insert(id);
+        }
+        let athunk = &mut self.athunks[id.0];
+        athunk.static_deps = true;
+        athunk.sub_computations.replace(sub_computations);
+        id
+    }
+
+    pub fn new_aref(&mut self, val: T) -> ARefID {
+        let id = self.new_athunk(move |_: &mut Handle<T, S>| val);
+        self.athunks[id.0].is_aref = true;
+        ARefID(id)
+    }
+
+    /// An aref whose updates only invalidate the nodes that depend on it once its value has moved
+    /// more than `delta` away from the value they last saw. Smaller updates are still visible to
+    /// anything that computes the aref directly, or that is recomputed for some other reason.
+    pub fn new_aref_with_threshold(&mut self, val: T, delta: f64) -> ARefID {
+        let id = self.new_aref(val);
+        self.athunks[id.id().0].threshold = Some(Threshold {
+            delta,
+            propagated: val,
+        });
         id
     }
 
-    pub fn new_aref(&mut self, val: f64) -> AThunkID {
-        let thunk = Box::new(move |_: &mut Handle| val);
-        self.new_athunk(thunk)
+    /// Looks up the aref registered under `name`, so it can be created the first time it's seen.
+    ///
+    /// ```
+    /// # use micro_adapton_rs::Graph;
+    /// let mut graph = Graph::new();
+    /// let aapl = graph.aref_entry("price.AAPL").or_insert(0.0);
+    /// assert_eq!(aapl, graph.aref_entry("price.AAPL").or_insert(1.0));
+    /// ```
+    pub fn aref_entry(&mut self, name: impl Into<String>) -> ARefEntry<'_, T, S> {
+        ARefEntry {
+            graph: self,
+            name: name.into(),
+        }
+    }
+
+    pub fn aref_named(&self, name: &str) -> Option<ARefID> {
+        self.named_arefs.get(name).copied()
+    }
+
+    pub fn compute(&self, id: impl Into<AThunkID>, args: &[f64]) -> Result<T, AdaptonError> {
+        self.compute_in(id.into(), args, None)
+    }
+
+    /// Like `compute`, but every thunk that runs can borrow `ctx` with `Handle::ctx`, for shared
+    /// resources such as connection pools that can't be moved into the graph. Values are memoized
+    /// regardless of the context they were computed in, so they mustn't depend on it.
+    pub fn compute_with_ctx<C: 'static>(
+        &self,
+        id: impl Into<AThunkID>,
+        args: &[f64],
+        ctx: &C,
+    ) -> Result<T, AdaptonError> {
+        self.compute_in(id.into(), args, Some(ctx))
+    }
+
+    /// Like `compute`, but with exclusive access to the graph a clean memoized value can be looked
+    /// up without going through any `RefCell`s, which makes it cheaper for callers that already
+    /// own the graph mutably. Anything that has to be run is computed as usual.
+    pub fn compute_mut(
+        &mut self,
+        id: impl Into<AThunkID>,
+        args: &[f64],
+    ) -> Result<T, AdaptonError> {
+        let id = id.into();
+        if self.incremental && self.undirtied.get_mut().is_empty() {
+            if let Some(athunk) = self.athunks.get_mut(id.0) {
+                let usable = !athunk.disabled
+                    && !athunk.pending
+                    && !athunk.volatile
+                    && athunk.poisoned.get().is_none()
+                    && *athunk.clean.get_mut()
+                    && athunk.arity.is_none_or(|arity| arity == args.len());
+                if usable {
+                    let result = athunk.result.get_mut();
+                    if let Some(r) = memo::with_key(args, |key| result.get(key)) {
+                        *self.visits.get_mut() += 1;
+                        return Ok(r);
+                    }
+                }
+            }
+        }
+        self.compute(id, args)
+    }
+
+    fn compute_in(&self, id: AThunkID, args: &[f64], ctx: Ctx) -> Result<T, AdaptonError> {
+        self.dirty_demanded(id);
+        let _progress = self.track_progress(|| self.dirty_cone(id));
+        self.athunks
+            .get(id.0)
+            .ok_or(AdaptonError::NoSuchNode(id))?
+            .compute(self, args, ctx)
+    }
+
+    /// Like `compute`, but re-runs the node's thunk even if it has a memoized value for `args`,
+    /// and memoizes the new value in place of the old one. Anything the thunk computes is still
+    /// looked up as usual. For when a value is suspected of being stale.
+    pub fn compute_uncached(
+        &self,
+        id: impl Into<AThunkID>,
+        args: &[f64],
+    ) -> Result<T, AdaptonError> {
+        let id = id.into();
+        let athunk = self.athunks.get(id.0).ok_or(AdaptonError::NoSuchNode(id))?;
+        let forgotten = memo::with_key(args, |key| {
+            let unverified = athunk.unverified.borrow_mut().remove(key);
+            let result = athunk.result.borrow_mut().remove(key);
+            let stale = athunk.stale.borrow_mut().remove(key);
+            result.is_some() || stale.is_some() || unverified.is_some()
+        });
+        if forgotten {
+            self.instances.set(self.instances.get() - 1);
+        }
+        self.compute_in(id, args, None)
+    }
+
+    pub fn compute_n<const N: usize>(
+        &self,
+        id: FixedAThunkID<N>,
+        args: [f64; N],
+    ) -> Result<T, AdaptonError> {
+        self.compute(id, &args)
+    }
+
+    /// Like `compute`, but gives up with a `Timeout` error once `timeout` has passed. Thunks
+    /// aren't interrupted, so the deadline is only checked before each one is run. Whatever was
+    /// finished before then stays memoized, so trying again carries on where this left off.
+    pub fn compute_timeout(
+        &self,
+        id: impl Into<AThunkID>,
+        args: &[f64],
+        timeout: Duration,
+    ) -> Result<T, AdaptonError> {
+        let start = Instant::now();
+        let outer = self
+            .deadline
+            .replace(Some((start, start + timeout, self.runs.get())));
+        let result = self.compute(id, args);
+        self.deadline.set(outer);
+        result
+    }
+
+    /// Like `compute`, but also reports how much work it took.
+    pub fn compute_with_report(
+        &self,
+        id: impl Into<AThunkID>,
+        args: &[f64],
+    ) -> Result<ComputeReport<T>, AdaptonError> {
+        let id = id.into();
+        let (start, runs, visits) = (Instant::now(), self.runs.get(), self.visits.get());
+        let version = self.version(id);
+        let value = self.compute(id, args)?;
+        Ok(ComputeReport {
+            value,
+            was_cache_hit: self.version(id) == version,
+            nodes_recomputed: self.runs.get() - runs,
+            nodes_visited: self.visits.get() - visits,
+            duration: start.elapsed(),
+        })
+    }
+
+    pub fn update_aref(&mut self, id: ARefID, val: T) {
+        if !self.replace_aref(id, val) {
+            return;
+        }
+        if self.propagation_reports {
+            let mut report = PropagationReport::new(id);
+            self.dirty(id.id(), Some(&mut report));
+            self.last_propagation_report = Some(report);
+        } else if let Some(budget) = self.dirtying_budget {
+            self.dirty_bounded(id.id(), budget);
+        } else {
+            self.dirty(id.id(), None);
+        }
+        // The aref's only "read" is the value that was just replaced, so its old value can never be
+        // brought back by cutoff.
+        self.athunks[id.id().0].forfeit_unverified();
+    }
+
+    // Swaps in the aref's new value, and returns whether the change should be propagated to the
+    // nodes that depend on it.
+    fn replace_aref(&mut self, id: ARefID, val: T) -> bool {
+        self.revision += 1;
+        // Swapping the thunk needs `&mut self`, so there's no way for this to race with a running
+        // computation.
+        let aref = self.athunks.get_mut(id.id().0).unwrap();
+        aref.thunk = Box::new(move |_: &mut Handle<T, S>| val);
+        if let Some(threshold) = aref.threshold.as_mut() {
+            if val.distance(threshold.propagated) <= threshold.delta {
+                // Too small a change to pass on, so only the aref itself sees the new value.
+                aref.result.get_mut().clear();
+                if self.propagation_reports {
+                    let mut report = PropagationReport::new(id);
+                    report.visited.push(id.id());
+                    report.skipped.push(id.id());
+                    self.last_propagation_report = Some(report);
+                }
+                return false;
+            }
+            threshold.propagated = val;
+        }
+        true
+    }
+
+    /// Removes a manually added edge, returning whether it existed.
+    pub fn remove_edge(
+        &mut self,
+        super_id: impl Into<AThunkID>,
+        sub_id: impl Into<AThunkID>,
+    ) -> bool {
+        let (super_id, sub_id) = (super_id.into(), sub_id.into());
+        match (self.athunks.get(super_id.0), self.athunks.get(sub_id.0)) {
+            (Some(sup), Some(sub)) => {
+                sub.super_computations.borrow_mut().remove(&super_id);
+                sup.sub_computations.borrow_mut().remove(&sub_id)
+            }
+            _ => false,
+        }
+    }
+
+    /// Retires the node without removing it, so that its ID stays valid but computing it fails
+    /// with a `Disabled` error. Its memoized values are dropped, and it's never dirtied again, so
+    /// invalidation stops there too.
+    pub fn disable(&mut self, id: impl Into<AThunkID>) {
+        let athunk = &mut self.athunks[id.into().0];
+        athunk.disabled = true;
+        let dropped = athunk.result.get_mut().take().len() + athunk.stale_len();
+        athunk.stale.get_mut().clear();
+        athunk.unverified.get_mut().clear();
+        athunk.clean.set(false);
+        self.instances.set(self.instances.get() - dropped);
+    }
+
+    pub fn is_disabled(&self, id: impl Into<AThunkID>) -> bool {
+        self.athunks[id.into().0].disabled
+    }
+
+    /// A counter that moves on whenever an input changes, so that checking whether anything at
+    /// all has changed since some earlier point is a single comparison.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// The revision the node's thunk was last executed at.
+    pub fn changed_at(&self, id: impl Into<AThunkID>) -> Option<u64> {
+        Some(self.athunks.get(id.into().0)?.changed_at.get())
+    }
+
+    /// How many times the node's thunk has been executed. This only moves on actual re-executions,
+    /// so it's handy for checking that a change recomputed no more than it had to.
+    pub fn version(&self, id: impl Into<AThunkID>) -> Option<u64> {
+        Some(self.athunks.get(id.into().0)?.version.get())
+    }
+
+    /// The nodes computed by the node's last run, in the order they were first computed. Unlike
+    /// its edges, this shows how the thunk actually went about computing its value.
+    pub fn trace(&self, id: impl Into<AThunkID>) -> Option<Vec<AThunkID>> {
+        Some(self.athunks.get(id.into().0)?.trace.borrow().clone())
+    }
+
+    /// Nodes holding values that were invalidated and haven't been recomputed yet, i.e. what
+    /// `stabilize` would recompute. Nodes that have never been computed aren't included.
+    pub fn dirty_nodes(&self) -> Vec<AThunkID> {
+        self.finish_dirtying();
+        self.athunks
+            .iter()
+            .filter(|(_, athunk)| athunk.has_stale())
+            .map(|(i, _)| AThunkID(i))
+            .collect()
     }
 
-    pub fn compute(&self, id: AThunkID, args: &[f64]) -> Option<f64> {
-        Some(self.athunks.get(id.0)?.borrow_mut().compute(self, args))
+    pub fn dirty_count(&self) -> usize {
+        self.finish_dirtying();
+        self.athunks
+            .iter()
+            .filter(|(_, athunk)| athunk.has_stale())
+            .count()
     }
 
-    pub fn update_aref(&mut self, id: AThunkID, val: f64) {
-        // This scope is very necessary or else the double borrow_mut will cause RefCell to panic.
-        {
-            let mut aref = self.athunks.get(id.0).unwrap().borrow_mut();
-            aref.thunk = Box::new(move |_: &mut Handle| val);
+    /// Recomputes every value that has been demanded before and invalidated since, with the same
+    /// arguments it was demanded with, and returns the nodes whose values changed. Nodes are
+    /// recomputed in dependency order, so each one finds its sub-computations already repaired.
+    ///
+    /// The order is the same every time for the same graph, with ties between independent nodes
+    /// broken by ID and between a node's arguments by their bits, so repairs are reproducible.
+    pub fn stabilize(&self) -> Result<HashSet<AThunkID>, AdaptonError> {
+        self.finish_dirtying();
+        let mut stale: Vec<(AThunkID, Memo<T, S>)> = self
+            .athunks
+            .iter()
+            .filter(|(_, athunk)| athunk.has_stale())
+            .map(|(i, athunk)| (AThunkID(i), athunk.stale_entries()))
+            .collect();
+
+        let mut order = Vec::with_capacity(stale.len());
+        let pending: HashSet<AThunkID, S> = stale.iter().map(|(id, _)| *id).collect();
+        let mut visited = HashSet::default();
+        for (id, _) in stale.iter() {
+            self.topological_order(*id, &pending, &mut visited, &mut order);
+        }
+        let position: HashMap<AThunkID, usize, S> = order
+            .into_iter()
+            .enumerate()
+            .map(|(i, id)| (id, i))
+            .collect();
+        stale.sort_by_key(|(id, _)| position[id]);
+        let _progress = self.track_progress(|| stale.iter().map(|(_, s)| s.len()).sum());
+
+        let mut changed = HashSet::new();
+        // Heavy nodes being computed by the pool, which are only waited for once something needs
+        // them, or at the end.
+        let mut jobs = HeavyJobs::new(self);
+        let launched = &mut jobs.launched;
+        for (id, entries) in stale {
+            let mut entries: Vec<(&[u64], T)> = entries.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            let entries: Vec<(Vec<f64>, T)> = entries
+                .into_iter()
+                .map(|(key, old)| (key.iter().map(|&b| f64::from_bits(b)).collect(), old))
+                .collect();
+            let subs: Vec<AThunkID> = self.athunks[id.0]
+                .sub_computations
+                .borrow()
+                .iter()
+                .collect();
+            if self.launch_heavy(id, &entries, &position, launched, &mut changed)? {
+                continue;
+            }
+            for sub in subs {
+                self.join_heavy(sub, &position, launched, &mut changed)?;
+            }
+            for (args, old) in entries {
+                if self.compute(id, &args)? != old {
+                    changed.insert(id);
+                }
+            }
+        }
+        while let Some((&p, _)) = launched.iter().next() {
+            let id = launched[&p].0;
+            self.join_heavy(id, &position, launched, &mut changed)?;
+        }
+        // Anything left over was computed by a node that didn't have an edge to it.
+        drop(jobs);
+        self.notify_observers(&changed)?;
+        Ok(changed)
+    }
+
+    /// Calls `observer` with the node's new value whenever `stabilize` finds that it changed.
+    /// Returns the current value, which the observer isn't called with.
+    pub fn observe(
+        &mut self,
+        id: impl Into<AThunkID>,
+        args: &[f64],
+        observer: impl FnMut(T) + 'static,
+    ) -> Result<T, AdaptonError> {
+        self.observe_with_diff(id, args, |_, new| new, observer)
+    }
+
+    /// Like `observe`, but the observer is called with `new - old` rather than the new value, so
+    /// that it can apply the change to some running total.
+    pub fn observe_delta(
+        &mut self,
+        id: impl Into<AThunkID>,
+        args: &[f64],
+        observer: impl FnMut(T) + 'static,
+    ) -> Result<T, AdaptonError>
+    where
+        T: std::ops::Sub<Output = T>,
+    {
+        self.observe_with_diff(id, args, |old, new| new - old, observer)
+    }
+
+    /// Like `observe`, but the observer is called with `diff(old, new)`.
+    pub fn observe_with_diff<D>(
+        &mut self,
+        id: impl Into<AThunkID>,
+        args: &[f64],
+        diff: impl Fn(T, T) -> D + 'static,
+        mut observer: impl FnMut(D) + 'static,
+    ) -> Result<T, AdaptonError> {
+        let id = id.into();
+        let last = self.compute(id, args)?;
+        self.observers.get_mut().push(Observer {
+            id,
+            args: args.to_vec(),
+            last,
+            notify: Box::new(move |old, new| observer(diff(old, new))),
+        });
+        Ok(last)
+    }
+
+    fn notify_observers(&self, changed: &HashSet<AThunkID>) -> Result<(), AdaptonError> {
+        let mut observers = self.observers.try_borrow_mut().map_err(|_| {
+            let node = self.notifying.get();
+            AdaptonError::BorrowConflict {
+                node,
+                label: node.and_then(|id| self.label(id)).map(String::from),
+                during: "notify observers",
+            }
+        })?;
+        for o in observers.iter_mut() {
+            if !changed.contains(&o.id) {
+                continue;
+            }
+            // Changed is per node, so check that it was these particular args that changed.
+            match self.compute(o.id, &o.args) {
+                Ok(v) if v != o.last => {
+                    let old = std::mem::replace(&mut o.last, v);
+                    self.notifying.set(Some(o.id));
+                    (o.notify)(old, v);
+                    self.notifying.set(None);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // The chain of demands that led to `id` being run.
+    fn demand_path(&self, id: AThunkID) -> Vec<AThunkID> {
+        let mut path = self.demand_stack.borrow().clone();
+        path.push(id);
+        path
+    }
+
+    // Post-order over sub-computations, restricted to `pending`.
+    fn topological_order(
+        &self,
+        id: AThunkID,
+        pending: &HashSet<AThunkID, S>,
+        visited: &mut HashSet<AThunkID, S>,
+        order: &mut Vec<AThunkID>,
+    ) {
+        if !visited.insert(id) {
+            return;
+        }
+        let mut subs: Vec<AThunkID> = self.athunks[id.0]
+            .sub_computations
+            .borrow()
+            .iter()
+            .collect();
+        // Sorted, so that the order doesn't depend on how the edge set happens to be hashed.
+        subs.sort();
+        for s in subs {
+            if pending.contains(&s) {
+                self.topological_order(s, pending, visited, order);
+            }
         }
-        self.dirty(id);
+        order.push(id);
     }
 
-    fn dirty(&self, id: AThunkID) {
-        let mut athunk = self.athunks.get(id.0).unwrap().borrow_mut();
-        if athunk.clean {
-            athunk.clean = false;
-            athunk.result.clear();
-            for &s in athunk.super_computations.iter() {
-                self.dirty(s);
+    fn dirty(&self, id: AThunkID, mut report: Option<&mut PropagationReport>) {
+        let athunk = self.athunks.get(id.0).unwrap();
+        if let Some(report) = report.as_deref_mut() {
+            report.visited.push(id);
+            if !athunk.clean.get() {
+                report.skipped.push(id);
+            }
+        }
+        if athunk.invalidate(self.cutoffs_in_use) {
+            lifecycle!(trace, "dirtied {}", self.node_name(id));
+            self.emit(GraphEvent::Dirtied(id));
+            self.record(|| TraceEvent::Dirty(id));
+            let supers: Vec<AThunkID> = athunk.super_computations.borrow().iter().collect();
+            for s in supers {
+                self.dirty(s, report.as_deref_mut());
             }
         }
     }
 }
 
-pub struct Handle<'a> {
+struct Observer<T> {
+    id: AThunkID,
+    args: Vec<f64>,
+    last: T,
+    // Called with the old value and the new one.
+    notify: Box<dyn FnMut(T, T)>,
+}
+
+pub struct ARefEntry<'a, T = f64, S = KeyHash> {
+    graph: &'a mut Graph<T, S>,
+    name: String,
+}
+
+impl<'a, T: Scalar, S: MemoHasher> ARefEntry<'a, T, S> {
+    pub fn or_insert(self, val: T) -> ARefID {
+        self.or_insert_with(|| val)
+    }
+
+    pub fn or_insert_with(self, val: impl FnOnce() -> T) -> ARefID {
+        if let Some(&id) = self.graph.named_arefs.get(&self.name) {
+            return id;
+        }
+        let id = self.graph.new_aref(val());
+        self.graph.set_label(id, self.name.clone());
+        self.graph.named_arefs.insert(self.name, id);
+        id
+    }
+}
+
+pub struct Handle<'a, T = f64, S = KeyHash> {
     pub args: &'a [f64],
     id: AThunkID,
-    sub_computations: &'a mut HashSet<AThunkID>,
-    graph: &'a Graph,
+    // The compactions since the node was created, which its thunk's IDs are from before.
+    remaps: &'a [IdRemap],
+    ctx: Ctx<'a>,
+    sub_computations: &'a mut Edges,
+    // Set when the node's edges can't change, because the graph is frozen and the node had
+    // already been run.
+    fixed_edges: bool,
+    graph: &'a Graph<T, S>,
+    error: Option<AdaptonError>,
+    computed: HashSet<AThunkID, S>,
+    // What was computed, in the order it was first computed.
+    trace: Vec<AThunkID>,
+    // Edges that were added more than once.
+    duplicate_edges: Vec<AThunkID>,
+    poisoned: bool,
+    reads: Reads<T, S>,
+    sources: Sources,
 }
 
-impl<'a> Handle<'a> {
-    pub fn add_edge(&mut self, sub_id: AThunkID) {
+impl<'a, T: Scalar, S: MemoHasher> Handle<'a, T, S> {
+    /// Once the graph is frozen, edges can no longer be added, and this does nothing unless the
+    /// node is being run for the first time.
+    pub fn add_edge(&mut self, sub_id: impl Into<AThunkID>) {
+        let sub_id = self.resolve(sub_id.into());
+        if self.fixed_edges {
+            return;
+        }
+        if self.graph.frozen {
+            self.graph.frozen_edges_added.set(true);
+        }
         self.graph
             .athunks
             .get(sub_id.0)
             .unwrap()
-            .borrow_mut()
             .super_computations
+            .borrow_mut()
             .insert(self.id);
-        self.sub_computations.insert(sub_id);
+        if !self.sub_computations.insert(sub_id) {
+            self.duplicate_edges.push(sub_id);
+        }
     }
 
-    pub fn compute(&self, id: AThunkID, args: &[f64]) -> Option<f64> {
-        self.graph.compute(id, args)
+    pub fn add_edges(&mut self, sub_ids: &[AThunkID]) {
+        if self.fixed_edges {
+            return;
+        }
+        if self.graph.frozen {
+            self.graph.frozen_edges_added.set(true);
+        }
+        self.sub_computations.reserve(sub_ids.len());
+        for &sub_id in sub_ids {
+            let sub_id = self.resolve(sub_id);
+            self.graph.athunks[sub_id.0]
+                .super_computations
+                .borrow_mut()
+                .insert(self.id);
+            if !self.sub_computations.insert(sub_id) {
+                self.duplicate_edges.push(sub_id);
+            }
+        }
     }
-}
-
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
-pub struct AThunkID(usize);
 
-struct AThunk {
-    id: AThunkID,
-    thunk: Thunk,
-    result: HashMap<Vec<u64>, f64>,
-    clean: bool,
-    sub_computations: HashSet<AThunkID>,
-    super_computations: HashSet<AThunkID>,
-}
+    pub fn remove_edge(&mut self, sub_id: impl Into<AThunkID>) {
+        let sub_id = self.resolve(sub_id.into());
+        if self.fixed_edges {
+            return;
+        }
+        self.graph
+            .athunks
+            .get(sub_id.0)
+            .unwrap()
+            .super_computations
+            .borrow_mut()
+            .remove(&self.id);
+        self.sub_computations.remove(&sub_id);
+    }
 
-impl AThunk {
-    fn new(id: AThunkID, thunk: Thunk) -> Self {
-        Self {
-            id,
-            thunk,
-            result: HashMap::new(),
-            sub_computations: HashSet::new(),
-            super_computations: HashSet::new(),
-            clean: false,
+    /// Returns the `i`th argument. Like `compute`, a failure is also remembered and returned from
+    /// the computation of this handle's node.
+    pub fn arg(&mut self, i: usize) -> Result<f64, ArityError> {
+        let result = self.args.get(i).copied().ok_or(ArityError {
+            id: self.id,
+            expected: i + 1,
+            found: self.args.len(),
+        });
+        if let Err(e) = &result {
+            self.error.get_or_insert_with(|| e.clone().into());
         }
+        result
     }
 
-    fn compute(&mut self, g: &Graph, args: &[f64]) -> f64 {
-        let key: Vec<u64> = args.iter().map(|&f| f as u64).collect();
-        let result = self.result.get(&key);
-        if self.clean {
-            if let Some(&r) = result {
-                return r;
+    /// If this fails, the error is also remembered and returned from the computation of this
+    /// handle's node, so the thunk is free to fall back to some other value.
+    pub fn compute(&mut self, id: impl Into<AThunkID>, args: &[f64]) -> Result<T, AdaptonError> {
+        let id = self.resolve(id.into());
+        let result = if self.graph.strict_tracking && !self.sub_computations.contains(&id) {
+            Err(AdaptonError::UntrackedCompute {
+                id: self.id,
+                sub_id: id,
+            })
+        } else {
+            if self.computed.insert(id) {
+                self.trace.push(id);
+            }
+            self.graph.compute_in(id, args, self.ctx)
+        };
+        match &result {
+            Ok(v) => {
+                if self.graph.provenance_tracking || self.graph.cutoffs_in_use {
+                    let args = self.graph.intern_args(args);
+                    if self.graph.provenance_tracking {
+                        self.sources.push((id, args));
+                    }
+                    if self.graph.cutoffs_in_use {
+                        let version = self.graph.athunks[id.0].version.get();
+                        self.reads.insert((id, args), (*v, version));
+                    }
+                }
+            }
+            Err(e) => {
+                let e = match poison::poison_source(e) {
+                    Some(source) if self.graph.poison_propagation => AdaptonError::UpstreamError {
+                        id: self.id,
+                        source,
+                    },
+                    _ => e.clone(),
+                };
+                self.error.get_or_insert(e);
             }
         }
+        result
+    }
 
-        // Delete edge between self and sub_computations. I guess this is in-case the mutation
-        // changes the computation's subcomputations? Which I believe is current illegal in my
-        // implementation? Which makes this useless?
-        for s in self.sub_computations.iter() {
-            g.athunks
-                .get(s.0)
-                .unwrap()
-                .borrow_mut()
-                .super_computations
-                .remove(&self.id);
+    /// The graph's environment, if one of type `E` was set with `Graph::set_env`. It's borrowed
+    /// from the graph rather than the handle, so the handle can still be used while it's held.
+    pub fn env<E: 'static>(&self) -> Option<&'a E> {
+        self.graph.env()
+    }
+
+    /// The context passed to `Graph::compute_with_ctx`, if this is being computed with one of type
+    /// `C`.
+    pub fn ctx<C: 'static>(&self) -> Option<&'a C> {
+        self.ctx?.downcast_ref()
+    }
+
+    /// Memoizes `f` under `key` for this node, for expensive helpers whose results don't deserve a
+    /// node of their own. Everything memoized is thrown away when the node is dirtied, so `f` must
+    /// only depend on what the node itself depends on.
+    pub fn memo<K, V>(&mut self, key: K, f: impl FnOnce() -> V) -> V
+    where
+        K: Hash + Eq + 'static,
+        V: Clone + 'static,
+    {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let hash = hasher.finish();
+        let athunk = &self.graph.athunks[self.id.0];
+        if let Some(bucket) = athunk.helpers.borrow().get(&hash) {
+            let found = bucket
+                .iter()
+                .find(|(k, _)| k.downcast_ref() == Some(&key))
+                .and_then(|(_, v)| v.downcast_ref::<V>());
+            if let Some(v) = found {
+                return v.clone();
+            }
         }
-        self.sub_computations.clear();
+        let v = f();
+        let mut helpers = athunk.helpers.borrow_mut();
+        let bucket = helpers.entry(hash).or_default();
+        bucket.retain(|(k, _)| k.downcast_ref() != Some(&key));
+        bucket.push((Box::new(key), Box::new(v.clone())));
+        v
+    }
 
-        self.clean = true;
-        let result = (self.thunk)(&mut Handle {
-            args,
-            id: self.id,
-            sub_computations: &mut self.sub_computations,
-            graph: g,
-        });
-        self.result.insert(key, result);
+    /// Adds an edge to each node and computes it with the arguments it's paired with, stopping at
+    /// the first failure.
+    pub fn force_all(&mut self, nodes: &[(AThunkID, &[f64])]) -> Result<Vec<T>, AdaptonError> {
+        let ids: Vec<AThunkID> = nodes.iter().map(|&(id, _)| id).collect();
+        self.add_edges(&ids);
+        nodes
+            .iter()
+            .map(|&(id, args)| self.compute(id, args))
+            .collect()
+    }
+
+    pub fn compute_n<const N: usize>(
+        &mut self,
+        id: FixedAThunkID<N>,
+        args: [f64; N],
+    ) -> Result<T, AdaptonError> {
+        self.compute(id, &args)
+    }
+
+    fn resolve(&self, id: AThunkID) -> AThunkID {
+        self.remaps.iter().fold(id, |id, remap| remap.get(id))
+    }
 
-        // Recurse in-case the above computation invalidated this one...? Which implies a cycle and
-        // is therefore an infinite loop? I still don't get why the paper suggests this.
-        self.compute(g, args)
+    fn unused_edge(&self) -> Option<AdaptonError> {
+        // A frozen node's edges cover every memo entry, so a run needn't compute all of them.
+        if !self.graph.strict_tracking || self.graph.frozen {
+            return None;
+        }
+        self.sub_computations
+            .iter()
+            .filter(|id| !self.computed.contains(id))
+            .min_by_key(|id| id.0)
+            .map(|sub_id| AdaptonError::UnusedEdge {
+                id: self.id,
+                sub_id,
+            })
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct AThunkID(usize);
 
-    #[test]
-    fn it_works() {
-        let mut graph = Graph::new();
+/// The ID of an input cell created by `new_aref`. Only these can be updated, but they can be used
+/// anywhere else an `AThunkID` can.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ARefID(AThunkID);
 
-        let r1 = graph.new_aref(8.0);
-        let r2 = graph.new_aref(10.0);
-        let r3 = graph.new_aref(2.0);
+impl ARefID {
+    pub fn id(self) -> AThunkID {
+        self.0
+    }
+}
 
-        let a1 = graph.new_athunk(Box::new(move |h| {
-            h.add_edge(r2);
-            h.add_edge(r1);
-            h.compute(r2, &[]).unwrap() - h.compute(r1, &[]).unwrap()
-        }));
+impl fmt::Display for AThunkID {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "node {}", self.0)
+    }
+}
 
-        let a2 = graph.new_athunk(Box::new(move |h| {
-            h.add_edge(r3);
-            h.add_edge(r1);
-            h.compute(r3, &[]).unwrap() + h.compute(r1, &[]).unwrap()
-        }));
+impl fmt::Display for ARefID {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
 
-        let a3 = graph.new_athunk(Box::new(move |h| {
-            h.add_edge(r2);
-            h.add_edge(a1);
-            h.add_edge(a2);
-            (h.compute(r2, &[]).unwrap()
-                + h.compute(a1, &[]).unwrap()
-                + h.compute(a2, &[]).unwrap())
-                / h.args[0]
-        }));
+impl From<ARefID> for AThunkID {
+    fn from(id: ARefID) -> Self {
+        id.0
+    }
+}
 
-        assert_eq!(Some(10.0), graph.compute(a2, &[]));
-        assert_eq!(Some(22.0), graph.compute(a3, &[1.0]));
-        assert_eq!(Some(11.0), graph.compute(a3, &[2.0]));
-        assert_eq!(Some(22.0), graph.compute(a3, &[1.0]));
-        assert_eq!(Some(11.0), graph.compute(a3, &[2.0]));
+/// An `AThunkID` created by `new_athunk_n`, which takes exactly `N` arguments.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct FixedAThunkID<const N: usize>(AThunkID);
 
-        graph.update_aref(r2, 6.0);
+impl<const N: usize> FixedAThunkID<N> {
+    pub fn id(self) -> AThunkID {
+        self.0
+    }
+}
+
+impl<const N: usize> From<FixedAThunkID<N>> for AThunkID {
+    fn from(id: FixedAThunkID<N>) -> Self {
+        id.0
+    }
+}
+
+// Every value a node read while computing its memo entries, keyed by the node and arguments that
+// were read, along with the version of the node that was read.
+type Reads<T, S> = HashMap<(AThunkID, ArgsId), (T, u64), S>;
+
+// Whatever was passed to `Graph::compute_with_ctx`.
+type Ctx<'a> = Option<&'a dyn Any>;
+
+// A node's edges in one direction.
+type Edges = EdgeSet;
+
+// Whatever was memoized with `Handle::memo`, as keys and values bucketed by the hash of the key.
+type Helpers<S> = HashMap<u64, Vec<(Box<dyn Any>, Box<dyn Any>)>, S>;
+
+// The nodes and arguments that were computed by a single run of a thunk.
+type Sources = Vec<(AThunkID, ArgsId)>;
+
+// The thunk lives outside of any cell so that it can be called without holding a borrow, and the
+// rest of the node is split into independently borrowable cells. Every borrow is short and never
+// spans user code, which means a thunk can touch whatever node it likes without tripping a RefCell
+// panic.
+struct AThunk<T, S> {
+    id: AThunkID,
+    thunk: Thunk<T, S>,
+    // Set for nodes made from a registered function, which is run in place of the thunk, along
+    // with the node's parameters.
+    shared_fn: Option<(FnID, Box<[f64]>)>,
+    arity: Option<usize>,
+    static_deps: bool,
+    // Set for nodes built by the arithmetic combinators, so they can be compiled.
+    op: Option<Op>,
+    result: RefCell<Memo<T, S>>,
+    // Values that were invalidated and haven't been recomputed since, apart from `unverified`.
+    stale: RefCell<Memo<T, S>>,
+    // The memo entries from just before the node was last dirtied, which can be brought back if
+    // cutoff shows that nothing the node read has changed. They're stale too, but kept apart so
+    // that dirtying is just a move however many there are, and only join `stale` once they can't
+    // be brought back.
+    unverified: RefCell<Memo<T, S>>,
+    reads: RefCell<Reads<T, S>>,
+    cutoff: Option<Cutoff>,
+    threshold: Option<Threshold<T>>,
+    is_aref: bool,
+    disabled: bool,
+    // Set for arefs that don't have a value yet, along with the nodes that tried to compute them.
+    pending: bool,
+    waiting: RefCell<Edges>,
+    // While the node is clean, the poisoned node it's failing because of, which may be itself.
+    poisoned: Cell<Option<AThunkID>>,
+    volatile: bool,
+    // How expensive the thunk is to run, relative to other nodes, if the user has said.
+    cost: Option<f64>,
+    label: Option<String>,
+    annotation: Option<String>,
+    key: Option<u64>,
+    // How many times the graph had been compacted when the node was created.
+    epoch: usize,
+    // What each memo entry was computed from, when provenance is being tracked.
+    sources: RefCell<HashMap<ArgsId, Sources, S>>,
+    clean: Cell<bool>,
+    computing: Cell<bool>,
+    version: Cell<u64>,
+    changed_at: Cell<u64>,
+    // How long the last run took, not counting the thunks it ran.
+    self_time: Cell<Duration>,
+    sub_computations: RefCell<Edges>,
+    super_computations: RefCell<Edges>,
+    trace: RefCell<Vec<AThunkID>>,
+    // The edges the last run added more than once.
+    duplicate_edges: RefCell<Vec<AThunkID>>,
+    // Whatever was memoized with `Handle::memo`.
+    helpers: RefCell<Helpers<S>>,
+}
+
+struct Threshold<T> {
+    delta: f64,
+    // The value that invalidation was last propagated for.
+    propagated: T,
+}
+
+impl<T: Scalar, S: MemoHasher> AThunk<T, S> {
+    fn new(id: AThunkID, thunk: Thunk<T, S>) -> Self {
+        Self {
+            id,
+            thunk,
+            shared_fn: None,
+            arity: None,
+            static_deps: false,
+            op: None,
+            result: RefCell::new(Memo::default()),
+            stale: RefCell::new(Memo::default()),
+            unverified: RefCell::new(Memo::default()),
+            reads: RefCell::new(HashMap::default()),
+            cutoff: None,
+            threshold: None,
+            is_aref: false,
+            disabled: false,
+            pending: false,
+            waiting: RefCell::new(Edges::default()),
+            poisoned: Cell::new(None),
+            volatile: false,
+            cost: None,
+            label: None,
+            annotation: None,
+            key: None,
+            epoch: 0,
+            sources: RefCell::new(HashMap::default()),
+            sub_computations: RefCell::new(Edges::default()),
+            super_computations: RefCell::new(Edges::default()),
+            trace: RefCell::new(Vec::new()),
+            duplicate_edges: RefCell::new(Vec::new()),
+            helpers: RefCell::new(HashMap::default()),
+            clean: Cell::new(false),
+            computing: Cell::new(false),
+            version: Cell::new(0),
+            changed_at: Cell::new(0),
+            self_time: Cell::new(Duration::ZERO),
+        }
+    }
+
+    fn compute(&self, g: &Graph<T, S>, args: &[f64], ctx: Ctx) -> Result<T, AdaptonError> {
+        g.visits.set(g.visits.get() + 1);
+        g.record(|| TraceEvent::Demand(self.id, args.to_vec()));
+        if self.disabled {
+            return Err(AdaptonError::Disabled(self.id));
+        }
+        if self.pending {
+            if let Some(&waiter) = g.demand_stack.borrow().last() {
+                self.waiting.borrow_mut().insert(waiter);
+            }
+            return Err(AdaptonError::Pending(self.id));
+        }
+        if let Some(e) = g.poison_error(self.id) {
+            return Err(e);
+        }
+        if self.computing.get() {
+            let path = g.demand_path(self.id);
+            return Err(AdaptonError::Cycle {
+                id: self.id,
+                demanded_by: path[path.len() - 2],
+                path,
+            });
+        }
+        if let Some(arity) = self.arity {
+            if arity != args.len() {
+                return Err(ArityError {
+                    id: self.id,
+                    expected: arity,
+                    found: args.len(),
+                }
+                .into());
+            }
+        }
+        if g.incremental && !self.volatile && self.clean.get() {
+            // The common case, which can be looked up without allocating the key.
+            if let Some(r) = memo::with_key(args, |key| self.result.borrow().get(key)) {
+                g.record(|| TraceEvent::Hit(self.id, args.to_vec()));
+                return Ok(r);
+            }
+        }
+        let key = ArgKey::new(args);
+
+        // The paper re-runs the computation in-case it invalidated itself. That can only happen
+        // when a sub-computation dirties this node, and a node that does that on every run would
+        // loop forever, so give up after a while.
+        let mut from_scratch = !g.incremental || self.volatile;
+        let mut ran = false;
+        for _ in 0..=g.max_reruns {
+            if from_scratch {
+                from_scratch = false;
+            } else if self.clean.get() {
+                if let Some(r) = self.result.borrow().get(&key) {
+                    if !ran {
+                        g.record(|| TraceEvent::Hit(self.id, args.to_vec()));
+                    }
+                    return Ok(r);
+                }
+            } else if self.verify(g, ctx) {
+                continue;
+            }
+            ran = true;
+            self.run(g, args, &key, ctx)?;
+        }
+        if self.clean.get() {
+            if let Some(r) = self.result.borrow().get(&key) {
+                return Ok(r);
+            }
+        }
+        Err(AdaptonError::RerunLimit {
+            id: self.id,
+            reruns: g.max_reruns,
+        })
+    }
+
+    // Marks the node dirty, returning whether it was clean before.
+    fn invalidate(&self, cutoffs_in_use: bool) -> bool {
+        if self.disabled || !self.clean.replace(false) {
+            return false;
+        }
+        self.poisoned.set(None);
+        // Hang on to the old values, so that `stabilize` knows what to recompute and can tell
+        // whether the recomputed values actually changed.
+        self.helpers.borrow_mut().clear();
+        let result = self.result.borrow_mut().take();
+        if cutoffs_in_use {
+            self.forfeit_unverified();
+            self.unverified.replace(result);
+        } else {
+            self.stale.borrow_mut().extend(result);
+        }
+        true
+    }
+
+    // Gives up on bringing back the entries from before the node was last dirtied.
+    fn forfeit_unverified(&self) {
+        let unverified = self.unverified.borrow_mut().take();
+        self.stale.borrow_mut().extend(unverified);
+    }
+
+    fn has_stale(&self) -> bool {
+        !self.stale.borrow().is_empty() || !self.unverified.borrow().is_empty()
+    }
+
+    fn stale_len(&self) -> usize {
+        self.stale.borrow().len() + self.unverified.borrow().len()
+    }
+
+    // Every value that has been invalidated and not recomputed since.
+    fn stale_entries(&self) -> Memo<T, S> {
+        let mut stale = self.stale.borrow().clone();
+        stale.extend(self.unverified.borrow().clone());
+        stale
+    }
+
+    // Marks a dirty node clean again, restoring its old memo entries, if everything it read is
+    // unchanged according to the cutoff of whatever was read.
+    fn verify(&self, g: &Graph<T, S>, ctx: Ctx) -> bool {
+        if self.unverified.borrow().is_empty() {
+            return false;
+        }
+        // Reads are only recorded through the handle, so an edge without any read means the
+        // thunk got at the value some other way and there's nothing to compare.
+        let reads = self.reads.borrow().clone();
+        let read_subs: HashSet<AThunkID, S> = reads.keys().map(|(id, _)| *id).collect();
+        if !self.sub_computations.borrow().is_subset(&read_subs) {
+            return false;
+        }
+
+        for ((id, args), (old, version)) in reads {
+            let args = g.interned_args(args);
+            let new = match g.compute_in(id, &args, ctx) {
+                Ok(new) => new,
+                Err(_) => return false,
+            };
+            let sub = &g.athunks[id.0];
+            let unchanged = sub.version.get() == version
+                || match sub.cutoff.unwrap_or(g.cutoff) {
+                    Cutoff::Never => false,
+                    Cutoff::Exact => new == old,
+                    Cutoff::Tolerance(eps) => new.distance(old) < eps,
+                };
+            if !unchanged {
+                return false;
+            }
+        }
+        // Computing what was read could have come back around to this node.
+        if self.clean.get() {
+            return false;
+        }
+
+        let restored = self.unverified.borrow_mut().take();
+        self.result.borrow_mut().extend(restored);
+        self.clean.set(true);
+        true
+    }
+
+    fn run(
+        &self,
+        g: &Graph<T, S>,
+        args: &[f64],
+        key: &[u64],
+        ctx: Ctx,
+    ) -> Result<(), AdaptonError> {
+        let new_instance = !self.result.borrow().contains_key(key)
+            && !self.stale.borrow().contains_key(key)
+            && !self.unverified.borrow().contains_key(key);
+        if let Some(max) = g.max_depth {
+            if g.demand_stack.borrow().len() >= max {
+                return Err(AdaptonError::DepthLimit {
+                    path: g.demand_path(self.id),
+                });
+            }
+        }
+        if let Some((start, deadline, runs)) = g.deadline.get() {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(AdaptonError::Timeout {
+                    id: self.id,
+                    recomputed: g.runs.get() - runs,
+                    elapsed: now - start,
+                });
+            }
+        }
+        if new_instance {
+            if let Some(limit) = g.max_nodes {
+                if g.instances.get() >= limit {
+                    return Err(AdaptonError::NodeLimit {
+                        limit,
+                        path: g.demand_path(self.id),
+                    });
+                }
+            }
+            // Counted up front, so that instances still being computed count towards the limit.
+            g.instances.set(g.instances.get() + 1);
+        }
+
+        // Delete edge between self and sub_computations. I guess this is in-case the mutation
+        // changes the computation's subcomputations? Which I believe is current illegal in my
+        // implementation? Which makes this useless?
+        //
+        // Only do it when dirty though. A clean node is just gaining a memo entry for new args, and
+        // the entries it already has still depend on those edges.
+        let mut sub_computations = self.sub_computations.take();
+        if !self.clean.get() {
+            self.reads.borrow_mut().clear();
+            self.forfeit_unverified();
+        }
+        // A node that's first run once the graph is frozen still has to add its edges.
+        let fixed_edges = g.frozen && self.version.get() > 0;
+        let keep_edges = self.static_deps || fixed_edges;
+        if !keep_edges && !self.clean.get() {
+            for s in sub_computations.drain() {
+                g.athunks
+                    .get(s.0)
+                    .unwrap()
+                    .super_computations
+                    .borrow_mut()
+                    .remove(&self.id);
+            }
+        }
+        // Static dependencies never change, so their edges are handed straight to the handle.
+        let mut edges = if keep_edges {
+            sub_computations.clone()
+        } else {
+            Edges::default()
+        };
+
+        lifecycle!(
+            debug,
+            "recomputing {} with {:?}",
+            g.node_name(self.id),
+            args
+        );
+        g.emit(GraphEvent::Recomputed(self.id));
+        g.record(|| TraceEvent::Recompute(self.id, args.to_vec()));
+        self.clean.set(true);
+        self.computing.set(true);
+        self.version.set(self.version.get() + 1);
+        self.changed_at.set(g.revision);
+        g.runs.set(g.runs.get() + 1);
+        let mut handle = Handle {
+            args,
+            id: self.id,
+            remaps: &g.remaps[self.epoch..],
+            ctx,
+            sub_computations: &mut edges,
+            fixed_edges,
+            graph: g,
+            error: None,
+            computed: HashSet::default(),
+            trace: Vec::new(),
+            duplicate_edges: Vec::new(),
+            poisoned: false,
+            reads: HashMap::default(),
+            sources: Vec::new(),
+        };
+        g.demand_stack.borrow_mut().push(self.id);
+        let outer = g.nested_time.replace(Duration::ZERO);
+        let start = Instant::now();
+        let result = match &self.shared_fn {
+            Some((f, params)) => g.call_fn(*f, &mut handle, params),
+            None => (self.thunk)(&mut handle),
+        };
+        let elapsed = start.elapsed();
+        let self_time = elapsed.saturating_sub(g.nested_time.replace(outer + elapsed));
+        self.self_time.set(self_time);
+        g.record_profile(self_time);
+        g.demand_stack.borrow_mut().pop();
+        g.report_progress();
+        let mut error = handle.error.take().or_else(|| handle.unused_edge());
+        // Errors from before the thunk poisoned itself are likely to be why it did.
+        if handle.poisoned && error.is_none() {
+            error = Some(AdaptonError::Poisoned(self.id));
+        }
+        let source = error.as_ref().and_then(poison::poison_source);
+        if source == Some(self.id) || (source.is_some() && g.poison_propagation) {
+            self.poisoned.set(source);
+        }
+        self.computing.set(false);
+        self.reads.borrow_mut().extend(handle.reads);
+        self.trace.replace(handle.trace);
+        self.duplicate_edges.replace(handle.duplicate_edges);
+        let sources = handle.sources;
+        sub_computations.extend(edges);
+        self.sub_computations.replace(sub_computations);
+
+        if error.is_some() || !self.clean.get() {
+            if new_instance {
+                g.instances.set(g.instances.get() - 1);
+            }
+        } else {
+            self.result.borrow_mut().insert(key, result);
+            let old = self.stale.borrow_mut().remove(key);
+            if old.is_some_and(|old| old != result) {
+                g.emit(GraphEvent::Changed(self.id));
+            }
+            if g.provenance_tracking {
+                self.sources.borrow_mut().insert(g.intern_key(key), sources);
+            }
+        }
+        match error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn it_works() {
+        let mut graph = Graph::new();
+
+        let r1 = graph.new_aref(8.0);
+        let r2 = graph.new_aref(10.0);
+        let r3 = graph.new_aref(2.0);
+
+        let a1 = graph.new_athunk(move |h| {
+            h.add_edge(r2);
+            h.add_edge(r1);
+            h.compute(r2, &[]).unwrap() - h.compute(r1, &[]).unwrap()
+        });
+
+        let a2 = graph.new_athunk(move |h| {
+            h.add_edge(r3);
+            h.add_edge(r1);
+            h.compute(r3, &[]).unwrap() + h.compute(r1, &[]).unwrap()
+        });
+
+        let a3 = graph.new_athunk(move |h| {
+            h.add_edge(r2);
+            h.add_edge(a1);
+            h.add_edge(a2);
+            (h.compute(r2, &[]).unwrap()
+                + h.compute(a1, &[]).unwrap()
+                + h.compute(a2, &[]).unwrap())
+                / h.args[0]
+        });
+
+        assert_eq!(Ok(10.0), graph.compute(a2, &[]));
+        assert_eq!(Ok(22.0), graph.compute(a3, &[1.0]));
+        assert_eq!(Ok(11.0), graph.compute(a3, &[2.0]));
+        assert_eq!(Ok(22.0), graph.compute(a3, &[1.0]));
+        assert_eq!(Ok(11.0), graph.compute(a3, &[2.0]));
+
+        graph.update_aref(r2, 6.0);
+
+        assert_eq!(Ok(10.0), graph.compute(a2, &[]));
+        assert_eq!(Ok(14.0), graph.compute(a3, &[1.0]));
+        assert_eq!(Ok(7.0), graph.compute(a3, &[2.0]));
+        assert_eq!(Ok(14.0), graph.compute(a3, &[1.0]));
+        assert_eq!(Ok(7.0), graph.compute(a3, &[2.0]));
+    }
+
+    #[test]
+    fn cycles_do_not_panic() {
+        let mut graph = Graph::new();
+
+        let a = graph.new_athunk(move |h| {
+            let me = h.id;
+            h.add_edge(me);
+            h.compute(me, &[]).unwrap_or(-1.0)
+        });
+
+        let cycle = AdaptonError::Cycle {
+            id: a,
+            demanded_by: a,
+            path: vec![a, a],
+        };
+        assert_eq!(Err(cycle), graph.compute(a, &[]));
+    }
+
+    #[test]
+    fn reentrant_compute() {
+        let graph = Rc::new(RefCell::new(Graph::new()));
+        let seen = Rc::new(RefCell::new(None));
+        let target = Rc::new(Cell::new(None));
+
+        let (g, s, t) = (Rc::downgrade(&graph), seen.clone(), target.clone());
+        let b = graph.borrow_mut().new_athunk(move |_| {
+            // Gets at the graph without going through the handle.
+            let result = g.upgrade().unwrap().borrow().compute(t.get().unwrap(), &[]);
+            *s.borrow_mut() = result.err();
+            0.0
+        });
+        let a = graph.borrow_mut().new_athunk(move |h| {
+            h.add_edge(b);
+            h.compute(b, &[]).unwrap_or(0.0)
+        });
+        target.set(Some(a));
+
+        let result = graph.borrow().compute(b, &[]);
+        assert_eq!(Ok(0.0), result);
+        let cycle = AdaptonError::Cycle {
+            id: b,
+            demanded_by: a,
+            path: vec![b, a, b],
+        };
+        assert_eq!(Some(cycle), seen.borrow_mut().take());
+    }
+
+    #[test]
+    fn remove_edge() {
+        let mut graph = Graph::new();
+
+        let r = graph.new_aref(1.0);
+        let a = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap()
+        });
+
+        assert_eq!(Ok(1.0), graph.compute(a, &[]));
+        assert!(graph.remove_edge(a, r));
+        assert!(!graph.remove_edge(a, r));
+
+        // Without the edge the update no longer reaches `a`.
+        graph.update_aref(r, 2.0);
+        assert_eq!(Ok(1.0), graph.compute(a, &[]));
+    }
+
+    #[test]
+    fn strict_tracking() {
+        let mut graph = Graph::new();
+        graph.set_strict_tracking(true);
+
+        let r1 = graph.new_aref(1.0);
+        let r2 = graph.new_aref(2.0);
+        let untracked = graph.new_athunk(move |h| {
+            h.add_edge(r1);
+            h.compute(r1, &[]).unwrap() + h.compute(r2, &[]).unwrap_or(0.0)
+        });
+        let unused = graph.new_athunk(move |h| {
+            h.add_edge(r1);
+            h.add_edge(r2);
+            h.compute(r1, &[]).unwrap()
+        });
+
+        assert_eq!(
+            Err(AdaptonError::UntrackedCompute {
+                id: untracked,
+                sub_id: r2.id()
+            }),
+            graph.compute(untracked, &[])
+        );
+        assert_eq!(
+            Err(AdaptonError::UnusedEdge {
+                id: unused,
+                sub_id: r2.id()
+            }),
+            graph.compute(unused, &[])
+        );
+    }
+
+    #[test]
+    fn arity() {
+        let mut graph = Graph::new();
+
+        let a = graph.new_athunk_with_arity(1, |h| h.args[0] * 2.0);
+        let b = graph.new_athunk(|h| h.arg(1).unwrap_or(0.0));
+
+        assert_eq!(Ok(4.0), graph.compute(a, &[2.0]));
+        assert_eq!(
+            Err(AdaptonError::Arity(ArityError {
+                id: a,
+                expected: 1,
+                found: 0
+            })),
+            graph.compute(a, &[])
+        );
+        assert_eq!(
+            Err(AdaptonError::Arity(ArityError {
+                id: b,
+                expected: 2,
+                found: 1
+            })),
+            graph.compute(b, &[1.0])
+        );
+    }
+
+    #[test]
+    fn fixed_arity() {
+        let mut graph = Graph::new();
+
+        let r = graph.new_aref(3.0);
+        let a = graph.new_athunk_n(move |h, [x, y]| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap() * x + y
+        });
+        let b = graph.new_athunk_n(move |h, []| {
+            h.add_edge(a);
+            h.compute_n(a, [2.0, 1.0]).unwrap()
+        });
+
+        assert_eq!(Ok(7.0), graph.compute_n(a, [2.0, 1.0]));
+        assert_eq!(Ok(7.0), graph.compute_n(b, []));
+        graph.update_aref(r, 4.0);
+        assert_eq!(Ok(9.0), graph.compute_n(b, []));
+    }
+
+    #[test]
+    fn boxed_thunks() {
+        let mut graph = Graph::new();
+
+        let thunk: Thunk = Box::new(|h| h.args[0] + 1.0);
+        let a = graph.new_athunk(thunk);
+
+        assert_eq!(Ok(2.0), graph.compute(a, &[1.0]));
+    }
+
+    #[test]
+    fn static_deps() {
+        let mut graph = Graph::new();
+
+        let r1 = graph.new_aref(1.0);
+        let r2 = graph.new_aref(2.0);
+        let a = graph.new_athunk_with_deps(&[r1.id(), r2.id()], |_, vals| vals[0] - vals[1]);
+
+        assert_eq!(Ok(-1.0), graph.compute(a, &[]));
+        graph.update_aref(r2, 5.0);
+        assert_eq!(Ok(-4.0), graph.compute(a, &[]));
+    }
+
+    #[test]
+    fn stabilize() {
+        let mut graph = Graph::new();
+
+        let r1 = graph.new_aref(1.0);
+        let r2 = graph.new_aref(2.0);
+        let a1 = graph.new_athunk(move |h| {
+            h.add_edge(r1);
+            h.compute(r1, &[]).unwrap() * h.args[0]
+        });
+        let a2 = graph.new_athunk(move |h| {
+            h.add_edge(r2);
+            h.compute(r2, &[]).unwrap() * 0.0
+        });
+
+        assert_eq!(Ok(1.5), graph.compute(a1, &[1.5]));
+        assert_eq!(Ok(2.0), graph.compute(a1, &[2.0]));
+        assert_eq!(Ok(0.0), graph.compute(a2, &[]));
+
+        graph.update_aref(r1, 2.0);
+        graph.update_aref(r2, 3.0);
+        assert_eq!(4, graph.dirty_count());
+        let changed = graph.stabilize().unwrap();
+        assert_eq!(
+            vec![r1.id(), a1, r2.id()]
+                .into_iter()
+                .collect::<HashSet<_>>(),
+            changed
+        );
+        assert!(graph.dirty_nodes().is_empty());
+        assert!(graph.stabilize().unwrap().is_empty());
+        assert_eq!(Ok(3.0), graph.compute(a1, &[1.5]));
+    }
+
+    #[test]
+    fn edges_are_kept_for_every_memo_entry() {
+        let mut graph = Graph::new();
+
+        let r1 = graph.new_aref(1.0);
+        let r2 = graph.new_aref(2.0);
+        let a = graph.new_athunk(move |h| {
+            let r = if h.args[0] == 0.0 { r1 } else { r2 };
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap()
+        });
+
+        assert_eq!(Ok(1.0), graph.compute(a, &[0.0]));
+        assert_eq!(Ok(2.0), graph.compute(a, &[1.0]));
+        graph.update_aref(r1, 3.0);
+        assert_eq!(Ok(3.0), graph.compute(a, &[0.0]));
+    }
+
+    #[test]
+    fn versions() {
+        let mut graph = Graph::new();
+
+        let r1 = graph.new_aref(1.0);
+        let r2 = graph.new_aref(2.0);
+        let a = graph.new_athunk(move |h| {
+            h.add_edge(r1);
+            h.compute(r1, &[]).unwrap()
+        });
+        let b = graph.new_athunk(move |h| {
+            h.add_edge(a);
+            h.add_edge(r2);
+            h.compute(a, &[]).unwrap() + h.compute(r2, &[]).unwrap()
+        });
+
+        assert_eq!(Some(0), graph.version(b));
+        graph.compute(b, &[]).unwrap();
+        graph.compute(b, &[]).unwrap();
+        assert_eq!(Some(1), graph.version(b));
+
+        graph.update_aref(r2, 3.0);
+        graph.compute(b, &[]).unwrap();
+        assert_eq!(Some(1), graph.version(a));
+        assert_eq!(Some(2), graph.version(b));
+    }
+
+    #[test]
+    fn propagation_report() {
+        let mut graph = Graph::new();
+        graph.set_propagation_reports(true);
+
+        let r = graph.new_aref(1.0);
+        let a = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap()
+        });
+        let b = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            h.add_edge(a);
+            h.compute(r, &[]).unwrap() + h.compute(a, &[]).unwrap()
+        });
+        graph.compute(b, &[]).unwrap();
+
+        graph.update_aref(r, 2.0);
+        let report = graph.last_propagation_report().unwrap();
+        assert_eq!(r, report.source);
+        assert_eq!(4, report.visited.len());
+        assert_eq!(vec![b], report.skipped);
+
+        graph.update_aref(r, 3.0);
+        let report = graph.last_propagation_report().unwrap();
+        assert_eq!(vec![r.id()], report.visited);
+        assert_eq!(vec![r.id()], report.skipped);
+    }
+
+    #[test]
+    fn cutoff() {
+        let mut graph = Graph::new();
+        graph.set_cutoff(Cutoff::Exact);
+
+        let r = graph.new_aref(1.2);
+        let rounded = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap().round()
+        });
+        let noisy = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap() * 1.0001
+        });
+        graph.set_node_cutoff(noisy, Cutoff::Tolerance(0.5));
+        let sum = graph.new_athunk(move |h| {
+            h.add_edge(rounded);
+            h.add_edge(noisy);
+            h.compute(rounded, &[]).unwrap() + h.compute(noisy, &[]).unwrap()
+        });
+        let sum_before = graph.compute(sum, &[]).unwrap();
+
+        graph.assert_recomputes(&[(r.id(), 1), (rounded, 1), (noisy, 1)], |g| {
+            g.update_aref(r, 1.3);
+            assert_eq!(Ok(sum_before), g.compute(sum, &[]));
+        });
+        graph.assert_recomputes(&[(r.id(), 1), (rounded, 1), (noisy, 1), (sum, 1)], |g| {
+            g.update_aref(r, 2.0);
+            assert_eq!(Ok(2.0 + 2.0 * 1.0001), g.compute(sum, &[]));
+        });
+    }
+
+    #[test]
+    fn cutoff_with_many_entries() {
+        let mut graph = Graph::new();
+        graph.set_cutoff(Cutoff::Exact);
+
+        let r = graph.new_aref(1.2);
+        let rounded = graph.new_athunk_with_deps(&[r.id()], |_, vals| vals[0].round());
+        let scaled = graph.new_athunk(move |h| {
+            h.add_edge(rounded);
+            h.compute(rounded, &[]).unwrap() * h.args[0]
+        });
+        for i in 0..10 {
+            graph.compute(scaled, &[i as f64]).unwrap();
+        }
+        assert_eq!(12, graph.instances.get());
+
+        // Every entry is brought back by the first one to be computed.
+        graph.assert_recomputes(&[(r.id(), 1), (rounded, 1)], |g| {
+            g.update_aref(r, 1.3);
+            assert_eq!(vec![r.id(), rounded, scaled], g.dirty_nodes());
+            assert_eq!(Ok(3.0), g.compute(scaled, &[3.0]));
+        });
+        assert!(graph.dirty_nodes().is_empty());
+
+        // Those that are left stale once one has been re-run are all still repaired.
+        graph.update_aref(r, 2.0);
+        graph.assert_recomputes(&[(r.id(), 1), (rounded, 1), (scaled, 10)], |g| {
+            assert_eq!(Ok(6.0), g.compute(scaled, &[3.0]));
+            assert_eq!(HashSet::from([scaled]), g.stabilize().unwrap());
+        });
+        assert_eq!(Ok(18.0), graph.compute(scaled, &[9.0]));
+        assert_eq!(12, graph.instances.get());
+    }
+
+    #[test]
+    fn threshold() {
+        let mut graph = Graph::new();
+
+        let r = graph.new_aref_with_threshold(10.0, 1.0);
+        let a = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap()
+        });
+        assert_eq!(Ok(10.0), graph.compute(a, &[]));
+
+        graph.update_aref(r, 10.5);
+        graph.update_aref(r, 9.5);
+        assert_eq!(Ok(9.5), graph.compute(r, &[]));
+        assert_eq!(Ok(10.0), graph.compute(a, &[]));
+
+        graph.update_aref(r, 11.5);
+        assert_eq!(Ok(11.5), graph.compute(a, &[]));
+    }
+
+    #[test]
+    fn limits() {
+        let mut graph = Graph::new();
+
+        // A chain of nodes, each adding one to the node below it.
+        let mut chain = vec![graph.new_athunk(|h| h.args.len() as f64)];
+        for _ in 0..4 {
+            let below = *chain.last().unwrap();
+            chain.push(graph.new_athunk(move |h| {
+                h.add_edge(below);
+                1.0 + h.compute(below, h.args).unwrap_or(f64::NAN)
+            }));
+        }
+
+        graph.set_max_depth(Some(3));
+        assert_eq!(
+            Err(AdaptonError::DepthLimit {
+                path: chain[1..].iter().rev().copied().collect()
+            }),
+            graph.compute(chain[4], &[])
+        );
+        assert_eq!(Ok(2.0), graph.compute(chain[2], &[]));
+
+        graph.set_max_depth(None);
+        graph.set_max_nodes(Some(6));
+        assert_eq!(Ok(4.0), graph.compute(chain[4], &[]));
+        assert_eq!(
+            Err(AdaptonError::NodeLimit {
+                limit: 6,
+                path: vec![chain[4], chain[3]]
+            }),
+            graph.compute(chain[4], &[1.0])
+        );
+    }
+
+    #[test]
+    fn compact_memo() {
+        let mut graph = Graph::new();
+
+        let r = graph.new_aref(2.0);
+        let a = graph.new_athunk_n(move |h, [x, y]| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap() * x + y
+        });
+        assert_eq!(Ok(5.0), graph.compute_n(a, [2.0, 1.0]));
+        graph.set_compact_memo(a).unwrap();
+
+        graph.assert_recomputes(&[(a.id(), 1)], |g| {
+            assert_eq!(Ok(5.0), g.compute_n(a, [2.0, 1.0]));
+            assert_eq!(Ok(7.0), g.compute_n(a, [3.0, 1.0]));
+        });
+        graph.update_aref(r, 3.0);
+        assert_eq!(Ok(7.0), graph.compute_n(a, [2.0, 1.0]));
+        assert_eq!(Some(3), graph.version(a));
+    }
+
+    #[test]
+    fn observe_delta() {
+        use std::rc::Rc;
+
+        let mut graph = Graph::new();
+
+        let r = graph.new_aref(1.0);
+        let a = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap() * 10.0
+        });
+        let total = Rc::new(Cell::new(0.0));
+        let t = total.clone();
+        let start = graph
+            .observe_delta(a, &[], move |d| t.set(t.get() + d))
+            .unwrap();
+        total.set(start);
+        let count = Rc::new(Cell::new(0));
+        let c = count.clone();
+        graph
+            .observe_with_diff(
+                a,
+                &[],
+                |old, new| new > old,
+                move |up| {
+                    if up {
+                        c.set(c.get() + 1);
+                    }
+                },
+            )
+            .unwrap();
+
+        for v in [3.0, 2.0, 5.0] {
+            graph.update_aref(r, v);
+            graph.stabilize().unwrap();
+        }
+        assert_eq!(50.0, total.get());
+        assert_eq!(2, count.get());
+    }
+
+    #[test]
+    fn compute_timeout() {
+        let mut graph = Graph::new();
+
+        let r = graph.new_aref(1.0);
+        let slow: Vec<AThunkID> = (0..3)
+            .map(|_| {
+                graph.new_athunk(move |h| {
+                    std::thread::sleep(Duration::from_millis(20));
+                    h.add_edge(r);
+                    h.compute(r, &[]).unwrap()
+                })
+            })
+            .collect();
+        let deps = slow.clone();
+        let sum = graph.new_athunk(move |h| {
+            deps.iter()
+                .map(|&d| {
+                    h.add_edge(d);
+                    h.compute(d, &[]).unwrap_or(0.0)
+                })
+                .sum()
+        });
+
+        match graph.compute_timeout(sum, &[], Duration::from_millis(30)) {
+            Err(AdaptonError::Timeout { recomputed, .. }) => assert!(recomputed >= 2),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+        // The finished thunks aren't run again.
+        assert_eq!(Some(1), graph.version(slow[0]));
+        assert_eq!(
+            Ok(3.0),
+            graph.compute_timeout(sum, &[], Duration::from_secs(10))
+        );
+        assert_eq!(Some(1), graph.version(slow[0]));
+    }
+
+    #[test]
+    fn compute_with_report() {
+        let mut graph = Graph::new();
+
+        let r1 = graph.new_aref(1.0);
+        let r2 = graph.new_aref(2.0);
+        let a = graph.new_athunk(move |h| {
+            h.add_edge(r1);
+            h.compute(r1, &[]).unwrap()
+        });
+        let b = graph.new_athunk(move |h| {
+            h.add_edge(a);
+            h.add_edge(r2);
+            h.compute(a, &[]).unwrap() + h.compute(r2, &[]).unwrap()
+        });
+
+        let report = graph.compute_with_report(b, &[]).unwrap();
+        assert_eq!(3.0, report.value);
+        assert!(!report.was_cache_hit);
+        assert_eq!((4, 4), (report.nodes_recomputed, report.nodes_visited));
+
+        let report = graph.compute_with_report(b, &[]).unwrap();
+        assert!(report.was_cache_hit);
+        assert_eq!((0, 1), (report.nodes_recomputed, report.nodes_visited));
+
+        graph.update_aref(r2, 3.0);
+        let report = graph.compute_with_report(b, &[]).unwrap();
+        assert_eq!(4.0, report.value);
+        assert_eq!((2, 3), (report.nodes_recomputed, report.nodes_visited));
+    }
+
+    #[test]
+    fn non_incremental() {
+        let mut graph = Graph::new();
+        graph.set_cutoff(Cutoff::Exact);
+
+        let r = graph.new_aref(1.0);
+        let a = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap() * 2.0
+        });
+        assert_eq!(Ok(2.0), graph.compute(a, &[]));
+
+        graph.set_incremental(false);
+        graph.assert_recomputes(&[(a, 2), (r.id(), 2)], |g| {
+            assert_eq!(Ok(2.0), g.compute(a, &[]));
+            assert_eq!(Ok(2.0), g.compute(a, &[]));
+        });
+        graph.update_aref(r, 3.0);
+        assert_eq!(Ok(6.0), graph.compute(a, &[]));
+
+        graph.set_incremental(true);
+        graph.assert_recomputes(&[], |g| {
+            assert_eq!(Ok(6.0), g.compute(a, &[]));
+        });
+    }
+
+    #[test]
+    fn labels() {
+        let mut graph = Graph::new();
+
+        let r = graph.aref_entry("rate").or_insert(0.5);
+        let a = graph.new_athunk(|_| 1.0);
+        assert_eq!(Some("rate"), graph.label(r));
+        assert_eq!(None, graph.label(a));
+        graph.set_label(a, "one");
+        assert_eq!("node 1 (\"one\")", graph.node_name(a).to_string());
+    }
+
+    #[test]
+    fn explain_error() {
+        let mut graph = Graph::new();
+
+        let r = graph.aref_entry("gross").or_insert(1.0);
+        let net = graph.new_athunk(move |h| h.compute(r, &[]).unwrap_or_default());
+        graph.annotate(net, "net of discounts");
+        graph.set_strict_tracking(true);
+        let e = graph.compute(net, &[]).unwrap_err();
+        assert_eq!(vec![net, r.id()], e.nodes());
+        assert_eq!(
+            "node 1 computed node 0 without adding an edge to it\n  \
+             node 1: net of discounts\n  \
+             node 0 (\"gross\")",
+            graph.explain_error(&e)
+        );
+        assert_eq!(Some("net of discounts"), graph.annotation(net));
+    }
+
+    #[test]
+    fn compute_uncached() {
+        let mut graph = Graph::new();
+
+        let r = graph.new_aref(2.0);
+        let sub = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap() * 2.0
+        });
+        // Reads something the graph doesn't know about, so its memoized value goes stale.
+        let outside = Rc::new(Cell::new(1.0));
+        let o = outside.clone();
+        let a = graph.new_athunk(move |h| {
+            h.add_edge(sub);
+            h.compute(sub, &[]).unwrap() + o.get() * h.args[0]
+        });
+        assert_eq!(Ok(5.0), graph.compute(a, &[1.0]));
+        assert_eq!(Ok(6.0), graph.compute(a, &[2.0]));
+
+        outside.set(10.0);
+        assert_eq!(Ok(5.0), graph.compute(a, &[1.0]));
+        graph.assert_recomputes(&[(a, 1)], |g| {
+            assert_eq!(Ok(14.0), g.compute_uncached(a, &[1.0]));
+            assert_eq!(Ok(14.0), g.compute(a, &[1.0]));
+            // Other arguments keep their memoized values.
+            assert_eq!(Ok(6.0), g.compute(a, &[2.0]));
+        });
+        assert_eq!(Ok(2.0), graph.compute_uncached(r, &[]));
+
+        // Dirtying that was put off reaches the node first, like it does for `compute`.
+        graph.set_dirtying_budget(Some(1));
+        graph.update_aref(r, 3.0);
+        graph.assert_recomputes(&[(r.id(), 1), (sub, 1), (a, 2)], |g| {
+            assert_eq!(Ok(16.0), g.compute_uncached(a, &[1.0]));
+            assert_eq!(Ok(26.0), g.compute(a, &[2.0]));
+            assert_eq!(Ok(16.0), g.compute(a, &[1.0]));
+        });
+    }
+
+    #[test]
+    fn keyed_nodes() {
+        let build = |order: &[u64]| {
+            let mut graph = Graph::new();
+            for &key in order {
+                graph.new_athunk_keyed(key, move |_| key as f64);
+            }
+            graph
+        };
+        let (g1, g2) = (build(&[1, 2, 3]), build(&[3, 1, 2]));
+        for key in 1..=3 {
+            let (id1, id2) = (g1.node_by_key(key).unwrap(), g2.node_by_key(key).unwrap());
+            assert_eq!(g1.compute(id1, &[]), g2.compute(id2, &[]));
+            assert_eq!(Some(key), g2.key(id2));
+        }
+        assert_eq!(None, g1.node_by_key(4));
+    }
+
+    #[test]
+    fn force_all() {
+        let mut graph = Graph::new();
+        graph.set_strict_tracking(true);
+
+        let inputs: Vec<AThunkID> = (0..4).map(|i| graph.new_aref(i as f64).id()).collect();
+        let scale = graph.new_athunk(|h| h.args[0] * 10.0);
+        let ids = inputs.clone();
+        let sum = graph.new_athunk(move |h| {
+            h.add_edges(&ids);
+            let vals: Vec<f64> = ids.iter().map(|&id| h.compute(id, &[]).unwrap()).collect();
+            let scaled = h.force_all(&[(scale, &[1.0]), (scale, &[2.0])]).unwrap();
+            vals.iter().sum::<f64>() + scaled.iter().sum::<f64>()
+        });
+        assert_eq!(Ok(36.0), graph.compute(sum, &[]));
+
+        graph.update_aref(ARefID(inputs[3]), 13.0);
+        assert_eq!(Ok(46.0), graph.compute(sum, &[]));
+    }
+
+    #[test]
+    fn trace() {
+        let mut graph = Graph::new();
+
+        let flag = graph.new_aref(1.0);
+        let (x, y) = (graph.new_aref(2.0), graph.new_aref(3.0));
+        let a = graph.new_athunk(move |h| {
+            h.add_edges(&[flag.id(), x.id(), y.id()]);
+            if h.compute(flag, &[]).unwrap() > 0.0 {
+                h.compute(y, &[]).unwrap() - h.compute(x, &[]).unwrap() - h.compute(y, &[]).unwrap()
+            } else {
+                h.compute(x, &[]).unwrap()
+            }
+        });
+        assert_eq!(Some(vec![]), graph.trace(a));
+        assert_eq!(Ok(-2.0), graph.compute(a, &[]));
+        assert_eq!(Some(vec![flag.id(), y.id(), x.id()]), graph.trace(a));
+        graph.update_aref(flag, 0.0);
+        assert_eq!(Ok(2.0), graph.compute(a, &[]));
+        assert_eq!(Some(vec![flag.id(), x.id()]), graph.trace(a));
+    }
+
+    #[test]
+    fn handle_memo() {
+        let mut graph = Graph::new();
+
+        let calls = Rc::new(Cell::new(0));
+        let c = calls.clone();
+        let r = graph.new_aref(3.0);
+        let a = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            let r = h.compute(r, &[]).unwrap();
+            let table: Vec<f64> = h.memo("table", || {
+                c.set(c.get() + 1);
+                (0..10).map(|i| i as f64 * r).collect()
+            });
+            table[h.args[0] as usize]
+        });
+        assert_eq!(Ok(6.0), graph.compute(a, &[2.0]));
+        assert_eq!(Ok(9.0), graph.compute(a, &[3.0]));
+        assert_eq!(1, calls.get());
+
+        graph.update_aref(r, 4.0);
+        assert_eq!(Ok(8.0), graph.compute(a, &[2.0]));
+        assert_eq!(2, calls.get());
+    }
+
+    #[test]
+    fn handle_memo_keys_with_equal_hashes() {
+        #[derive(PartialEq, Eq)]
+        struct Key(u32);
+        impl Hash for Key {
+            fn hash<H: Hasher>(&self, _: &mut H) {}
+        }
+
+        let mut graph = Graph::new();
+        let a = graph.new_athunk(|h| {
+            let x = h.memo(Key(1), || 1.0);
+            let y = h.memo(Key(2), || 2.0);
+            let z = h.memo(Key(1), || 3.0);
+            x + 10.0 * y + 100.0 * z
+        });
+        assert_eq!(Ok(121.0), graph.compute(a, &[]));
+    }
+
+    #[test]
+    fn env() {
+        struct Rates {
+            names: Vec<&'static str>,
+            values: HashMap<&'static str, f64>,
+        }
+
+        let mut graph = Graph::new();
+        let amount = graph.new_aref(10.0);
+        let a = graph.new_athunk(move |h| {
+            h.add_edge(amount);
+            let rates: &Rates = h.env().unwrap();
+            let name = rates.names[h.args[0] as usize];
+            h.compute(amount, &[]).unwrap() * rates.values[name]
+        });
+        assert_eq!(None, graph.env::<Rates>().map(|r| r.names.len()));
+
+        let rates = |usd| Rates {
+            names: vec!["eur", "usd"],
+            values: vec![("eur", 1.0), ("usd", usd)].into_iter().collect(),
+        };
+        graph.set_env(rates(1.5));
+        assert_eq!(Ok(15.0), graph.compute(a, &[1.0]));
+        graph.set_env(rates(2.0));
+        assert_eq!(Ok(20.0), graph.compute(a, &[1.0]));
+        assert_eq!(Ok(10.0), graph.compute(a, &[0.0]));
+    }
+
+    #[test]
+    fn compute_with_ctx() {
+        struct Prices(HashMap<u64, f64>);
+
+        let mut graph = Graph::new();
+        let item = graph.new_athunk(|h| {
+            let prices: &Prices = h.ctx().expect("needs prices");
+            prices.0[&(h.args[0] as u64)]
+        });
+        let total = graph.new_athunk(move |h| {
+            h.add_edge(item);
+            h.compute(item, &[1.0]).unwrap() + h.compute(item, &[2.0]).unwrap()
+        });
+
+        let prices = Prices(vec![(1, 2.5), (2, 4.0)].into_iter().collect());
+        assert_eq!(Ok(6.5), graph.compute_with_ctx(total, &[], &prices));
+        // Memoized values don't need the context again.
+        assert_eq!(Ok(6.5), graph.compute(total, &[]));
+        assert_eq!(Ok(4.0), graph.compute(item, &[2.0]));
+    }
+
+    #[test]
+    fn borrow_conflict() {
+        let graph = Rc::new(RefCell::new(Graph::new()));
+        let r = graph.borrow_mut().aref_entry("r").or_insert(1.0);
+        let seen = Rc::new(RefCell::new(None));
+
+        let (g, s) = (Rc::downgrade(&graph), seen.clone());
+        graph
+            .borrow_mut()
+            .observe(r, &[], move |_| {
+                // Stabilizing from inside an observer has to notify observers again.
+                *s.borrow_mut() = g.upgrade().unwrap().borrow().stabilize().err();
+            })
+            .unwrap();
+        graph.borrow_mut().update_aref(r, 2.0);
+        graph.borrow().stabilize().unwrap();
+
+        let conflict = AdaptonError::BorrowConflict {
+            node: Some(r.id()),
+            label: Some("r".to_string()),
+            during: "notify observers",
+        };
+        assert_eq!(
+            "can't notify observers while node 0 (\"r\") is using it",
+            conflict.to_string()
+        );
+        assert_eq!(Some(conflict), seen.borrow_mut().take());
+    }
+
+    #[test]
+    fn disable() {
+        let mut graph = Graph::new();
+
+        let r = graph.new_aref(1.0);
+        let a = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap() + 1.0
+        });
+        let b = graph.new_athunk(move |h| {
+            h.add_edge(a);
+            h.compute(a, &[]).unwrap_or(0.0) * 2.0
+        });
+        assert_eq!(Ok(4.0), graph.compute(b, &[]));
+
+        graph.disable(a);
+        assert!(graph.is_disabled(a));
+        assert_eq!(Err(AdaptonError::Disabled(a)), graph.compute(a, &[]));
+        // `b` keeps its value, since `a` no longer passes on invalidation.
+        graph.assert_recomputes(&[(r.id(), 1)], |g| {
+            g.update_aref(r, 5.0);
+            assert_eq!(Ok(5.0), g.compute(r, &[]));
+            assert_eq!(Ok(4.0), g.compute(b, &[]));
+        });
+    }
+
+    #[test]
+    fn revision() {
+        let mut graph = Graph::new();
+
+        let (x, y) = (graph.new_aref(1.0), graph.new_aref(2.0));
+        let a = graph.new_athunk(move |h| {
+            h.add_edge(x);
+            h.compute(x, &[]).unwrap()
+        });
+        graph.compute(a, &[]).unwrap();
+        let seen = graph.revision();
+        assert_eq!(seen, graph.revision());
+
+        graph.update_aref(y, 3.0);
+        assert!(graph.revision() > seen);
+        graph.update_aref(x, 3.0);
+        graph.compute(a, &[]).unwrap();
+        assert_eq!(Some(graph.revision()), graph.changed_at(a));
+        // `y` has never been computed.
+        assert_eq!(Some(0), graph.changed_at(y));
+    }
+
+    #[test]
+    fn stabilize_is_deterministic() {
+        let run = || {
+            let mut graph = Graph::new();
+            let log = Rc::new(RefCell::new(Vec::new()));
+            let r = graph.new_aref(1.0);
+            let mut nodes = Vec::new();
+            for i in 0..20 {
+                let log = log.clone();
+                let deps = nodes.clone();
+                nodes.push(graph.new_athunk(move |h| {
+                    log.borrow_mut().push((i, h.args[0]));
+                    h.add_edge(r);
+                    let mut v = h.compute(r, &[]).unwrap();
+                    for &d in deps.iter().rev().take(3) {
+                        h.add_edge(d);
+                        v += h.compute(d, &[1.0]).unwrap();
+                    }
+                    v
+                }));
+            }
+            for &n in nodes.iter() {
+                for arg in 1..5 {
+                    graph.compute(n, &[arg as f64]).unwrap();
+                }
+            }
+            graph.update_aref(r, 2.0);
+            log.borrow_mut().clear();
+            let changed = graph.stabilize().unwrap();
+            let order = log.borrow().clone();
+            (changed.len(), order)
+        };
+        let first = run();
+        for _ in 0..10 {
+            assert_eq!(first, run());
+        }
+    }
+
+    #[test]
+    fn stabilize_is_deterministic_across_workers() {
+        let run = |workers| {
+            let mut graph = Graph::new();
+            graph.set_heavy_workers(workers);
+            let r = graph.new_aref(1.0);
+            let mut nodes = vec![r.id()];
+            // Alternating layers of heavy and ordinary nodes, each reading the last few before it.
+            for i in 0..24 {
+                let deps: Vec<_> = nodes.iter().rev().take(3).copied().collect();
+                let node = if i % 3 == 2 {
+                    graph.new_athunk_with_deps(&deps, |_, vals| vals.iter().sum::<f64>() / 2.0)
+                } else {
+                    graph.new_heavy_athunk(&deps, move |args, vals| {
+                        let v: f64 = vals.iter().map(|v| v * (i + 1) as f64).sum();
+                        (v + args.iter().sum::<f64>()) % 1000.0
+                    })
+                };
+                nodes.push(node);
+            }
+            let args: [&[f64]; 3] = [&[], &[1.0], &[2.5]];
+            let values = |graph: &Graph| -> Vec<f64> {
+                nodes
+                    .iter()
+                    .flat_map(|&n| args.iter().map(move |a| (n, *a)))
+                    .filter(|&(n, a)| n != r.id() || a.is_empty())
+                    .map(|(n, a)| graph.compute(n, a).unwrap())
+                    .collect()
+            };
+            values(&graph);
+            let mut runs = Vec::new();
+            for v in [2.0, 2.0, 7.0, -3.0] {
+                graph.update_aref(r, v);
+                let mut changed: Vec<_> = graph.stabilize().unwrap().into_iter().collect();
+                changed.sort();
+                runs.push((changed, values(&graph)));
+            }
+            runs
+        };
+        let serial = run(0);
+        // Setting the same value again changes nothing.
+        assert!(serial[1].0.is_empty());
+        assert!(serial[3].0.len() > 20);
+        for workers in [1, 2, 4] {
+            assert_eq!(serial, run(workers));
+        }
+    }
+
+    #[test]
+    fn compute_mut() {
+        let mut graph = Graph::new();
+        let r = graph.new_aref(2.0);
+        let runs = Rc::new(Cell::new(0));
+        let counter = runs.clone();
+        let sq = graph.new_athunk(move |h| {
+            counter.set(counter.get() + 1);
+            h.add_edge(r);
+            let x = h.compute(r, &[]).unwrap();
+            x * x + h.args.iter().sum::<f64>()
+        });
+        assert_eq!(Ok(4.0), graph.compute_mut(sq, &[]));
+        assert_eq!(Ok(4.0), graph.compute_mut(sq, &[]));
+        assert_eq!(Ok(5.0), graph.compute_mut(sq, &[1.0]));
+        assert_eq!(2, runs.get());
 
-        assert_eq!(Some(10.0), graph.compute(a2, &[]));
-        assert_eq!(Some(14.0), graph.compute(a3, &[1.0]));
-        assert_eq!(Some(7.0), graph.compute(a3, &[2.0]));
-        assert_eq!(Some(14.0), graph.compute(a3, &[1.0]));
-        assert_eq!(Some(7.0), graph.compute(a3, &[2.0]));
+        graph.update_aref(r, 3.0);
+        assert_eq!(Ok(9.0), graph.compute_mut(sq, &[]));
+        assert_eq!(Ok(9.0), graph.compute(sq, &[]));
+        assert_eq!(3, runs.get());
+        assert_eq!(
+            Err(AdaptonError::NoSuchNode(AThunkID(99))),
+            graph.compute_mut(AThunkID(99), &[])
+        );
     }
 }
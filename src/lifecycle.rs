@@ -0,0 +1,43 @@
+//! Log records for the lifecycle of nodes, emitted with the `log` crate when the `log` feature is
+//! enabled. Creation, recomputation and eviction are logged at debug level, and dirtying, which
+//! happens far more often, at trace level.
+
+use crate::{AThunkID, Graph};
+use std::fmt;
+
+#[cfg(feature = "log")]
+macro_rules! lifecycle {
+    ($level:ident, $($arg:tt)*) => {
+        log::$level!(target: "micro_adapton_rs", $($arg)*)
+    };
+}
+
+// Without the feature the arguments are never evaluated, so logging costs nothing.
+#[cfg(not(feature = "log"))]
+macro_rules! lifecycle {
+    ($level:ident, $($arg:tt)*) => {};
+}
+
+/// A node's index along with its label, if it has one.
+pub(crate) struct NodeName<'a> {
+    id: AThunkID,
+    label: Option<&'a str>,
+}
+
+impl fmt::Display for NodeName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.label {
+            Some(label) => write!(f, "{} ({:?})", self.id, label),
+            None => self.id.fmt(f),
+        }
+    }
+}
+
+impl<T, S> Graph<T, S> {
+    pub(crate) fn node_name(&self, id: AThunkID) -> NodeName<'_> {
+        NodeName {
+            id,
+            label: self.athunks[id.0].label.as_deref(),
+        }
+    }
+}
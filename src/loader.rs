@@ -0,0 +1,169 @@
+//! Creating nodes from several threads at once, for loading a large graph in parallel.
+//!
+//! A graph can't be shared between threads, so a `Loader` stands in for it: IDs are handed out by
+//! an atomic counter, and the nodes' definitions are kept in shards picked by ID, so threads only
+//! contend when they happen to create nodes in the same shard at the same moment. Once the
+//! threads are done, `Graph::install` creates the nodes for real, in ID order.
+
+use crate::{ARefID, AThunkID, Graph, IdRemap, MemoHasher};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+const SHARDS: usize = 16;
+
+type LoadedFn = Box<dyn Fn(&[f64], &[f64]) -> f64 + Send + Sync>;
+
+enum Definition {
+    Aref(f64),
+    Thunk(Vec<AThunkID>, LoadedFn),
+}
+
+struct Shared {
+    next: AtomicUsize,
+    installed: AtomicBool,
+    shards: Vec<Mutex<Vec<(AThunkID, Definition)>>>,
+}
+
+/// Creates nodes for a graph from any number of threads, created by `Graph::loader`. Clones share
+/// the same nodes.
+#[derive(Clone)]
+pub struct Loader {
+    shared: Arc<Shared>,
+}
+
+impl Loader {
+    pub fn new_aref(&self, val: f64) -> ARefID {
+        ARefID(self.define(Definition::Aref(val)))
+    }
+
+    /// Like `Graph::new_athunk_with_deps`, except that `f` is only given the node's arguments and
+    /// its dependencies' values, since it has to be sent between threads. The dependencies can be
+    /// nodes of the graph or of the loader.
+    pub fn new_athunk_with_deps(
+        &self,
+        deps: &[AThunkID],
+        f: impl Fn(&[f64], &[f64]) -> f64 + Send + Sync + 'static,
+    ) -> AThunkID {
+        self.define(Definition::Thunk(deps.to_vec(), Box::new(f)))
+    }
+
+    fn define(&self, definition: Definition) -> AThunkID {
+        let id = AThunkID(self.shared.next.fetch_add(1, Ordering::Relaxed));
+        let mut shard = self.shared.shards[id.0 % SHARDS].lock().unwrap();
+        // Checked under the lock, so that a node is either installed or refused.
+        assert!(
+            !self.shared.installed.load(Ordering::Relaxed),
+            "the loader has already been installed"
+        );
+        shard.push((id, definition));
+        id
+    }
+}
+
+impl<S: MemoHasher> Graph<f64, S> {
+    /// A loader whose nodes are given the IDs that come after the graph's last node.
+    pub fn loader(&self) -> Loader {
+        let next = self.athunks.iter().next_back().map_or(0, |(i, _)| i + 1);
+        Loader {
+            shared: Arc::new(Shared {
+                next: AtomicUsize::new(next),
+                installed: AtomicBool::new(false),
+                shards: (0..SHARDS).map(|_| Mutex::new(Vec::new())).collect(),
+            }),
+        }
+    }
+
+    /// Creates every node defined through `loader` or its clones, which can't be used to create
+    /// any more afterwards. The nodes keep the IDs the loader gave them unless the graph had
+    /// vacant slots or created nodes of its own since the loader was made, in which case the
+    /// returned remap says where each one ended up.
+    pub fn install(&mut self, loader: Loader) -> IdRemap {
+        let mut definitions = Vec::new();
+        for shard in loader.shared.shards.iter() {
+            let mut shard = shard.lock().unwrap();
+            loader.shared.installed.store(true, Ordering::Relaxed);
+            definitions.append(&mut shard);
+        }
+        // A node's dependencies were created before it, so they come first.
+        definitions.sort_unstable_by_key(|(id, _)| *id);
+
+        let mut moved = HashMap::new();
+        for (id, definition) in definitions {
+            let installed = match definition {
+                Definition::Aref(val) => self.new_aref(val).id(),
+                Definition::Thunk(deps, f) => {
+                    let deps: Vec<AThunkID> = deps
+                        .iter()
+                        .map(|d| moved.get(d).copied().unwrap_or(*d))
+                        .collect();
+                    self.new_athunk_with_deps(&deps, move |h, vals| f(h.args, vals))
+                }
+            };
+            if installed != id {
+                moved.insert(id, installed);
+            }
+        }
+        IdRemap {
+            moved: Rc::new(moved),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Graph;
+    use std::thread;
+
+    #[test]
+    fn parallel_loading() {
+        let mut graph = Graph::new();
+        let base = graph.new_aref(1.0);
+        let loader = graph.loader();
+        let workers: Vec<_> = (0..4)
+            .map(|t| {
+                let loader = loader.clone();
+                thread::spawn(move || {
+                    (0..250)
+                        .map(|i| {
+                            let r = loader.new_aref((t * 250 + i) as f64);
+                            let sum = loader
+                                .new_athunk_with_deps(&[base.id(), r.id()], |_, v| v[0] + v[1]);
+                            (r, sum)
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let loaded: Vec<_> = workers
+            .into_iter()
+            .flat_map(|w| w.join().unwrap())
+            .collect();
+
+        assert!(graph.install(loader).is_empty());
+        assert_eq!(2001, graph.iter().count());
+        for (i, &(r, sum)) in loaded.iter().enumerate() {
+            assert_eq!(Ok(i as f64 + 1.0), graph.compute(sum, &[]));
+            graph.update_aref(r, 0.0);
+            assert_eq!(Ok(1.0), graph.compute(sum, &[]));
+        }
+    }
+
+    #[test]
+    fn installing_around_new_nodes() {
+        let mut graph = Graph::new();
+        let loader = graph.loader();
+        let r = loader.new_aref(2.0);
+        let double = loader.new_athunk_with_deps(&[r.id()], |_, v| v[0] * 2.0);
+        // Takes the ID the loader gave `r`.
+        let own = graph.new_aref(5.0);
+
+        let remap = graph.install(loader.clone());
+        assert_eq!(2, remap.len());
+        assert_eq!(Ok(5.0), graph.compute(own, &[]));
+        graph.update_aref(remap.get_aref(r), 3.0);
+        assert_eq!(Ok(6.0), graph.compute(remap.get(double), &[]));
+        assert!(std::panic::catch_unwind(|| loader.new_aref(0.0)).is_err());
+    }
+}
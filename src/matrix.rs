@@ -0,0 +1,154 @@
+//! Matrices, with combinators that only recompute the rows a change affects.
+//!
+//! Like `AVec`, a matrix is stored as an aref per element rather than as a single value, so that
+//! `ndarray` arrays only appear at the edges: when creating, updating or reading a matrix.
+
+use crate::{AThunkID, AVec, AdaptonError, Graph, Handle, MemoHasher};
+use ndarray::{Array1, Array2, ArrayView1, ArrayView2};
+use std::rc::Rc;
+
+#[derive(Clone, Debug)]
+pub struct AMatrix {
+    rows: Rc<[AVec]>,
+    cols: usize,
+}
+
+impl<S: MemoHasher> Graph<f64, S> {
+    pub fn new_amatrix(&mut self, vals: ArrayView2<f64>) -> AMatrix {
+        AMatrix {
+            rows: vals
+                .rows()
+                .into_iter()
+                .map(|row| self.new_avec(&row.to_vec()))
+                .collect(),
+            cols: vals.ncols(),
+        }
+    }
+
+    /// Replaces row `i`, which only invalidates the computations that read from that row.
+    pub fn update_row(&mut self, matrix: &AMatrix, i: usize, row: ArrayView1<f64>) {
+        assert_eq!(matrix.cols, row.len(), "row has the wrong length");
+        let changes: Vec<(usize, f64)> = row.iter().copied().enumerate().collect();
+        self.update_avec(&matrix.rows[i], &changes);
+    }
+
+    /// One node per row, computing `f` of that row.
+    pub fn map_rows(
+        &mut self,
+        matrix: &AMatrix,
+        f: impl Fn(ArrayView1<f64>) -> f64 + 'static,
+    ) -> Vec<AThunkID> {
+        let f = Rc::new(f);
+        matrix
+            .rows
+            .iter()
+            .map(|row| {
+                let (row, f) = (row.clone(), f.clone());
+                self.new_athunk(move |h: &mut Handle<f64, S>| match row.read(h, ..) {
+                    Ok(vals) => f(Array1::from(vals).view()),
+                    // The handle remembers the error, so this value is never seen.
+                    Err(_) => f64::NAN,
+                })
+            })
+            .collect()
+    }
+
+    /// One node per element of the product `a * b`. Changing a row of `a` only invalidates the
+    /// same row of the product.
+    pub fn matmul(&mut self, a: &AMatrix, b: &AMatrix) -> Array2<AThunkID> {
+        assert_eq!(a.cols, b.rows.len(), "matrices can't be multiplied");
+        let (rows, cols) = (a.rows.len(), b.cols);
+        let mut ids = Vec::with_capacity(rows * cols);
+        for i in 0..rows {
+            for j in 0..cols {
+                let (row, b) = (a.rows[i].clone(), b.clone());
+                ids.push(self.new_athunk(move |h: &mut Handle<f64, S>| {
+                    let row = row.read(h, ..).unwrap_or_default();
+                    let col = b.read_col(h, j).unwrap_or_default();
+                    row.iter().zip(col.iter()).map(|(x, y)| x * y).sum()
+                }));
+            }
+        }
+        Array2::from_shape_vec((rows, cols), ids).unwrap()
+    }
+
+    /// The values of a matrix of nodes, such as one returned by `matmul`.
+    pub fn compute_array(&self, ids: &Array2<AThunkID>) -> Result<Array2<f64>, AdaptonError> {
+        let vals: Result<Vec<f64>, AdaptonError> =
+            ids.iter().map(|&id| self.compute(id, &[])).collect();
+        Ok(Array2::from_shape_vec(ids.raw_dim(), vals?).unwrap())
+    }
+}
+
+impl AMatrix {
+    /// The number of rows and columns.
+    pub fn shape(&self) -> (usize, usize) {
+        (self.rows.len(), self.cols)
+    }
+
+    pub fn row(&self, i: usize) -> &AVec {
+        &self.rows[i]
+    }
+
+    /// Adds edges to just the elements of column `j` and returns their values.
+    pub fn read_col<S: MemoHasher>(
+        &self,
+        h: &mut Handle<f64, S>,
+        j: usize,
+    ) -> Result<Vec<f64>, AdaptonError> {
+        self.rows
+            .iter()
+            .map(|row| {
+                h.add_edge(row.element(j));
+                h.compute(row.element(j), &[])
+            })
+            .collect()
+    }
+
+    pub fn values<S: MemoHasher>(
+        &self,
+        graph: &Graph<f64, S>,
+    ) -> Result<Array2<f64>, AdaptonError> {
+        let mut vals = Vec::with_capacity(self.rows.len() * self.cols);
+        for row in self.rows.iter() {
+            vals.extend(row.values(graph)?);
+        }
+        Ok(Array2::from_shape_vec((self.rows.len(), self.cols), vals).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Graph;
+    use ndarray::{array, Axis};
+
+    #[test]
+    fn matmul() {
+        let mut graph = Graph::new();
+
+        let a = graph.new_amatrix(array![[1.0, 2.0], [3.0, 4.0]].view());
+        let b = graph.new_amatrix(array![[5.0, 6.0], [7.0, 8.0]].view());
+        let product = graph.matmul(&a, &b);
+        let sums = graph.map_rows(&a, |row| row.sum());
+        assert_eq!(
+            Ok(array![[19.0, 22.0], [43.0, 50.0]]),
+            graph.compute_array(&product)
+        );
+        assert_eq!(Ok(7.0), graph.compute(sums[1], &[]));
+
+        let first_row: Vec<_> = product.index_axis(Axis(0), 0).iter().copied().collect();
+        let mut expected: Vec<_> = first_row.iter().map(|&id| (id, 1)).collect();
+        expected.extend((0..2).map(|j| (a.row(0).element(j).id(), 1)));
+        expected.push((sums[0], 1));
+        graph.assert_recomputes(&expected, |g| {
+            g.update_row(&a, 0, array![0.0, 1.0].view());
+            assert_eq!(
+                Ok(array![[7.0, 8.0], [43.0, 50.0]]),
+                g.compute_array(&product)
+            );
+            assert_eq!(Ok(1.0), g.compute(sums[0], &[]));
+            assert_eq!(Ok(7.0), g.compute(sums[1], &[]));
+        });
+        assert_eq!(Ok(array![[0.0, 1.0], [3.0, 4.0]]), a.values(&graph));
+    }
+}
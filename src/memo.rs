@@ -0,0 +1,387 @@
+//! Memo tables, keyed by the bits of the arguments their values were computed with.
+
+use crate::hashing::{KeyHash, MemoHasher};
+use crate::memory::entry_bytes;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::mem::size_of;
+use std::ops::Deref;
+
+// How many arguments `with_key` can make a key for without allocating.
+const INLINE_KEY: usize = 8;
+
+// Calls `f` with the key for `args`, built on the stack unless there are a lot of them, for lookups
+// that would otherwise allocate a key just to throw it away.
+pub(crate) fn with_key<R>(args: &[f64], f: impl FnOnce(&[u64]) -> R) -> R {
+    if args.len() > INLINE_KEY {
+        let key: Vec<u64> = args.iter().map(|a| a.to_bits()).collect();
+        return f(&key);
+    }
+    let mut key = [0; INLINE_KEY];
+    for (k, a) in key.iter_mut().zip(args) {
+        *k = a.to_bits();
+    }
+    f(&key[..args.len()])
+}
+
+// How many arguments an `ArgKey` holds without allocating. Most thunks take fewer.
+const INLINE_ARGS: usize = 3;
+
+// The bits of a node's arguments, kept inline when there are few enough of them so that most
+// memo entries don't need an allocation of their own.
+#[derive(Clone, Debug)]
+pub(crate) enum ArgKey {
+    Inline(usize, [u64; INLINE_ARGS]),
+    Spilled(Box<[u64]>),
+}
+
+impl ArgKey {
+    pub(crate) fn new(args: &[f64]) -> Self {
+        if args.len() > INLINE_ARGS {
+            return ArgKey::Spilled(args.iter().map(|a| a.to_bits()).collect());
+        }
+        let mut bits = [0; INLINE_ARGS];
+        for (b, a) in bits.iter_mut().zip(args) {
+            *b = a.to_bits();
+        }
+        ArgKey::Inline(args.len(), bits)
+    }
+
+    pub(crate) fn from_bits(key: &[u64]) -> Self {
+        if key.len() > INLINE_ARGS {
+            return ArgKey::Spilled(key.into());
+        }
+        let mut bits = [0; INLINE_ARGS];
+        bits[..key.len()].copy_from_slice(key);
+        ArgKey::Inline(key.len(), bits)
+    }
+
+    // How many of the key's bits are stored outside of it.
+    fn spilled(&self) -> usize {
+        match self {
+            ArgKey::Inline(..) => 0,
+            ArgKey::Spilled(bits) => bits.len(),
+        }
+    }
+}
+
+impl Deref for ArgKey {
+    type Target = [u64];
+
+    fn deref(&self) -> &[u64] {
+        match self {
+            ArgKey::Inline(len, bits) => &bits[..*len],
+            ArgKey::Spilled(bits) => bits,
+        }
+    }
+}
+
+// Keys are compared and hashed as slices, so that tables can be searched with a plain `&[u64]`.
+impl Borrow<[u64]> for ArgKey {
+    fn borrow(&self) -> &[u64] {
+        self
+    }
+}
+
+impl PartialEq for ArgKey {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for ArgKey {}
+
+impl Hash for ArgKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Memo<T = f64, S = KeyHash> {
+    storage: Storage<T, S>,
+}
+
+#[derive(Clone)]
+enum Storage<T, S> {
+    Hashed(HashMap<ArgKey, T, S>),
+    // Every key is `width` long, and they're all stored back to back in sorted order, with
+    // `values[i]` belonging to the `i`th key. A lookup is a binary search and an insertion has to
+    // shift everything after it, but an entry costs no more than its key and value.
+    Compact {
+        width: usize,
+        keys: Vec<u64>,
+        values: Vec<T>,
+    },
+}
+
+impl<T, S: Default> Default for Memo<T, S> {
+    fn default() -> Self {
+        Self {
+            storage: Storage::Hashed(HashMap::default()),
+        }
+    }
+}
+
+impl<T: Copy, S: MemoHasher> Memo<T, S> {
+    pub(crate) fn compact(width: usize) -> Self {
+        Self {
+            storage: Storage::Compact {
+                width,
+                keys: Vec::new(),
+                values: Vec::new(),
+            },
+        }
+    }
+
+    pub(crate) fn is_compact(&self) -> bool {
+        matches!(self.storage, Storage::Compact { .. })
+    }
+
+    pub(crate) fn get(&self, key: &[u64]) -> Option<T> {
+        match &self.storage {
+            Storage::Hashed(map) => map.get(key).copied(),
+            Storage::Compact { values, .. } => self.search(key).ok().map(|i| values[i]),
+        }
+    }
+
+    pub(crate) fn contains_key(&self, key: &[u64]) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub(crate) fn insert(&mut self, key: &[u64], val: T) {
+        let position = self.search(key);
+        match &mut self.storage {
+            Storage::Hashed(map) => {
+                map.insert(ArgKey::from_bits(key), val);
+            }
+            Storage::Compact {
+                width,
+                keys,
+                values,
+            } => match position {
+                Ok(i) => values[i] = val,
+                Err(i) => {
+                    assert_eq!(*width, key.len(), "compact memo keys must all be as long");
+                    keys.splice(i * *width..i * *width, key.iter().copied());
+                    values.insert(i, val);
+                }
+            },
+        }
+    }
+
+    pub(crate) fn remove(&mut self, key: &[u64]) -> Option<T> {
+        let position = self.search(key);
+        match &mut self.storage {
+            Storage::Hashed(map) => map.remove(key),
+            Storage::Compact {
+                width,
+                keys,
+                values,
+            } => {
+                let i = position.ok()?;
+                keys.drain(i * *width..(i + 1) * *width);
+                Some(values.remove(i))
+            }
+        }
+    }
+
+    /// Empties the table, keeping what it has allocated for the entries that replace these.
+    pub(crate) fn clear(&mut self) {
+        match &mut self.storage {
+            Storage::Hashed(map) => map.clear(),
+            Storage::Compact { keys, values, .. } => {
+                keys.clear();
+                values.clear();
+            }
+        }
+    }
+
+    /// Empties the table, returning its old contents and keeping the way it's stored.
+    pub(crate) fn take(&mut self) -> Memo<T, S> {
+        let empty = match &self.storage {
+            Storage::Hashed(_) => Memo::default(),
+            Storage::Compact { width, .. } => Memo::compact(*width),
+        };
+        std::mem::replace(self, empty)
+    }
+
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        match &mut self.storage {
+            Storage::Hashed(map) => map.reserve(additional),
+            Storage::Compact {
+                width,
+                keys,
+                values,
+            } => {
+                keys.reserve(additional * *width);
+                values.reserve(additional);
+            }
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match &self.storage {
+            Storage::Hashed(map) => map.len(),
+            Storage::Compact { values, .. } => values.len(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn iter(&self) -> Box<dyn Iterator<Item = (&[u64], T)> + '_> {
+        match &self.storage {
+            Storage::Hashed(map) => Box::new(map.iter().map(|(k, &v)| (&k[..], v))),
+            // `chunks` refuses a width of zero, and a zero-width table has at most one entry.
+            Storage::Compact {
+                width: 0, values, ..
+            } => Box::new(values.iter().map(|&v| (&[][..], v))),
+            Storage::Compact {
+                width,
+                keys,
+                values,
+            } => Box::new(keys.chunks(*width).zip(values.iter().copied())),
+        }
+    }
+
+    pub(crate) fn extend(&mut self, other: Memo<T, S>) {
+        // Moving a whole table into an empty one that's stored the same way is just a move, so
+        // dirtying a node costs the same however many values it has memoized.
+        let same_storage = match (&self.storage, &other.storage) {
+            (Storage::Hashed(_), Storage::Hashed(_)) => true,
+            (Storage::Compact { width: a, .. }, Storage::Compact { width: b, .. }) => a == b,
+            _ => false,
+        };
+        if self.is_empty() && same_storage {
+            *self = other;
+            return;
+        }
+        for (key, val) in other.iter() {
+            self.insert(key, val);
+        }
+    }
+
+    /// Roughly how many bytes the table's entries take up.
+    pub(crate) fn bytes(&self) -> usize {
+        match &self.storage {
+            Storage::Hashed(map) => map
+                .keys()
+                .map(|key| entry_bytes::<(ArgKey, T)>(key.spilled()))
+                .sum(),
+            Storage::Compact { width, values, .. } => {
+                values.len() * (width * size_of::<u64>() + size_of::<T>())
+            }
+        }
+    }
+
+    // Where the key is in a compact table, or where it would go. Hashed tables have no positions.
+    fn search(&self, key: &[u64]) -> Result<usize, usize> {
+        match &self.storage {
+            Storage::Hashed(_) => Err(0),
+            Storage::Compact { width, values, .. } if *width == 0 => {
+                if values.is_empty() {
+                    Err(0)
+                } else {
+                    Ok(0)
+                }
+            }
+            Storage::Compact { width, keys, .. } => {
+                let (mut lo, mut hi) = (0, keys.len() / width);
+                while lo < hi {
+                    let mid = (lo + hi) / 2;
+                    match keys[mid * width..(mid + 1) * width].cmp(key) {
+                        std::cmp::Ordering::Less => lo = mid + 1,
+                        std::cmp::Ordering::Greater => hi = mid,
+                        std::cmp::Ordering::Equal => return Ok(mid),
+                    }
+                }
+                Err(lo)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Memo;
+
+    #[test]
+    fn compact_matches_hashed() {
+        let mut hashed: Memo = Memo::default();
+        let mut compact: Memo = Memo::compact(2);
+
+        let mut seed = 3u64;
+        for i in 0..200 {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            let key = vec![(seed >> 60), (seed >> 58) & 3];
+            if i % 3 == 0 {
+                assert_eq!(hashed.remove(&key), compact.remove(&key));
+            } else {
+                hashed.insert(&key, i as f64);
+                compact.insert(&key, i as f64);
+            }
+            assert_eq!(hashed.len(), compact.len());
+        }
+        for (key, val) in hashed.iter() {
+            assert_eq!(Some(val), compact.get(key));
+        }
+        assert!(compact.bytes() < hashed.bytes());
+    }
+
+    #[test]
+    fn extend() {
+        let mut hashed: Memo = Memo::default();
+        let mut compact: Memo = Memo::compact(1);
+        for i in 0..10 {
+            hashed.insert(&[i], i as f64);
+            compact.insert(&[i], i as f64);
+        }
+
+        let mut empty = Memo::compact(1);
+        empty.extend(compact.clone());
+        assert!(empty.is_compact());
+        assert_eq!(10, empty.len());
+
+        // Tables stored differently are merged entry by entry, keeping the way they're stored.
+        let mut empty = Memo::compact(1);
+        empty.extend(hashed.clone());
+        assert!(empty.is_compact());
+        assert_eq!(Some(3.0), empty.get(&[3]));
+
+        let mut partial = Memo::default();
+        partial.insert(&[20], 20.0);
+        partial.extend(hashed);
+        assert_eq!(11, partial.len());
+    }
+
+    #[test]
+    fn with_key() {
+        let mut memo: Memo = Memo::default();
+        for len in [0, 1, 8, 9, 20] {
+            let args: Vec<f64> = (0..len).map(|i| i as f64 - 0.5).collect();
+            memo.insert(&super::ArgKey::new(&args), len as f64);
+        }
+        for len in [0, 1, 8, 9, 20] {
+            let args: Vec<f64> = (0..len).map(|i| i as f64 - 0.5).collect();
+            assert_eq!(
+                Some(len as f64),
+                super::with_key(&args, |key| memo.get(key))
+            );
+        }
+        assert_eq!(None, super::with_key(&[1.0], |key| memo.get(key)));
+    }
+
+    #[test]
+    fn arg_keys() {
+        let short = super::ArgKey::new(&[1.0, 2.0]);
+        let long = super::ArgKey::new(&[1.0, 2.0, 3.0, 4.0]);
+        assert!(matches!(short, super::ArgKey::Inline(2, _)));
+        assert!(matches!(long, super::ArgKey::Spilled(_)));
+        assert_eq!(&[1f64.to_bits(), 2f64.to_bits()][..], &*short);
+        assert_eq!(4, long.len());
+        assert_eq!(short, super::ArgKey::from_bits(&short));
+    }
+}
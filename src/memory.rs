@@ -0,0 +1,190 @@
+//! Approximate memory accounting, and shedding cached values to stay under a budget.
+
+use crate::{AThunk, AThunkID, ArgsId, Graph, MemoHasher, Reads, Scalar, Sources};
+use std::collections::HashMap;
+use std::mem::size_of;
+
+impl<T: Scalar, S: MemoHasher> Graph<T, S> {
+    /// Roughly how many bytes the graph's memo tables, edge sets and other cached values take up.
+    /// Thunks and whatever they capture aren't counted.
+    pub fn memory_usage(&self) -> usize {
+        let nodes: usize = self
+            .athunks
+            .iter()
+            .map(|(_, athunk)| athunk_bytes(athunk))
+            .sum();
+        let shared: usize = self
+            .shared_results
+            .borrow()
+            .keys()
+            .map(|(_, vals, args)| {
+                entry_bytes::<(u64, Vec<u64>, Vec<u64>, T)>(vals.len() + args.len())
+            })
+            .sum();
+        nodes + shared
+    }
+
+    /// Throws away every cached value and marks every node dirty, leaving the graph as though
+    /// nothing had been computed yet apart from its nodes and edges.
+    pub fn clear_caches(&mut self) {
+        self.shared_results.get_mut().clear();
+        for (_, athunk) in self.athunks.iter_mut() {
+            athunk.result.get_mut().clear();
+            athunk.stale.get_mut().clear();
+            athunk.unverified.get_mut().clear();
+            athunk.reads.get_mut().clear();
+            athunk.sources.get_mut().clear();
+            athunk.helpers.get_mut().clear();
+            athunk.clean.set(false);
+        }
+        self.instances.set(0);
+    }
+
+    /// Throws away cached values until `memory_usage` is at most `target_bytes`, or there's nothing
+    /// left to throw away, and returns the resulting usage. Nothing is lost besides time: anything
+    /// thrown away is recomputed when it's next needed.
+    ///
+    /// The least useful values go first. Shared results go before the bookkeeping for cutoff and
+    /// provenance, which goes before the invalidated values kept for `stabilize`, which go before
+    /// the memoized values themselves. Within each of those, nodes with a lower cost hint go
+    /// first, and nodes without one count as costing nothing.
+    pub fn trim_caches(&mut self, target_bytes: usize) -> usize {
+        let mut usage = self.memory_usage();
+        if usage <= target_bytes {
+            return usage;
+        }
+        self.shared_results.get_mut().clear();
+        usage = self.memory_usage();
+
+        // The cheapest values to recompute go first, when there are hints to tell which they are.
+        let mut athunks: Vec<&AThunk<T, S>> = self.athunks.iter().map(|(_, a)| a).collect();
+        athunks.sort_by(|a, b| a.cost.unwrap_or(0.0).total_cmp(&b.cost.unwrap_or(0.0)));
+        for step in 0..3 {
+            for athunk in athunks.iter() {
+                if usage <= target_bytes {
+                    return usage;
+                }
+                let before = athunk_bytes(athunk);
+                let dropped = match step {
+                    0 => {
+                        athunk.forfeit_unverified();
+                        athunk.reads.take();
+                        athunk.sources.take();
+                        athunk.helpers.take();
+                        0
+                    }
+                    1 => athunk.stale.borrow_mut().take().len(),
+                    _ => athunk.result.borrow_mut().take().len(),
+                };
+                if dropped > 0 {
+                    lifecycle!(
+                        debug,
+                        "evicted {} memo entries from {}",
+                        dropped,
+                        self.node_name(athunk.id)
+                    );
+                }
+                self.instances
+                    .set(self.instances.get().saturating_sub(dropped));
+                usage -= before - athunk_bytes(athunk);
+            }
+        }
+        usage
+    }
+}
+
+// The size of a hash table entry of type `T` whose key has `key_len` heap-allocated u64s, plus a
+// byte of hash table overhead.
+pub(crate) fn entry_bytes<T>(key_len: usize) -> usize {
+    size_of::<T>() + key_len * size_of::<u64>() + 1
+}
+
+fn reads_bytes<T, S>(reads: &Reads<T, S>) -> usize {
+    reads.keys().len() * entry_bytes::<((AThunkID, ArgsId), (T, u64))>(0)
+}
+
+fn sources_bytes<S>(sources: &HashMap<ArgsId, Sources, S>) -> usize {
+    sources
+        .values()
+        .map(|read| {
+            entry_bytes::<(ArgsId, Sources)>(0) + read.len() * size_of::<(AThunkID, ArgsId)>()
+        })
+        .sum()
+}
+
+fn athunk_bytes<T: Scalar, S: MemoHasher>(athunk: &AThunk<T, S>) -> usize {
+    let edges = athunk.sub_computations.borrow().len() + athunk.super_computations.borrow().len();
+    size_of::<AThunk<T, S>>()
+        + athunk.result.borrow().bytes()
+        + athunk.stale.borrow().bytes()
+        + athunk.unverified.borrow().bytes()
+        + reads_bytes(&athunk.reads.borrow())
+        + sources_bytes(&athunk.sources.borrow())
+        + edges * entry_bytes::<usize>(0)
+        + athunk.trace.borrow().len() * size_of::<AThunkID>()
+        + athunk
+            .shared_fn
+            .as_ref()
+            .map_or(0, |(_, params)| params.len() * size_of::<f64>())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Graph;
+
+    #[test]
+    fn trim_caches() {
+        let mut graph = Graph::new();
+
+        let r = graph.new_aref(2.0);
+        let a = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap() * h.args[0]
+        });
+        let empty = graph.memory_usage();
+        for i in 0..100 {
+            graph.compute(a, &[i as f64]).unwrap();
+        }
+        let full = graph.memory_usage();
+        assert!(full > empty);
+
+        assert_eq!(full, graph.trim_caches(full));
+        // Only the edges are left.
+        let trimmed = graph.trim_caches(0);
+        assert_eq!(trimmed, graph.memory_usage());
+        assert!(trimmed - empty < (full - empty) / 10);
+
+        // Everything still works, it just has to be computed again.
+        graph.assert_recomputes(&[(r.id(), 1), (a, 1)], |g| {
+            assert_eq!(Ok(6.0), g.compute(a, &[3.0]));
+        });
+        graph.update_aref(r, 3.0);
+        assert_eq!(Ok(9.0), graph.compute(a, &[3.0]));
+    }
+
+    #[test]
+    fn clear_caches() {
+        let mut graph = Graph::new();
+
+        let r = graph.new_aref(2.0);
+        let a = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap() * h.args[0]
+        });
+        assert_eq!(Ok(4.0), graph.compute(a, &[2.0]));
+        graph.update_aref(r, 3.0);
+        assert_eq!(Ok(9.0), graph.compute(a, &[3.0]));
+        let full = graph.memory_usage();
+
+        graph.clear_caches();
+        assert!(graph.iter().all(|(_, node)| !node.clean));
+        assert!(graph.memory_usage() < full);
+        assert_eq!(graph.memory_usage(), graph.trim_caches(0));
+        graph.assert_recomputes(&[(r.id(), 1), (a, 1)], |g| {
+            assert_eq!(Ok(6.0), g.compute(a, &[2.0]));
+        });
+        // The edge is still there.
+        graph.update_aref(r, 1.0);
+        assert_eq!(Ok(2.0), graph.compute(a, &[2.0]));
+    }
+}
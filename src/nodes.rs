@@ -0,0 +1,559 @@
+//! Looking over the nodes of a graph as a whole, removing them, and compacting what's left.
+
+use crate::memo::Memo;
+use crate::{
+    ARefID, AThunk, AThunkID, ArgsId, Edges, Graph, MemoHasher, Reads, Scalar, Sources, TraceEvent,
+};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+
+/// The nodes that `Graph::compact` moved, and where to.
+#[derive(Clone, Debug, Default)]
+pub struct IdRemap {
+    pub(crate) moved: Rc<HashMap<AThunkID, AThunkID>>,
+}
+
+impl IdRemap {
+    /// The node's new ID, which is its old one if it wasn't moved.
+    pub fn get(&self, id: AThunkID) -> AThunkID {
+        self.moved.get(&id).copied().unwrap_or(id)
+    }
+
+    pub fn get_aref(&self, id: ARefID) -> ARefID {
+        ARefID(self.get(id.id()))
+    }
+
+    /// How many nodes were moved.
+    pub fn len(&self) -> usize {
+        self.moved.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.moved.is_empty()
+    }
+}
+
+/// What a node looks like from the outside.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NodeInfo<'a> {
+    pub label: Option<&'a str>,
+    pub annotation: Option<&'a str>,
+    pub clean: bool,
+    /// How many argument lists the node has an up to date value for.
+    pub n_cached_results: usize,
+    /// How many nodes this node depends on.
+    pub fan_in: usize,
+    /// How many nodes depend on this node.
+    pub fan_out: usize,
+}
+
+impl<T: Scalar, S: MemoHasher> Graph<T, S> {
+    /// Every node in the graph, in order of creation unless nodes have been removed.
+    pub fn iter(&self) -> impl Iterator<Item = (AThunkID, NodeInfo<'_>)> + '_ {
+        self.athunks
+            .iter()
+            .map(|(i, athunk)| (AThunkID(i), athunk.info()))
+    }
+
+    /// Nodes that nothing depends on and that have never been run, which in a graph built on the
+    /// fly usually means nodes that were created and then forgotten about.
+    pub fn find_orphans(&self) -> Vec<AThunkID> {
+        self.athunks
+            .iter()
+            .filter(|(_, athunk)| {
+                athunk.version.get() == 0 && athunk.super_computations.borrow().is_empty()
+            })
+            .map(|(i, _)| AThunkID(i))
+            .collect()
+    }
+
+    /// Nodes that none of `roots` depend on, directly or otherwise, as of their last runs.
+    pub fn unreachable_from(&self, roots: &[AThunkID]) -> Vec<AThunkID> {
+        let mut reached: HashSet<_, S> = HashSet::default();
+        let mut next = roots.to_vec();
+        while let Some(id) = next.pop() {
+            if let Some(athunk) = self.athunks.get(id.0) {
+                if reached.insert(id) {
+                    next.extend(athunk.sub_computations.borrow().iter());
+                }
+            }
+        }
+        self.athunks
+            .iter()
+            .map(|(i, _)| AThunkID(i))
+            .filter(|id| !reached.contains(id))
+            .collect()
+    }
+
+    /// Moves nodes into the slots left behind by removed ones, so that the graph takes no more
+    /// space than it would have if the removed nodes had never existed. Every ID the graph keeps
+    /// track of is updated, and so are those captured by thunks, but anything else holding on to a
+    /// moved node's ID needs to look up its new one in the returned remapping. That includes lists
+    /// and vectors created before the compaction.
+    pub fn compact(&mut self) -> IdRemap {
+        self.finish_dirtying();
+        let mut moved = HashMap::new();
+        self.athunks.compact(|athunk, from, to| {
+            athunk.id = AThunkID(to);
+            moved.insert(AThunkID(from), AThunkID(to));
+            true
+        });
+        let remap = IdRemap {
+            moved: Rc::new(moved),
+        };
+        if remap.is_empty() {
+            return remap;
+        }
+
+        let ids = |ids: &mut Edges| *ids = ids.iter().map(|id| remap.get(id)).collect();
+        for (_, athunk) in self.athunks.iter_mut() {
+            ids(athunk.sub_computations.get_mut());
+            ids(athunk.super_computations.get_mut());
+            ids(athunk.waiting.get_mut());
+            let reads = athunk.reads.get_mut();
+            *reads = reads
+                .drain()
+                .map(|((id, key), read)| ((remap.get(id), key), read))
+                .collect();
+            for id in athunk
+                .trace
+                .get_mut()
+                .iter_mut()
+                .chain(athunk.duplicate_edges.get_mut().iter_mut())
+            {
+                *id = remap.get(*id);
+            }
+            for sources in athunk.sources.get_mut().values_mut() {
+                for (id, _) in sources.iter_mut() {
+                    *id = remap.get(*id);
+                }
+            }
+            athunk.op = athunk.op.map(|op| op.map(|id| remap.get(id)));
+            let poisoned = athunk.poisoned.get_mut();
+            *poisoned = poisoned.map(|id| remap.get(id));
+        }
+        for id in self.named_arefs.values_mut() {
+            *id = remap.get_aref(*id);
+        }
+        for id in self.keyed_nodes.values_mut() {
+            *id = remap.get(*id);
+        }
+        self.heavy = self
+            .heavy
+            .drain()
+            .map(|(id, mut heavy)| {
+                for dep in heavy.deps.iter_mut() {
+                    *dep = remap.get(*dep);
+                }
+                (remap.get(id), heavy)
+            })
+            .collect();
+        if let Some(profile) = self.profile.as_mut() {
+            *profile.get_mut() = profile
+                .get_mut()
+                .drain()
+                .map(|(stack, time)| (stack.into_iter().map(|id| remap.get(id)).collect(), time))
+                .collect();
+        }
+        for (id, _) in self.prefetches.get_mut().iter_mut() {
+            *id = remap.get(*id);
+        }
+        if let Some(recording) = self.recording.as_mut() {
+            for event in recording.get_mut().iter_mut() {
+                match event {
+                    TraceEvent::Demand(id, _)
+                    | TraceEvent::Hit(id, _)
+                    | TraceEvent::Recompute(id, _)
+                    | TraceEvent::Dirty(id) => *id = remap.get(*id),
+                }
+            }
+        }
+        self.inputs.remap(|id| Some(remap.get_aref(id)));
+        self.seed_node = self.seed_node.map(|id| remap.get_aref(id));
+        for observer in self.observers.get_mut().iter_mut() {
+            observer.id = remap.get(observer.id);
+        }
+        self.last_propagation_report = None;
+        self.remaps.push(remap.clone());
+        remap
+    }
+
+    /// Removes every node. The memo tables, edge sets and other collections the nodes had
+    /// allocated are kept and handed to the nodes created after, for graphs that are torn down
+    /// and built up again many times over.
+    pub fn clear(&mut self) {
+        self.remove_nodes(|_, _| false, true);
+    }
+
+    /// Removes every node for which `keep` returns false, along with their edges, names and
+    /// observers. Nodes that are kept must not compute any of the removed ones, since they aren't
+    /// dirtied by the removal. Removed nodes' IDs will be reused by nodes created later on.
+    pub fn retain(&mut self, keep: impl FnMut(AThunkID, &NodeInfo) -> bool) {
+        self.remove_nodes(keep, false);
+    }
+
+    fn remove_nodes(&mut self, mut keep: impl FnMut(AThunkID, &NodeInfo) -> bool, reuse: bool) {
+        self.finish_dirtying();
+        let removed: HashSet<AThunkID, S> = self
+            .athunks
+            .iter()
+            .map(|(i, athunk)| (AThunkID(i), athunk))
+            .filter(|(id, athunk)| !keep(*id, &athunk.info()))
+            .map(|(id, _)| id)
+            .collect();
+        if removed.is_empty() {
+            return;
+        }
+
+        for &id in removed.iter() {
+            let mut athunk = self.athunks.remove(id.0);
+            let dropped = athunk.result.borrow().len() + athunk.stale_len();
+            self.instances
+                .set(self.instances.get().saturating_sub(dropped));
+            for sub in athunk.sub_computations.get_mut().iter() {
+                if let Some(sub) = self.athunks.get(sub.0) {
+                    sub.super_computations.borrow_mut().remove(&id);
+                }
+            }
+            for sup in athunk.super_computations.get_mut().iter() {
+                if let Some(sup) = self.athunks.get(sup.0) {
+                    sup.sub_computations.borrow_mut().remove(&id);
+                    sup.reads.borrow_mut().retain(|(read, _), _| *read != id);
+                    sup.trace.borrow_mut().retain(|&traced| traced != id);
+                    sup.duplicate_edges.borrow_mut().retain(|&sub| sub != id);
+                    for sources in sup.sources.borrow_mut().values_mut() {
+                        sources.retain(|(source, _)| *source != id);
+                    }
+                }
+            }
+            if reuse {
+                self.spares.push(Spare::from(athunk));
+            }
+        }
+
+        for (_, athunk) in self.athunks.iter_mut() {
+            athunk.waiting.get_mut().retain(|id| !removed.contains(id));
+            let poisoned = athunk.poisoned.get_mut();
+            if matches!(poisoned, Some(id) if removed.contains(id)) {
+                *poisoned = None;
+            }
+        }
+        self.named_arefs.retain(|_, id| !removed.contains(&id.id()));
+        self.keyed_nodes.retain(|_, id| !removed.contains(id));
+        self.heavy.retain(|id, _| !removed.contains(id));
+        self.prefetches
+            .get_mut()
+            .retain(|(id, _)| !removed.contains(id));
+        self.inputs
+            .remap(|id| Some(id).filter(|id| !removed.contains(&id.id())));
+        if let Some(profile) = self.profile.as_mut() {
+            profile
+                .get_mut()
+                .retain(|stack, _| stack.iter().all(|id| !removed.contains(id)));
+        }
+        if let Some(recording) = self.recording.as_mut() {
+            recording
+                .get_mut()
+                .retain(|event| !removed.contains(&event.id()));
+        }
+        if matches!(self.seed_node, Some(id) if removed.contains(&id.id())) {
+            self.seed_node = None;
+        }
+        self.observers
+            .get_mut()
+            .retain(|o| !removed.contains(&o.id));
+    }
+}
+
+// The collections a removed node had allocated, emptied out for a new node to fill.
+pub(crate) struct Spare<T, S> {
+    result: Memo<T, S>,
+    reads: Reads<T, S>,
+    sources: HashMap<ArgsId, Sources, S>,
+    sub_computations: Edges,
+    super_computations: Edges,
+    trace: Vec<AThunkID>,
+}
+
+impl<T: Scalar, S: MemoHasher> From<AThunk<T, S>> for Spare<T, S> {
+    fn from(athunk: AThunk<T, S>) -> Self {
+        let mut result = athunk.result.into_inner();
+        if result.is_compact() {
+            // Whatever has the node's ID next may not have its arity.
+            result = Memo::default();
+        }
+        result.clear();
+        let mut spare = Spare {
+            result,
+            reads: athunk.reads.into_inner(),
+            sources: athunk.sources.into_inner(),
+            sub_computations: athunk.sub_computations.into_inner(),
+            super_computations: athunk.super_computations.into_inner(),
+            trace: athunk.trace.into_inner(),
+        };
+        spare.reads.clear();
+        spare.sources.clear();
+        spare.sub_computations.clear();
+        spare.super_computations.clear();
+        spare.trace.clear();
+        spare
+    }
+}
+
+impl<T: Scalar, S: MemoHasher> AThunk<T, S> {
+    pub(crate) fn reuse(&mut self, spare: Spare<T, S>) {
+        *self.result.get_mut() = spare.result;
+        *self.reads.get_mut() = spare.reads;
+        *self.sources.get_mut() = spare.sources;
+        *self.sub_computations.get_mut() = spare.sub_computations;
+        *self.super_computations.get_mut() = spare.super_computations;
+        *self.trace.get_mut() = spare.trace;
+    }
+}
+
+/// A summary of the graph, or with `{:#?}`, every node along with its label, whether it's clean,
+/// and its edges.
+impl<T: Scalar, S: MemoHasher> fmt::Debug for Graph<T, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let alternate = f.alternate();
+        let mut graph = f.debug_struct("Graph");
+        graph
+            .field("nodes", &self.athunks.len())
+            .field("dirty", &self.iter().filter(|(_, n)| !n.clean).count())
+            .field("instances", &self.instances.get());
+        if alternate {
+            let nodes: Vec<NodeDump<T, S>> =
+                self.athunks.iter().map(|(_, a)| NodeDump(a)).collect();
+            graph.field("node", &nodes);
+        }
+        graph.finish()
+    }
+}
+
+struct NodeDump<'a, T, S>(&'a AThunk<T, S>);
+
+impl<T: Scalar, S: MemoHasher> fmt::Debug for NodeDump<'_, T, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let athunk = self.0;
+        let sorted = |ids: &Edges| ids.iter().map(|id| id.0).collect::<BTreeSet<_>>();
+        let mut node = f.debug_struct("Node");
+        node.field("id", &athunk.id.0);
+        if let Some(label) = &athunk.label {
+            node.field("label", label);
+        }
+        if let Some(annotation) = &athunk.annotation {
+            node.field("annotation", annotation);
+        }
+        node.field("clean", &athunk.clean.get())
+            .field("subs", &sorted(&athunk.sub_computations.borrow()))
+            .field("supers", &sorted(&athunk.super_computations.borrow()))
+            .finish()
+    }
+}
+
+impl<T: Scalar, S: MemoHasher> AThunk<T, S> {
+    pub(crate) fn info(&self) -> NodeInfo<'_> {
+        NodeInfo {
+            label: self.label.as_deref(),
+            annotation: self.annotation.as_deref(),
+            clean: self.clean.get(),
+            n_cached_results: self.result.borrow().len(),
+            fan_in: self.sub_computations.borrow().len(),
+            fan_out: self.super_computations.borrow().len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::edges::EdgeSet;
+    use crate::{Graph, NodeInfo};
+
+    #[test]
+    fn retain() {
+        let mut graph = Graph::new();
+
+        let r = graph.aref_entry("r").or_insert(1.0);
+        let old = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap() + 1.0
+        });
+        graph.set_label(old, "old");
+        let new = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap() * 10.0
+        });
+        assert_eq!(Ok(2.0), graph.compute(old, &[]));
+        assert_eq!(Ok(10.0), graph.compute(new, &[]));
+        graph
+            .observe(old, &[], |_| panic!("removed nodes aren't observed"))
+            .unwrap();
+
+        graph.retain(|_, info| info.label != Some("old"));
+        assert_eq!(None, graph.version(old));
+        assert_eq!(Some(r), graph.aref_named("r"));
+        graph.update_aref(r, 2.0);
+        assert!(graph.stabilize().is_ok());
+        assert_eq!(Ok(20.0), graph.compute(new, &[]));
+
+        graph.retain(|id, _| id == new);
+        assert_eq!(None, graph.aref_named("r"));
+        // `new` still has its value, it just can't be recomputed any more.
+        assert_eq!(Ok(20.0), graph.compute(new, &[]));
+    }
+
+    #[test]
+    fn iter() {
+        let mut graph = Graph::new();
+
+        let r = graph.new_aref(1.0);
+        let a = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap() * h.args[0]
+        });
+        graph.set_label(a, "a");
+        graph.compute(a, &[1.0]).unwrap();
+        graph.compute(a, &[2.0]).unwrap();
+
+        let nodes: Vec<_> = graph.iter().collect();
+        assert_eq!(2, nodes.len());
+        assert_eq!(
+            (r.id(), 0, 1),
+            (nodes[0].0, nodes[0].1.fan_in, nodes[0].1.fan_out)
+        );
+        let info = NodeInfo {
+            label: Some("a"),
+            annotation: None,
+            clean: true,
+            n_cached_results: 2,
+            fan_in: 1,
+            fan_out: 0,
+        };
+        assert_eq!((a, info), nodes[1]);
+
+        graph.update_aref(r, 2.0);
+        let dirty: Vec<_> = graph.iter().filter(|(_, n)| !n.clean).collect();
+        assert_eq!(2, dirty.len());
+    }
+
+    #[test]
+    fn orphans() {
+        let mut graph = Graph::new();
+
+        let r = graph.new_aref(1.0);
+        let a = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap()
+        });
+        let forgotten = graph.new_athunk(|_| 0.0);
+        let unused = graph.new_aref(2.0);
+        // Edges are only added when thunks run.
+        assert_eq!(
+            vec![r.id(), a, forgotten, unused.id()],
+            graph.find_orphans()
+        );
+
+        graph.compute(a, &[]).unwrap();
+        assert_eq!(vec![forgotten, unused.id()], graph.find_orphans());
+        assert_eq!(vec![forgotten, unused.id()], graph.unreachable_from(&[a]));
+        assert_eq!(
+            vec![r.id(), a, unused.id()],
+            graph.unreachable_from(&[forgotten])
+        );
+    }
+
+    #[test]
+    fn compact() {
+        let mut graph = Graph::new();
+
+        let garbage: Vec<_> = (0..3).map(|_| graph.new_athunk(|_| 0.0)).collect();
+        let r = graph.aref_entry("r").or_insert(1.0);
+        let a = graph.new_athunk_keyed(7, move |h| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap() + 1.0
+        });
+        assert_eq!(Ok(2.0), graph.compute(a, &[]));
+        graph.retain(|id, _| !garbage.contains(&id));
+
+        let remap = graph.compact();
+        assert_eq!(2, remap.len());
+        assert_eq!(Some(remap.get(a)), graph.node_by_key(7));
+        let (r, a) = (remap.get_aref(r), remap.get(a));
+        assert_eq!(Some(r), graph.aref_named("r"));
+        let mut ids: Vec<_> = graph.iter().map(|(id, _)| id.0).collect();
+        ids.sort();
+        assert_eq!(vec![0, 1], ids);
+
+        // `a`'s thunk still refers to `r` by its old ID, which is translated for it.
+        graph.update_aref(r, 2.0);
+        assert_eq!(Ok(3.0), graph.compute(a, &[]));
+        let b = graph.new_athunk(move |h| {
+            h.add_edge(a);
+            h.compute(a, &[]).unwrap() * 2.0
+        });
+        assert_eq!(Ok(6.0), graph.compute(b, &[]));
+        assert!(graph.compact().is_empty());
+    }
+
+    #[test]
+    fn debug() {
+        let mut graph = Graph::new();
+
+        let r = graph.aref_entry("r").or_insert(1.0);
+        let a = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap()
+        });
+        graph.annotate(a, "the same as r");
+        graph.compute(a, &[]).unwrap();
+        assert_eq!("node 1", a.to_string());
+        assert_eq!("node 0", r.to_string());
+        assert_eq!(
+            "Graph { nodes: 2, dirty: 0, instances: 2 }",
+            format!("{:?}", graph)
+        );
+        let dump = format!("{:#?}", graph);
+        assert!(dump.contains("label: \"r\""));
+        assert!(dump.contains("annotation: \"the same as r\""));
+        assert!(dump.contains("clean: true"));
+        assert!(dump.contains("supers: {"));
+    }
+
+    #[test]
+    fn clear() {
+        let mut graph = Graph::new();
+        for round in 0..3 {
+            let r = graph.new_aref(round as f64);
+            let ids: Vec<_> = (0..100)
+                .map(|i| {
+                    graph.new_athunk(move |h| {
+                        h.add_edge(r);
+                        h.compute(r, &[]).unwrap() + i as f64
+                    })
+                })
+                .collect();
+            for (i, &id) in ids.iter().enumerate() {
+                assert_eq!(Ok((round + i) as f64), graph.compute(id, &[]));
+            }
+            let capacity = graph.athunks.capacity();
+            graph.clear();
+            assert_eq!(0, graph.athunks.len());
+            assert_eq!(capacity, graph.athunks.capacity());
+            assert_eq!(0, graph.instances.get());
+            assert_eq!(101, graph.spares.len());
+        }
+
+        // Whichever nodes get the arefs' old edge sets keep what they had grown into, empty.
+        let ids: Vec<_> = (0..101).map(|i| graph.new_aref(i as f64)).collect();
+        assert!(graph.spares.is_empty());
+        let grown: Vec<_> = ids
+            .iter()
+            .map(|r| graph.athunks[r.id().0].super_computations.borrow())
+            .filter(|supers| !matches!(**supers, EdgeSet::Inline(..)))
+            .map(|supers| supers.len())
+            .collect();
+        assert!(!grown.is_empty() && grown.iter().all(|&len| len == 0));
+        assert_eq!(Ok(100.0), graph.compute(ids[100], &[]));
+    }
+}
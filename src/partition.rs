@@ -0,0 +1,150 @@
+//! Splitting a graph into pieces that can each be owned by a different thread or actor.
+
+use crate::{AThunkID, Graph, MemoHasher, Scalar};
+use std::collections::{HashMap, HashSet};
+
+/// One of the pieces returned by `Graph::partition`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Partition {
+    pub nodes: Vec<AThunkID>,
+    /// Nodes of this partition that nodes of later partitions depend on, and so whose changes
+    /// have to be passed on to them.
+    pub boundary: Vec<AThunkID>,
+    /// Nodes of earlier partitions that this one depends on.
+    pub inputs: Vec<AThunkID>,
+}
+
+impl<T: Scalar, S: MemoHasher> Graph<T, S> {
+    /// Splits the nodes into `k` partitions of about the same size. Partitions are consecutive
+    /// runs of a dependency order that goes one weakly connected component at a time, so
+    /// components are only split when they have to be, and a partition only ever depends on
+    /// partitions before it. Repairs can then flow from each partition to the next through its
+    /// boundary nodes.
+    pub fn partition(&self, k: usize) -> Vec<Partition> {
+        assert!(k > 0, "there must be at least one partition");
+        let mut order = Vec::with_capacity(self.athunks.len());
+        let mut visited = HashSet::default();
+        for component in self.components() {
+            for id in component {
+                self.dependency_order(id, &mut visited, &mut order);
+            }
+        }
+
+        let size = order.len().div_ceil(k);
+        let mut partitions = vec![Partition::default(); k];
+        let mut owner: HashMap<_, _, S> = HashMap::default();
+        for (i, &id) in order.iter().enumerate() {
+            let p = i / size.max(1);
+            owner.insert(id, p);
+            partitions[p].nodes.push(id);
+        }
+        let mut boundary: HashSet<_, S> = HashSet::default();
+        for (p, partition) in partitions.iter_mut().enumerate() {
+            let mut inputs: HashSet<_, S> = HashSet::default();
+            for id in partition.nodes.iter() {
+                for sub in self.athunks[id.0].sub_computations.borrow().iter() {
+                    if owner[&sub] != p {
+                        inputs.insert(sub);
+                    }
+                }
+            }
+            boundary.extend(inputs.iter().copied());
+            partition.inputs = inputs.into_iter().collect();
+            partition.inputs.sort();
+        }
+        for partition in partitions.iter_mut() {
+            partition.boundary = partition
+                .nodes
+                .iter()
+                .copied()
+                .filter(|id| boundary.contains(id))
+                .collect();
+        }
+        partitions
+    }
+
+    // Weakly connected components, each sorted by ID, in order of their lowest ID.
+    fn components(&self) -> Vec<Vec<AThunkID>> {
+        let mut seen: HashSet<_, S> = HashSet::default();
+        let mut components = Vec::new();
+        for (i, _) in self.athunks.iter() {
+            if !seen.insert(AThunkID(i)) {
+                continue;
+            }
+            let mut component = Vec::new();
+            let mut stack = vec![AThunkID(i)];
+            while let Some(id) = stack.pop() {
+                component.push(id);
+                let athunk = &self.athunks[id.0];
+                let sub = athunk.sub_computations.borrow();
+                let sup = athunk.super_computations.borrow();
+                for next in sub.iter().chain(sup.iter()) {
+                    if seen.insert(next) {
+                        stack.push(next);
+                    }
+                }
+            }
+            component.sort();
+            components.push(component);
+        }
+        components
+    }
+
+    // Post-order over sub-computations, so that every node comes after what it depends on.
+    fn dependency_order(
+        &self,
+        id: AThunkID,
+        visited: &mut HashSet<AThunkID, S>,
+        order: &mut Vec<AThunkID>,
+    ) {
+        if !visited.insert(id) {
+            return;
+        }
+        let mut subs: Vec<AThunkID> = self.athunks[id.0]
+            .sub_computations
+            .borrow()
+            .iter()
+            .collect();
+        subs.sort();
+        for s in subs {
+            self.dependency_order(s, visited, order);
+        }
+        order.push(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Graph;
+
+    #[test]
+    fn partition() {
+        let mut graph = Graph::new();
+
+        // Two independent chains of three nodes.
+        let mut chains = Vec::new();
+        for _ in 0..2 {
+            let r = graph.new_aref(1.0);
+            let a = graph.new_athunk_with_deps(&[r.id()], |_, v| v[0] + 1.0);
+            let b = graph.new_athunk_with_deps(&[a], |_, v| v[0] * 2.0);
+            chains.push((r.id(), a, b));
+        }
+
+        let halves = graph.partition(2);
+        let (x, y) = (chains[0], chains[1]);
+        assert_eq!(vec![x.0, x.1, x.2], halves[0].nodes);
+        assert_eq!(vec![y.0, y.1, y.2], halves[1].nodes);
+        assert!(halves
+            .iter()
+            .all(|p| p.boundary.is_empty() && p.inputs.is_empty()));
+
+        // A chain has to be cut somewhere once there are more partitions than chains.
+        let thirds = graph.partition(3);
+        assert_eq!(vec![x.0, x.1], thirds[0].nodes);
+        assert_eq!(vec![x.1], thirds[0].boundary);
+        assert_eq!(vec![x.2, y.0], thirds[1].nodes);
+        assert_eq!(vec![x.1], thirds[1].inputs);
+        assert_eq!(vec![y.0], thirds[1].boundary);
+        assert_eq!(vec![y.0], thirds[2].inputs);
+    }
+}
@@ -0,0 +1,72 @@
+//! Inputs that don't have a value yet, such as data still being fetched.
+
+use crate::{ARefID, AThunkID, Graph, Handle, MemoHasher, Scalar};
+use std::collections::HashSet;
+
+impl<T: Scalar, S: MemoHasher> Graph<T, S> {
+    /// An aref without a value. Computing it fails with a `Pending` error until it's given one with
+    /// `fulfill`, and so does computing anything that needs it, as long as thunks pass the error
+    /// on. Nodes that tried are remembered, whether or not they added an edge to it.
+    pub fn new_pending(&mut self) -> ARefID {
+        let id = self.new_aref(T::default());
+        self.athunks[id.id().0].pending = true;
+        id
+    }
+
+    pub fn is_pending(&self, id: impl Into<AThunkID>) -> bool {
+        self.athunks
+            .get(id.into().0)
+            .is_some_and(|athunk| athunk.pending)
+    }
+
+    /// Gives a pending aref its value, and dirties the nodes that tried to compute it in the
+    /// meantime, so that they're recomputed. Fulfilling an aref that isn't pending is the same as
+    /// updating it.
+    pub fn fulfill(&mut self, id: ARefID, val: T) {
+        let athunk = &mut self.athunks[id.id().0];
+        if !athunk.pending {
+            self.update_aref(id, val);
+            return;
+        }
+        athunk.pending = false;
+        athunk.thunk = Box::new(move |_: &mut Handle<T, S>| val);
+        self.revision += 1;
+        let waiting: HashSet<AThunkID, S> = athunk.waiting.get_mut().drain().collect();
+        for waiter in waiting {
+            self.dirty(waiter, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AdaptonError, Graph};
+
+    #[test]
+    fn pending() {
+        let mut graph = Graph::new();
+
+        let price = graph.new_pending();
+        let r = graph.new_aref(2.0);
+        let total = graph.new_athunk(move |h| {
+            h.add_edges(&[r.id(), price.id()]);
+            let r = h.compute(r, &[]).unwrap();
+            h.compute(price, &[]).unwrap_or_default() * r
+        });
+        let report = graph.new_athunk(move |h| {
+            h.add_edge(total);
+            h.compute(total, &[]).unwrap_or_default()
+        });
+        assert!(graph.is_pending(price));
+        assert_eq!(
+            Err(AdaptonError::Pending(price.id())),
+            graph.compute(report, &[])
+        );
+
+        graph.fulfill(price, 5.0);
+        assert!(!graph.is_pending(price));
+        assert_eq!(Ok(10.0), graph.compute(report, &[]));
+        graph.fulfill(price, 6.0);
+        assert_eq!(Ok(12.0), graph.compute(report, &[]));
+    }
+}
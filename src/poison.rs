@@ -0,0 +1,132 @@
+//! Marking values as bad, so that nothing is computed from them until they've been fixed.
+
+use crate::{AThunkID, AdaptonError, Graph, Handle, MemoHasher, Scalar};
+
+impl<T: Scalar, S: MemoHasher> Graph<T, S> {
+    /// With poison propagation on, a node that fails because something it computed is poisoned
+    /// fails with an `UpstreamError` naming the poisoned node, and keeps failing without being
+    /// re-run until it's dirtied. Otherwise its failure is like any other, and it's re-run every
+    /// time it's computed.
+    pub fn set_poison_propagation(&mut self, enabled: bool) {
+        self.poison_propagation = enabled;
+    }
+
+    /// Puts the node in a poisoned state, for every argument, so that computing it fails with a
+    /// `Poisoned` error until it's dirtied. Its values are thrown away, and everything that
+    /// depends on it is dirtied. Updating a poisoned aref gives it a value again.
+    pub fn poison(&mut self, id: impl Into<AThunkID>) {
+        let id = id.into();
+        let athunk = &mut self.athunks[id.0];
+        let dropped = athunk.result.get_mut().take().len() + athunk.stale_len();
+        athunk.stale.get_mut().clear();
+        athunk.unverified.get_mut().clear();
+        athunk.clean.set(true);
+        athunk.poisoned.set(Some(id));
+        self.instances
+            .set(self.instances.get().saturating_sub(dropped));
+        let supers: Vec<AThunkID> = athunk.super_computations.get_mut().iter().collect();
+        for s in supers {
+            self.dirty(s, None);
+        }
+    }
+
+    /// Dirties a poisoned node, so that it's re-run the next time it's computed.
+    pub fn unpoison(&mut self, id: impl Into<AThunkID>) {
+        self.dirty(id.into(), None);
+    }
+
+    pub fn is_poisoned(&self, id: impl Into<AThunkID>) -> bool {
+        self.athunks
+            .get(id.into().0)
+            .is_some_and(|athunk| athunk.clean.get() && athunk.poisoned.get().is_some())
+    }
+
+    // A node that is poisoned, or with poison propagation, also one that failed because of a
+    // poisoned node.
+    pub(crate) fn poison_error(&self, id: AThunkID) -> Option<AdaptonError> {
+        let athunk = &self.athunks[id.0];
+        if !athunk.clean.get() {
+            return None;
+        }
+        athunk.poisoned.get().map(|source| match source == id {
+            true => AdaptonError::Poisoned(id),
+            false => AdaptonError::UpstreamError { id, source },
+        })
+    }
+}
+
+impl<T: Scalar, S: MemoHasher> Handle<'_, T, S> {
+    /// Poisons the node being computed, for when the thunk finds it has nothing sensible to
+    /// return. The value it does return is thrown away.
+    pub fn poison(&mut self) {
+        self.poisoned = true;
+    }
+}
+
+// The node that's to blame for `error`, if it's down to a poisoned node.
+pub(crate) fn poison_source(error: &AdaptonError) -> Option<AThunkID> {
+    match *error {
+        AdaptonError::Poisoned(source) | AdaptonError::UpstreamError { source, .. } => Some(source),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AdaptonError, Graph};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn poison() {
+        let mut graph = Graph::new();
+        graph.set_poison_propagation(true);
+
+        let runs = Rc::new(Cell::new(0));
+        let counter = runs.clone();
+        let r = graph.new_aref(1.0);
+        let inverse = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            let r = h.compute(r, &[]).unwrap_or_default();
+            if r == 0.0 {
+                h.poison();
+            }
+            1.0 / r
+        });
+        let total = graph.new_athunk(move |h| {
+            counter.set(counter.get() + 1);
+            h.add_edge(inverse);
+            h.compute(inverse, &[]).unwrap_or_default() + 1.0
+        });
+        assert_eq!(Ok(2.0), graph.compute(total, &[]));
+
+        graph.update_aref(r, 0.0);
+        let upstream = AdaptonError::UpstreamError {
+            id: total,
+            source: inverse,
+        };
+        assert_eq!(Err(upstream.clone()), graph.compute(total, &[]));
+        assert_eq!(Err(upstream), graph.compute(total, &[]));
+        assert_eq!(2, runs.get());
+        assert!(graph.is_poisoned(inverse));
+        assert_eq!(
+            Err(AdaptonError::Poisoned(inverse)),
+            graph.compute(inverse, &[])
+        );
+
+        graph.update_aref(r, 2.0);
+        assert_eq!(Ok(1.5), graph.compute(total, &[]));
+
+        graph.poison(r);
+        assert_eq!(
+            Err(AdaptonError::UpstreamError {
+                id: total,
+                source: r.id()
+            }),
+            graph.compute(total, &[])
+        );
+        graph.update_aref(r, 4.0);
+        assert!(!graph.is_poisoned(r));
+        assert_eq!(Ok(1.25), graph.compute(total, &[]));
+    }
+}
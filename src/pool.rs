@@ -0,0 +1,258 @@
+//! A work-stealing pool of worker threads, and the slots that heavy nodes' values arrive in.
+//!
+//! Each worker has a queue of its own, which jobs spawned by its own jobs go on, and takes the
+//! newest job from it first. Jobs spawned from other threads go on a queue shared by every
+//! worker. A worker whose own queue is empty takes the oldest shared job, or failing that steals
+//! the oldest job from another worker, so a chain of jobs that spawn each other stays on one
+//! worker until the others run out of work.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+// What a heavy node's function returned, or the message it panicked with.
+pub(crate) type Outcome<T> = Result<T, Option<String>>;
+
+pub(crate) struct Queues {
+    shared: Mutex<VecDeque<Job>>,
+    own: Vec<Mutex<VecDeque<Job>>>,
+    // How many jobs are queued anywhere, and whether the pool has been dropped. Workers sleep on
+    // `wake` while there are none.
+    state: Mutex<(usize, bool)>,
+    wake: Condvar,
+}
+
+thread_local! {
+    // The pool the current thread is a worker of, and which worker it is.
+    static WORKER: RefCell<Option<(Arc<Queues>, usize)>> = const { RefCell::new(None) };
+}
+
+impl Queues {
+    /// Queues `job` on the current worker's own queue if it's one of this pool's, and on the
+    /// shared queue otherwise.
+    pub(crate) fn spawn(self: &Arc<Self>, job: impl FnOnce() + Send + 'static) {
+        let worker = WORKER.with(|w| match &*w.borrow() {
+            Some((queues, i)) if Arc::ptr_eq(queues, self) => Some(*i),
+            _ => None,
+        });
+        // Counted before it's queued, so that a worker can't take it and uncount it first.
+        self.state.lock().unwrap().0 += 1;
+        match worker {
+            Some(i) => self.own[i].lock().unwrap().push_back(Box::new(job)),
+            None => self.shared.lock().unwrap().push_back(Box::new(job)),
+        }
+        self.wake.notify_one();
+    }
+
+    fn take(&self, worker: usize) -> Option<Job> {
+        if let Some(job) = self.own[worker].lock().unwrap().pop_back() {
+            return Some(job);
+        }
+        if let Some(job) = self.shared.lock().unwrap().pop_front() {
+            return Some(job);
+        }
+        let others = (1..self.own.len()).map(|i| (worker + i) % self.own.len());
+        others
+            .into_iter()
+            .find_map(|i| self.own[i].lock().unwrap().pop_front())
+    }
+
+    fn work(self: Arc<Self>, worker: usize) {
+        WORKER.with(|w| *w.borrow_mut() = Some((self.clone(), worker)));
+        loop {
+            if let Some(job) = self.take(worker) {
+                self.state.lock().unwrap().0 -= 1;
+                // The job has already been handed over, so there's nothing to unwind into.
+                let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                continue;
+            }
+            let mut state = self.state.lock().unwrap();
+            // A job that was counted but not found yet is still being queued.
+            while state.0 == 0 {
+                if state.1 {
+                    WORKER.with(|w| w.borrow_mut().take());
+                    return;
+                }
+                state = self.wake.wait(state).unwrap();
+            }
+        }
+    }
+}
+
+/// A minimal work-stealing pool of worker threads, for running heavy nodes without any
+/// dependencies beyond the standard library. The threads stop once the pool is dropped and every
+/// queued job has run, and a job that panics doesn't take its worker with it.
+pub struct ThreadPool {
+    queues: Arc<Queues>,
+    workers: usize,
+}
+
+impl ThreadPool {
+    /// Starts `workers` threads, or one if `workers` is 0, since nothing sent to a pool without
+    /// any would ever run.
+    pub fn new(workers: usize) -> Self {
+        let workers = workers.max(1);
+        let queues = Arc::new(Queues {
+            shared: Mutex::new(VecDeque::new()),
+            own: (0..workers).map(|_| Mutex::new(VecDeque::new())).collect(),
+            state: Mutex::new((0, false)),
+            wake: Condvar::new(),
+        });
+        for worker in 0..workers {
+            let queues = queues.clone();
+            thread::spawn(move || queues.work(worker));
+        }
+        ThreadPool { queues, workers }
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    /// Runs `job` on a worker. A job spawned by another of this pool's jobs goes on the queue of
+    /// the worker running it, where any idle worker can steal it from.
+    #[cfg(any(test, feature = "threads-lite"))]
+    pub fn spawn(&self, job: impl FnOnce() + Send + 'static) {
+        self.queues.spawn(job);
+    }
+
+    pub(crate) fn queues(&self) -> &Arc<Queues> {
+        &self.queues
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.queues.state.lock().unwrap().1 = true;
+        self.queues.wake.notify_all();
+    }
+}
+
+type Waiter<T> = Box<dyn FnOnce(&Outcome<T>) + Send>;
+
+enum SlotState<T> {
+    Waiting(Vec<Waiter<T>>),
+    Done(Outcome<T>),
+}
+
+/// Where the outcome of a job ends up, for the graph's thread to wait for or for other jobs to
+/// be started from once it's there.
+pub(crate) struct Slot<T> {
+    state: Mutex<SlotState<T>>,
+    done: Condvar,
+}
+
+impl<T: Clone> Slot<T> {
+    pub(crate) fn new() -> Arc<Self> {
+        Arc::new(Slot {
+            state: Mutex::new(SlotState::Waiting(Vec::new())),
+            done: Condvar::new(),
+        })
+    }
+
+    pub(crate) fn fill(&self, outcome: Outcome<T>) {
+        let waiters = {
+            let mut state = self.state.lock().unwrap();
+            match std::mem::replace(&mut *state, SlotState::Done(outcome.clone())) {
+                SlotState::Waiting(waiters) => waiters,
+                SlotState::Done(_) => unreachable!("a slot is only filled once"),
+            }
+        };
+        self.done.notify_all();
+        for waiter in waiters {
+            waiter(&outcome);
+        }
+    }
+
+    pub(crate) fn wait(&self) -> Outcome<T> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            match &*state {
+                SlotState::Done(outcome) => return outcome.clone(),
+                SlotState::Waiting(_) => state = self.done.wait(state).unwrap(),
+            }
+        }
+    }
+
+    /// Calls `f` with the outcome once it's there, on whichever thread fills the slot, or right
+    /// away if it's already been filled.
+    pub(crate) fn then(&self, f: impl FnOnce(&Outcome<T>) + Send + 'static) {
+        let mut state = self.state.lock().unwrap();
+        match &mut *state {
+            SlotState::Waiting(waiters) => waiters.push(Box::new(f)),
+            SlotState::Done(outcome) => {
+                let outcome = outcome.clone();
+                drop(state);
+                f(&outcome);
+            }
+        }
+    }
+}
+
+pub(crate) fn panic_message(payload: Box<dyn Any + Send>) -> Option<String> {
+    match payload.downcast::<String>() {
+        Ok(message) => Some(*message),
+        Err(payload) => payload.downcast_ref::<&str>().map(|m| m.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ThreadPool;
+    use std::sync::mpsc;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn idle_workers_steal() {
+        let pool = Arc::new(ThreadPool::new(2));
+        let (tx, rx) = mpsc::channel();
+        let threads = Arc::new(Mutex::new(Vec::new()));
+        let spawner = pool.clone();
+        let seen = threads.clone();
+        pool.spawn(move || {
+            // Every job lands on this worker's own queue, so the other one can only get at
+            // them by stealing.
+            for _ in 0..20 {
+                let (tx, seen) = (tx.clone(), seen.clone());
+                spawner.spawn(move || {
+                    thread::sleep(Duration::from_millis(5));
+                    seen.lock().unwrap().push(thread::current().id());
+                    tx.send(()).unwrap();
+                });
+            }
+        });
+        for _ in 0..20 {
+            rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        }
+        let mut threads = threads.lock().unwrap().clone();
+        threads.sort_by_key(|t| format!("{:?}", t));
+        threads.dedup();
+        assert_eq!(2, threads.len());
+    }
+
+    #[test]
+    fn spawn_stress() {
+        let pool = Arc::new(ThreadPool::new(4));
+        for _ in 0..500 {
+            let (tx, rx) = mpsc::channel();
+            for _ in 0..200 {
+                let (spawner, tx) = (pool.clone(), tx.clone());
+                pool.spawn(move || {
+                    spawner.spawn(move || tx.send(()).unwrap());
+                });
+            }
+            drop(tx);
+            for _ in 0..200 {
+                rx.recv_timeout(Duration::from_secs(10)).unwrap();
+            }
+        }
+        assert_eq!(0, pool.queues.state.lock().unwrap().0);
+    }
+}
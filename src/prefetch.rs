@@ -0,0 +1,113 @@
+//! Computing values ahead of time, while there's nothing better to do.
+
+use crate::{AThunkID, AdaptonError, Graph, Handle, MemoHasher, Scalar};
+use std::time::{Duration, Instant};
+
+impl<T: Scalar, S: MemoHasher> Graph<T, S> {
+    /// Queues up the node's value for each of `args` to be computed by `run_prefetches`, for
+    /// values that are likely to be asked for soon, such as the neighbours of a slider's
+    /// position.
+    pub fn prefetch(&self, id: impl Into<AThunkID>, args: &[&[f64]]) {
+        let id = id.into();
+        self.prefetches
+            .borrow_mut()
+            .extend(args.iter().map(|args| (id, args.to_vec())));
+    }
+
+    /// Computes queued prefetches, oldest first, until they're all done or `budget` has passed,
+    /// and returns how many are left. A prefetch that runs out of time is put back, and carries on
+    /// where it left off next time. Any that fail are dropped, since their errors will come up
+    /// again if the value is ever really needed.
+    pub fn run_prefetches(&self, budget: Duration) -> usize {
+        let deadline = Instant::now() + budget;
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            let (id, args) = match self.prefetches.borrow_mut().pop_front() {
+                Some(prefetch) => prefetch,
+                None => break,
+            };
+            if let Err(AdaptonError::Timeout { .. }) =
+                self.compute_timeout(id, &args, deadline - now)
+            {
+                self.prefetches.borrow_mut().push_front((id, args));
+                break;
+            }
+        }
+        self.prefetches.borrow().len()
+    }
+}
+
+impl<T: Scalar, S: MemoHasher> Handle<'_, T, S> {
+    /// Lets the graph know that the node might be computed with `args` next time, without
+    /// computing it now, so it can be prefetched. Hints for values that are already up to date or
+    /// already queued are ignored.
+    pub fn hint(&mut self, id: impl Into<AThunkID>, args: &[f64]) {
+        let id = self.resolve(id.into());
+        let athunk = match self.graph.athunks.get(id.0) {
+            Some(athunk) => athunk,
+            None => return,
+        };
+        let key: Vec<u64> = args.iter().map(|a| a.to_bits()).collect();
+        if athunk.clean.get() && athunk.result.borrow().contains_key(&key) {
+            return;
+        }
+        let mut prefetches = self.graph.prefetches.borrow_mut();
+        if !prefetches.iter().any(|(i, a)| *i == id && a == args) {
+            prefetches.push_back((id, args.to_vec()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Graph;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    #[test]
+    fn prefetch() {
+        let mut graph = Graph::new();
+
+        let runs = Rc::new(Cell::new(0));
+        let counter = runs.clone();
+        let square = graph.new_athunk(move |h| {
+            counter.set(counter.get() + 1);
+            h.args[0] * h.args[0]
+        });
+        graph.prefetch(square, &[&[1.0], &[2.0], &[3.0]]);
+        assert_eq!(0, runs.get());
+        assert_eq!(3, graph.run_prefetches(Duration::ZERO));
+
+        assert_eq!(0, graph.run_prefetches(Duration::from_secs(1)));
+        assert_eq!(3, runs.get());
+        assert_eq!(Ok(4.0), graph.compute(square, &[2.0]));
+        assert_eq!(3, runs.get());
+    }
+
+    #[test]
+    fn hint() {
+        let mut graph = Graph::new();
+
+        let page = graph.new_athunk(|h| h.args[0] * 10.0);
+        let current = graph.new_aref(1.0);
+        let view = graph.new_athunk(move |h| {
+            h.add_edge(current);
+            let n = h.compute(current, &[]).unwrap();
+            h.add_edge(page);
+            h.hint(page, &[n + 1.0]);
+            h.hint(page, &[n + 1.0]);
+            h.compute(page, &[n]).unwrap()
+        });
+        assert_eq!(Ok(10.0), graph.compute(view, &[]));
+        assert_eq!(0, graph.run_prefetches(Duration::from_secs(1)));
+
+        graph.update_aref(current, 2.0);
+        graph.assert_recomputes(&[(current.id(), 1), (view, 1), (page, 0)], |g| {
+            assert_eq!(Ok(20.0), g.compute(view, &[]));
+        });
+    }
+}
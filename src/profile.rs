@@ -0,0 +1,185 @@
+//! Finding out where the time goes when the graph is recomputed.
+
+use crate::{AThunkID, Graph, MemoHasher, Scalar};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+impl<T: Scalar, S: MemoHasher> Graph<T, S> {
+    /// How long the node's thunk took the last time it was run, not counting the thunks it ran
+    /// itself.
+    pub fn self_time(&self, id: impl Into<AThunkID>) -> Option<Duration> {
+        Some(self.athunks.get(id.into().0)?.self_time.get())
+    }
+
+    /// What the node costs to recompute: its cost hint, or if it doesn't have one, how many
+    /// seconds its last run took by itself.
+    pub fn node_cost(&self, id: impl Into<AThunkID>) -> f64 {
+        let athunk = &self.athunks[id.into().0];
+        athunk
+            .cost
+            .unwrap_or_else(|| athunk.self_time.get().as_secs_f64())
+    }
+
+    /// Starts or stops keeping track of how long each thunk takes, for `folded_stacks`. Starting
+    /// again throws away what was tracked before.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profile = if enabled {
+            Some(RefCell::new(HashMap::default()))
+        } else {
+            None
+        };
+    }
+
+    /// How long thunks have taken while profiling, in the folded stack format read by flamegraph
+    /// tools such as inferno: a line per stack of thunks that were running at once, outermost
+    /// first and separated by semicolons, followed by how many microseconds the innermost one
+    /// spent by itself. The lines are sorted.
+    pub fn folded_stacks(&self) -> String {
+        let profile = match &self.profile {
+            Some(profile) => profile.borrow(),
+            None => return String::new(),
+        };
+        let mut lines: Vec<String> = profile
+            .iter()
+            .map(|(stack, time)| {
+                let mut line = String::new();
+                for (i, &id) in stack.iter().enumerate() {
+                    if i > 0 {
+                        line.push(';');
+                    }
+                    let name = self.node_name(id).to_string();
+                    line.push_str(&name.replace(';', ","));
+                }
+                write!(line, " {}", time.as_micros()).unwrap();
+                line
+            })
+            .collect();
+        lines.sort();
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+
+    pub(crate) fn record_profile(&self, self_time: Duration) {
+        if let Some(profile) = &self.profile {
+            let stack = self.demand_stack.borrow().clone();
+            *profile.borrow_mut().entry(stack).or_default() += self_time;
+        }
+    }
+
+    /// The most expensive chain of dependencies from `root` down to a node that doesn't depend on
+    /// anything, starting with `root`, going by `node_cost`. Even if everything independent were
+    /// recomputed in parallel, a change at the bottom of the chain would take at least this
+    /// chain's total cost to repair.
+    pub fn critical_path(&self, root: impl Into<AThunkID>) -> Vec<AThunkID> {
+        let root = root.into();
+        let mut best = HashMap::default();
+        self.heaviest_chain(root, &mut best);
+        let mut path = Vec::new();
+        let mut next = Some(root);
+        while let Some(id) = next {
+            path.push(id);
+            next = best[&id].1;
+        }
+        path
+    }
+
+    // The cost of the most expensive chain starting at `id`, and the next node on it.
+    fn heaviest_chain(
+        &self,
+        id: AThunkID,
+        best: &mut HashMap<AThunkID, (f64, Option<AThunkID>), S>,
+    ) -> f64 {
+        if let Some(&(cost, _)) = best.get(&id) {
+            return cost;
+        }
+        let mut subs: Vec<AThunkID> = self.athunks[id.0]
+            .sub_computations
+            .borrow()
+            .iter()
+            .collect();
+        // Ties go to the lowest ID, so the path is the same every time.
+        subs.sort();
+        let mut heaviest: (f64, Option<AThunkID>) = (0.0, None);
+        for sub in subs {
+            let cost = self.heaviest_chain(sub, best);
+            if heaviest.1.is_none() || cost > heaviest.0 {
+                heaviest = (cost, Some(sub));
+            }
+        }
+        let cost = self.node_cost(id) + heaviest.0;
+        best.insert(id, (cost, heaviest.1));
+        cost
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Graph, NodeConfig};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn critical_path() {
+        let mut graph = Graph::new();
+
+        let r = graph.new_aref(1.0);
+        let cheap = graph.new_athunk_with_deps(&[r.id()], |_, vals| vals[0]);
+        let expensive = graph.new_athunk_with_deps(&[r.id()], |_, vals| vals[0]);
+        let top = graph.new_athunk_with_deps(&[cheap, expensive], |_, vals| vals[0] + vals[1]);
+        for (id, cost) in [(r.id(), 0.0), (cheap, 1.0), (expensive, 5.0), (top, 1.0)] {
+            let config = NodeConfig {
+                cost: Some(cost),
+                ..NodeConfig::default()
+            };
+            graph.configure_node(id, config).unwrap();
+        }
+        assert_eq!(vec![top, expensive, r.id()], graph.critical_path(top));
+        assert_eq!(vec![r.id()], graph.critical_path(r));
+
+        assert_eq!(Ok(2.0), graph.compute(top, &[]));
+        assert!(graph.self_time(top).is_some());
+    }
+
+    #[test]
+    fn folded_stacks() {
+        let mut graph = Graph::new();
+        graph.set_profiling(true);
+
+        let r = graph.aref_entry("r").or_insert(1.0);
+        let slow = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            thread::sleep(Duration::from_millis(5));
+            h.compute(r, &[]).unwrap()
+        });
+        graph.set_label(slow, "slow; really");
+        let top = graph.new_athunk(move |h| {
+            h.add_edge(slow);
+            h.compute(slow, &[]).unwrap()
+        });
+        graph.compute(top, &[]).unwrap();
+
+        let folded = graph.folded_stacks();
+        let lines: Vec<(&str, u128)> = folded
+            .lines()
+            .map(|line| {
+                let (stack, micros) = line.rsplit_once(' ').unwrap();
+                (stack, micros.parse().unwrap())
+            })
+            .collect();
+        let stacks: Vec<&str> = lines.iter().map(|(stack, _)| *stack).collect();
+        assert_eq!(
+            vec![
+                "node 2",
+                "node 2;node 1 (\"slow, really\")",
+                "node 2;node 1 (\"slow, really\");node 0 (\"r\")",
+            ],
+            stacks
+        );
+        assert!(lines[1].1 >= 5000);
+        assert!(lines[0].1 < lines[1].1);
+
+        graph.set_profiling(false);
+        assert_eq!("", graph.folded_stacks());
+    }
+}
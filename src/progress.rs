@@ -0,0 +1,134 @@
+//! Reporting how far along a long computation is.
+
+use crate::{AThunkID, Graph, MemoHasher, Scalar};
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+
+pub(crate) struct Progress {
+    callback: RefCell<Box<dyn FnMut(usize, usize)>>,
+    // Set while a call to `compute` or `stabilize` is being tracked, to how many thunks it has run
+    // so far and how many it's expected to run.
+    tracking: Cell<Option<(usize, usize)>>,
+}
+
+// Stops tracking once the outermost call returns, however it returns.
+pub(crate) struct Tracking<'a, T, S> {
+    graph: &'a Graph<T, S>,
+}
+
+impl<T, S> Drop for Tracking<'_, T, S> {
+    fn drop(&mut self) {
+        let progress = self.graph.progress.as_ref().unwrap();
+        if let Some((done, _)) = progress.tracking.take() {
+            if done > 0 {
+                (progress.callback.borrow_mut())(done, done);
+            }
+        }
+    }
+}
+
+impl<T: Scalar, S: MemoHasher> Graph<T, S> {
+    /// Calls `callback` with how many thunks have been run so far, and roughly how many will have
+    /// been by the end, after each thunk run by `compute` or `stabilize`, and once more with the
+    /// final count when they finish. The estimate is the number of dirty nodes `compute` depends
+    /// on, or the number of stale values `stabilize` has to repair, and it's raised if it turns out
+    /// to be too low, so the first number never exceeds the second.
+    pub fn on_progress(&mut self, callback: impl FnMut(usize, usize) + 'static) {
+        self.progress = Some(Progress {
+            callback: RefCell::new(Box::new(callback)),
+            tracking: Cell::new(None),
+        });
+    }
+
+    // Starts tracking progress, unless there's nothing to report to or an outer call is already
+    // being tracked.
+    pub(crate) fn track_progress(
+        &self,
+        estimate: impl FnOnce() -> usize,
+    ) -> Option<Tracking<'_, T, S>> {
+        let progress = self.progress.as_ref()?;
+        if progress.tracking.get().is_some() {
+            return None;
+        }
+        progress.tracking.set(Some((0, estimate())));
+        Some(Tracking { graph: self })
+    }
+
+    pub(crate) fn report_progress(&self) {
+        let progress = match &self.progress {
+            Some(progress) => progress,
+            None => return,
+        };
+        if let Some((done, estimate)) = progress.tracking.get() {
+            let done = done + 1;
+            progress.tracking.set(Some((done, estimate)));
+            (progress.callback.borrow_mut())(done, estimate.max(done));
+        }
+    }
+
+    // How many dirty nodes computing `id` might have to run.
+    pub(crate) fn dirty_cone(&self, id: AThunkID) -> usize {
+        let mut seen: HashSet<_, S> = HashSet::default();
+        let mut next = vec![id];
+        while let Some(id) = next.pop() {
+            let athunk = match self.athunks.get(id.0) {
+                Some(athunk) if !athunk.clean.get() => athunk,
+                _ => continue,
+            };
+            if seen.insert(id) {
+                next.extend(athunk.sub_computations.borrow().iter());
+            }
+        }
+        seen.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Graph;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn progress() {
+        let mut graph = Graph::new();
+        let reports = Rc::new(RefCell::new(Vec::new()));
+        let log = reports.clone();
+        graph.on_progress(move |done, total| log.borrow_mut().push((done, total)));
+
+        let r = graph.new_aref(1.0);
+        let mut chain = vec![r.id()];
+        for _ in 0..3 {
+            let prev = *chain.last().unwrap();
+            chain.push(graph.new_athunk(move |h| {
+                h.add_edge(prev);
+                h.compute(prev, &[]).unwrap() + 1.0
+            }));
+        }
+        let last = *chain.last().unwrap();
+        assert_eq!(Ok(4.0), graph.compute(last, &[]));
+        // Nothing is known about the nodes' dependencies before they've been run.
+        assert_eq!(
+            vec![(1, 1), (2, 2), (3, 3), (4, 4), (4, 4)],
+            *reports.borrow()
+        );
+        reports.borrow_mut().clear();
+        assert_eq!(Ok(4.0), graph.compute(last, &[]));
+        assert!(reports.borrow().is_empty());
+
+        graph.update_aref(r, 2.0);
+        assert_eq!(Ok(5.0), graph.compute(last, &[]));
+        assert_eq!(
+            vec![(1, 4), (2, 4), (3, 4), (4, 4), (4, 4)],
+            *reports.borrow()
+        );
+        reports.borrow_mut().clear();
+
+        graph.update_aref(r, 3.0);
+        graph.stabilize().unwrap();
+        assert_eq!(
+            vec![(1, 4), (2, 4), (3, 4), (4, 4), (4, 4)],
+            *reports.borrow()
+        );
+    }
+}
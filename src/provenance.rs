@@ -0,0 +1,96 @@
+//! Working out which inputs a value was derived from.
+
+use crate::{ARefID, AThunkID, AdaptonError, Graph, MemoHasher, Scalar};
+use std::collections::HashSet;
+
+impl<T: Scalar, S: MemoHasher> Graph<T, S> {
+    /// Keep track of what every value is computed from, for `provenance`.
+    pub fn set_provenance_tracking(&mut self, enabled: bool) {
+        self.provenance_tracking = enabled;
+        if !enabled {
+            for (_, athunk) in self.athunks.iter() {
+                athunk.sources.borrow_mut().clear();
+            }
+        }
+    }
+
+    /// Brings the node's value for `args` up to date, and returns every aref it was derived from,
+    /// however indirectly.
+    ///
+    /// With provenance tracking on, this is exactly the arefs the value's computation read, along
+    /// with everything they were derived from in turn. Values computed while it was off aren't
+    /// known to have read anything in particular, so everything below them in the graph is
+    /// included instead.
+    pub fn provenance(
+        &self,
+        id: impl Into<AThunkID>,
+        args: &[f64],
+    ) -> Result<HashSet<ARefID>, AdaptonError> {
+        let id = id.into();
+        self.compute(id, args)?;
+
+        let mut inputs = HashSet::new();
+        let mut visited: HashSet<_, S> = HashSet::default();
+        let mut stack = vec![(id, Some(self.intern_args(args)))];
+        while let Some((id, key)) = stack.pop() {
+            if !visited.insert((id, key)) {
+                continue;
+            }
+            let athunk = &self.athunks[id.0];
+            if athunk.is_aref {
+                inputs.insert(ARefID(id));
+                continue;
+            }
+            let sources = key.and_then(|key| athunk.sources.borrow().get(&key).cloned());
+            match sources {
+                Some(sources) => stack.extend(sources.into_iter().map(|(s, k)| (s, Some(k)))),
+                None => stack.extend(athunk.sub_computations.borrow().iter().map(|s| (s, None))),
+            }
+        }
+        Ok(inputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ARefID, Graph};
+    use std::collections::HashSet;
+
+    #[test]
+    fn provenance() {
+        let mut graph = Graph::new();
+
+        let rates = [graph.new_aref(0.1), graph.new_aref(0.2)];
+        let principal = graph.new_aref(100.0);
+        let interest = graph.new_athunk(move |h| {
+            let rate = rates[h.args[0] as usize];
+            h.add_edge(rate);
+            h.add_edge(principal);
+            h.compute(rate, &[]).unwrap() * h.compute(principal, &[]).unwrap()
+        });
+        let second = graph.new_athunk(move |h| {
+            h.add_edge(interest);
+            h.compute(interest, &[1.0]).unwrap()
+        });
+        let set = |ids: &[ARefID]| ids.iter().copied().collect::<HashSet<_>>();
+
+        // Without tracking, every input `interest` has ever had an edge to is included.
+        graph.compute(interest, &[0.0]).unwrap();
+        assert_eq!(
+            set(&[rates[0], rates[1], principal]),
+            graph.provenance(second, &[]).unwrap()
+        );
+
+        graph.set_provenance_tracking(true);
+        graph.update_aref(principal, 200.0);
+        assert_eq!(
+            set(&[rates[1], principal]),
+            graph.provenance(second, &[]).unwrap()
+        );
+        assert_eq!(
+            set(&[rates[0], principal]),
+            graph.provenance(interest, &[0.0]).unwrap()
+        );
+        assert_eq!(set(&[principal]), graph.provenance(principal, &[]).unwrap());
+    }
+}
@@ -0,0 +1,127 @@
+//! Recording what the graph does in a plain-text format, so that the same workload can be run
+//! against other incremental computation libraries and their behaviour compared line by line.
+//!
+//! `export_recording` writes one event per line, as three fields separated by single spaces,
+//! except for `dirty` which has no arguments:
+//!
+//! ```text
+//! demand <node> <args>
+//! hit <node> <args>
+//! recompute <node> <args>
+//! dirty <node>
+//! ```
+//!
+//! `demand` is written whenever a node's value is asked for, by the caller or another thunk, then
+//! either `hit` if it was answered from the memo table or `recompute` if its thunk was run. `dirty`
+//! is written when a change marks a clean node dirty. `<node>` is the node's label with any
+//! whitespace replaced by `_`, or its index if it has no label, and `<args>` are the arguments in
+//! square brackets, separated by commas without spaces, each written as the shortest decimal that
+//! reads back as the same `f64`, always with a fractional part: `[]`, `[1.0,-2.5]`.
+
+use crate::{AThunkID, Graph, MemoHasher, Scalar};
+use std::cell::RefCell;
+use std::fmt::Write;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TraceEvent {
+    Demand(AThunkID, Vec<f64>),
+    Hit(AThunkID, Vec<f64>),
+    Recompute(AThunkID, Vec<f64>),
+    Dirty(AThunkID),
+}
+
+impl TraceEvent {
+    pub fn id(&self) -> AThunkID {
+        match self {
+            TraceEvent::Demand(id, _)
+            | TraceEvent::Hit(id, _)
+            | TraceEvent::Recompute(id, _)
+            | TraceEvent::Dirty(id) => *id,
+        }
+    }
+}
+
+impl<T: Scalar, S: MemoHasher> Graph<T, S> {
+    /// Starts or stops recording `TraceEvent`s. Starting again throws away what was recorded
+    /// before.
+    pub fn set_recording(&mut self, enabled: bool) {
+        self.recording = if enabled {
+            Some(RefCell::new(Vec::new()))
+        } else {
+            None
+        };
+    }
+
+    pub fn recorded_events(&self) -> Vec<TraceEvent> {
+        self.recording
+            .as_ref()
+            .map_or_else(Vec::new, |recording| recording.borrow().clone())
+    }
+
+    /// The recorded events, in the format described in the module documentation.
+    pub fn export_recording(&self) -> String {
+        let mut out = String::new();
+        for event in self.recorded_events() {
+            let (kind, id, args) = match &event {
+                TraceEvent::Demand(id, args) => ("demand", *id, Some(args)),
+                TraceEvent::Hit(id, args) => ("hit", *id, Some(args)),
+                TraceEvent::Recompute(id, args) => ("recompute", *id, Some(args)),
+                TraceEvent::Dirty(id) => ("dirty", *id, None),
+            };
+            let node = match self.athunks.get(id.0).and_then(|a| a.label.as_deref()) {
+                Some(label) => label.split_whitespace().collect::<Vec<_>>().join("_"),
+                None => id.0.to_string(),
+            };
+            write!(out, "{} {}", kind, node).unwrap();
+            if let Some(args) = args {
+                let args: Vec<String> = args.iter().map(|a| format!("{:?}", a)).collect();
+                write!(out, " [{}]", args.join(",")).unwrap();
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    pub(crate) fn record(&self, event: impl FnOnce() -> TraceEvent) {
+        if let Some(recording) = &self.recording {
+            recording.borrow_mut().push(event());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Graph;
+
+    #[test]
+    fn recording() {
+        let mut graph = Graph::new();
+        let r = graph.new_aref(1.0);
+        let a = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap() * h.arg(0).unwrap()
+        });
+        graph.set_label(a, "scaled value");
+        graph.compute(a, &[2.0]).unwrap();
+
+        graph.set_recording(true);
+        graph.compute(a, &[2.0]).unwrap();
+        graph.update_aref(r, 3.0);
+        graph.compute(a, &[-0.5]).unwrap();
+        assert_eq!(
+            "demand scaled_value [2.0]\n\
+             hit scaled_value [2.0]\n\
+             dirty 0\n\
+             dirty scaled_value\n\
+             demand scaled_value [-0.5]\n\
+             recompute scaled_value [-0.5]\n\
+             demand 0 []\n\
+             recompute 0 []\n",
+            graph.export_recording()
+        );
+
+        graph.set_recording(false);
+        graph.compute(a, &[2.0]).unwrap();
+        assert!(graph.recorded_events().is_empty());
+    }
+}
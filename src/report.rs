@@ -0,0 +1,40 @@
+use crate::{ARefID, AThunkID};
+use std::time::Duration;
+
+/// What a single dirtying pass did, recorded by `update_aref` when propagation reports are turned
+/// on with `Graph::set_propagation_reports`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropagationReport {
+    /// The aref whose update started the pass.
+    pub source: ARefID,
+    /// Every node the pass reached, in the order it reached them, starting with `source`.
+    pub visited: Vec<AThunkID>,
+    /// Nodes the pass reached but didn't propagate through, either because they were already
+    /// dirty or because the update was within the source's threshold.
+    pub skipped: Vec<AThunkID>,
+}
+
+impl PropagationReport {
+    pub(crate) fn new(source: ARefID) -> Self {
+        Self {
+            source,
+            visited: Vec::new(),
+            skipped: Vec::new(),
+        }
+    }
+}
+
+/// What a single call to `Graph::compute_with_report` did.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComputeReport<T = f64> {
+    pub value: T,
+    /// Whether the node's value was already memoized, or could be brought back by cutoff, rather
+    /// than its thunk having to be run.
+    pub was_cache_hit: bool,
+    /// How many thunks were run, including the node's own.
+    pub nodes_recomputed: usize,
+    /// How many times a node was computed, whether or not its thunk was run, including the node
+    /// itself.
+    pub nodes_visited: usize,
+    pub duration: Duration,
+}
@@ -0,0 +1,60 @@
+//! Resources, such as I/O handles, that thunks can borrow from the graph by type.
+
+use crate::{Graph, Handle, MemoHasher, Scalar};
+
+impl<T: Scalar, S: MemoHasher> Graph<T, S> {
+    /// Gives every thunk access to `resource` through `Handle::resource`, alongside any other
+    /// resources of different types. Like `set_env`, replacing a resource of the same type clears
+    /// every cache, and the old one is returned.
+    pub fn provide<R: 'static>(&mut self, resource: R) -> Option<R> {
+        let old = self.resources.insert(resource);
+        if old.is_some() {
+            self.clear_caches();
+            self.revision += 1;
+        }
+        old
+    }
+
+    pub fn resource<R: 'static>(&self) -> Option<&R> {
+        self.resources.get()
+    }
+}
+
+impl<'a, T: Scalar, S: MemoHasher> Handle<'a, T, S> {
+    /// The resource of type `R` given to `Graph::provide`. Like `env`, it's borrowed from the graph
+    /// rather than the handle.
+    pub fn resource<R: 'static>(&self) -> Option<&'a R> {
+        self.graph.resource()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Graph;
+    use std::collections::HashMap;
+
+    #[test]
+    fn resources() {
+        struct Db(HashMap<u32, f64>);
+        struct Scale(f64);
+
+        let mut graph = Graph::new();
+        graph.provide(Db(vec![(1, 2.0), (2, 5.0)].into_iter().collect()));
+        graph.provide(Scale(10.0));
+        let lookup = graph.new_athunk(|h| {
+            let db = h.resource::<Db>().unwrap();
+            let scale = h.resource::<Scale>().map_or(1.0, |s| s.0);
+            db.0.get(&(h.arg(0).unwrap() as u32))
+                .copied()
+                .unwrap_or(0.0)
+                * scale
+        });
+        assert_eq!(Ok(20.0), graph.compute(lookup, &[1.0]));
+        assert_eq!(Ok(50.0), graph.compute(lookup, &[2.0]));
+        assert!(graph.resource::<String>().is_none());
+
+        let old = graph.provide(Scale(1.0)).unwrap();
+        assert_eq!(10.0, old.0);
+        assert_eq!(Ok(2.0), graph.compute(lookup, &[1.0]));
+    }
+}
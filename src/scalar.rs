@@ -0,0 +1,127 @@
+//! The values nodes can compute.
+
+use std::fmt::Debug;
+
+/// A type that nodes can compute, such as `f64` (the default) or `f32`. With the `num-traits`
+/// feature, anything with subtraction, an ordering and a conversion to `f64` is a scalar, integers
+/// included. With the `rust_decimal` feature, so is `Decimal`, which is best used with
+/// `Cutoff::Exact` since its arithmetic is too.
+///
+/// Arguments are always `f64`s, whatever the graph's scalar is.
+pub trait Scalar: Copy + PartialEq + Default + Debug + 'static {
+    /// How far apart two values are, for `Cutoff::Tolerance` and arefs with thresholds.
+    fn distance(self, other: Self) -> f64;
+}
+
+#[cfg(not(feature = "num-traits"))]
+impl Scalar for f64 {
+    fn distance(self, other: Self) -> f64 {
+        (self - other).abs()
+    }
+}
+
+#[cfg(not(feature = "num-traits"))]
+impl Scalar for f32 {
+    fn distance(self, other: Self) -> f64 {
+        f64::from((self - other).abs())
+    }
+}
+
+#[cfg(all(feature = "rust_decimal", not(feature = "num-traits")))]
+impl Scalar for rust_decimal::Decimal {
+    fn distance(self, other: Self) -> f64 {
+        use rust_decimal::prelude::ToPrimitive;
+        (self - other).abs().to_f64().unwrap_or(f64::INFINITY)
+    }
+}
+
+#[cfg(feature = "num-traits")]
+impl<T> Scalar for T
+where
+    T: Copy
+        + PartialEq
+        + PartialOrd
+        + Default
+        + Debug
+        + 'static
+        + std::ops::Sub<Output = T>
+        + num_traits::ToPrimitive,
+{
+    fn distance(self, other: Self) -> f64 {
+        let d = if self > other {
+            self - other
+        } else {
+            other - self
+        };
+        d.to_f64().unwrap_or(f64::INFINITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Cutoff, Graph, Handle};
+
+    #[test]
+    fn f32_graph() {
+        let mut graph: Graph<f32> = Graph::default();
+        graph.set_cutoff(Cutoff::Tolerance(0.01));
+
+        let r = graph.new_aref(1.5f32);
+        let a = graph.new_athunk(move |h: &mut Handle<f32>| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap() * 2.0
+        });
+        assert_eq!(Ok(3.0), graph.compute(a, &[]));
+        graph.update_aref(r, 2.0);
+        assert_eq!(Ok(4.0), graph.compute(a, &[]));
+    }
+
+    #[cfg(feature = "rust_decimal")]
+    #[test]
+    fn decimal_graph() {
+        use rust_decimal::Decimal;
+
+        let mut graph: Graph<Decimal> = Graph::default();
+        graph.set_cutoff(Cutoff::Exact);
+
+        let (r1, r2) = (
+            graph.new_aref(Decimal::new(1, 1)),
+            graph.new_aref(Decimal::new(2, 1)),
+        );
+        let sum = graph.new_athunk_with_deps(&[r1.id(), r2.id()], |_, vals| vals[0] + vals[1]);
+        let big = graph.new_athunk(move |h: &mut Handle<Decimal>| {
+            h.add_edge(sum);
+            let s = h.compute(sum, &[]).unwrap();
+            if s == Decimal::new(3, 1) {
+                Decimal::ONE
+            } else {
+                Decimal::ZERO
+            }
+        });
+        assert_eq!(Ok(Decimal::ONE), graph.compute(big, &[]));
+
+        // 0.2 + 0.1 is exactly 0.3, which floats would disagree with, so `big` is left alone.
+        graph.assert_recomputes(&[(r1.id(), 1), (r2.id(), 1), (sum, 1)], |g| {
+            g.update_aref(r1, Decimal::new(2, 1));
+            g.update_aref(r2, Decimal::new(1, 1));
+            assert_eq!(Ok(Decimal::ONE), g.compute(big, &[]));
+        });
+    }
+
+    #[cfg(feature = "num-traits")]
+    #[test]
+    fn integer_graph() {
+        let mut graph: Graph<i64> = Graph::default();
+
+        let r = graph.new_aref_with_threshold(10, 2.0);
+        let a = graph.new_athunk(move |h: &mut Handle<i64>| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap() + 1
+        });
+        assert_eq!(Ok(11), graph.compute(a, &[]));
+        graph.update_aref(r, 8);
+        assert_eq!(Ok(11), graph.compute(a, &[]));
+        graph.update_aref(r, 7);
+        assert_eq!(Ok(8), graph.compute(a, &[]));
+    }
+}
@@ -0,0 +1,80 @@
+//! Sharing results between nodes that perform the same computation.
+
+use crate::{AThunkID, Graph, Handle, MemoHasher};
+
+// What a shared computation's result depends on: the identity of its code, and the bits of its
+// dependencies' values and of its arguments.
+pub(crate) type ContentKey = (u64, Vec<u64>, Vec<u64>);
+
+impl<S: MemoHasher> Graph<f64, S> {
+    /// Like `new_athunk_with_deps`, but the result is shared with every other shared node that
+    /// has the same `code` and is run on the same dependency values and arguments, whichever node
+    /// it happens to be. `code` identifies what the thunk computes, so two nodes must only be given
+    /// the same one if their thunks are interchangeable.
+    pub fn new_shared_athunk(
+        &mut self,
+        code: u64,
+        deps: &[AThunkID],
+        thunk: impl Fn(&mut Handle<f64, S>, &[f64]) -> f64 + 'static,
+    ) -> AThunkID {
+        self.new_athunk_with_deps(deps, move |h, vals| {
+            let key = (code, to_bits(vals), to_bits(h.args));
+            if let Some(&v) = h.graph.shared_results.borrow().get(&key) {
+                return v;
+            }
+            let v = thunk(h, vals);
+            if h.error.is_none() {
+                h.graph.shared_results.borrow_mut().insert(key, v);
+            }
+            v
+        })
+    }
+
+    /// How many results are held for shared nodes.
+    pub fn shared_results_len(&self) -> usize {
+        self.shared_results.borrow().len()
+    }
+
+    pub fn clear_shared_results(&mut self) {
+        self.shared_results.get_mut().clear();
+    }
+}
+
+fn to_bits(vals: &[f64]) -> Vec<u64> {
+    vals.iter().map(|f| f.to_bits()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Graph, Handle};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn shared_results() {
+        let mut graph = Graph::new();
+
+        let runs = Rc::new(Cell::new(0));
+        let r1 = graph.new_aref(2.0);
+        let r2 = graph.new_aref(2.0);
+        let square = |runs: Rc<Cell<usize>>| {
+            move |_: &mut Handle, vals: &[f64]| {
+                runs.set(runs.get() + 1);
+                vals[0] * vals[0]
+            }
+        };
+        let a = graph.new_shared_athunk(1, &[r1.id()], square(runs.clone()));
+        let b = graph.new_shared_athunk(1, &[r2.id()], square(runs.clone()));
+
+        assert_eq!(Ok(4.0), graph.compute(a, &[]));
+        assert_eq!(Ok(4.0), graph.compute(b, &[]));
+        assert_eq!(1, runs.get());
+
+        graph.update_aref(r2, 3.0);
+        assert_eq!(Ok(9.0), graph.compute(b, &[]));
+        graph.update_aref(r1, 3.0);
+        assert_eq!(Ok(9.0), graph.compute(a, &[]));
+        assert_eq!(2, runs.get());
+        assert_eq!(2, graph.shared_results_len());
+    }
+}
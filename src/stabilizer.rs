@@ -0,0 +1,135 @@
+use crate::{ARefID, AdaptonError, Graph, MemoHasher};
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+/// Collects bursts of aref updates on a background thread, so that they can be applied with a
+/// single repair once things have gone quiet. See `Graph::spawn_stabilizer`.
+pub struct Stabilizer {
+    updates: Sender<(ARefID, f64)>,
+    batches: Receiver<Vec<(ARefID, f64)>>,
+}
+
+impl Graph {
+    /// Starts a worker thread that batches up the updates sent to the returned stabilizer, and
+    /// hands a batch over once no update has arrived for `quiet`. Within a batch, only the last
+    /// update to each aref is kept.
+    ///
+    /// The graph itself stays on its own thread, which applies the batches with
+    /// `Stabilizer::apply` or `Stabilizer::apply_next`.
+    pub fn spawn_stabilizer(quiet: Duration) -> Stabilizer {
+        let (updates, updates_rx) = mpsc::channel();
+        let (batches_tx, batches) = mpsc::channel();
+        thread::spawn(move || debounce(updates_rx, batches_tx, quiet));
+        Stabilizer { updates, batches }
+    }
+}
+
+impl Stabilizer {
+    /// A sender for updates, which can be handed to other threads.
+    pub fn sender(&self) -> Sender<(ARefID, f64)> {
+        self.updates.clone()
+    }
+
+    pub fn update_aref(&self, id: ARefID, val: f64) {
+        // The worker only stops once this stabilizer is dropped.
+        self.updates.send((id, val)).unwrap();
+    }
+
+    /// Applies every batch that is ready without waiting for more, stabilizing the graph (and so
+    /// notifying its observers) after each one. Returns how many batches were applied.
+    pub fn apply<S: MemoHasher>(&self, graph: &mut Graph<f64, S>) -> Result<usize, AdaptonError> {
+        let mut applied = 0;
+        while let Ok(batch) = self.batches.try_recv() {
+            apply_batch(graph, batch)?;
+            applied += 1;
+        }
+        Ok(applied)
+    }
+
+    /// Waits for the next batch and applies it like `apply`.
+    pub fn apply_next<S: MemoHasher>(&self, graph: &mut Graph<f64, S>) -> Result<(), AdaptonError> {
+        // The worker only stops once this stabilizer is dropped.
+        let batch = self.batches.recv().unwrap();
+        apply_batch(graph, batch)
+    }
+}
+
+fn apply_batch<S: MemoHasher>(
+    graph: &mut Graph<f64, S>,
+    batch: Vec<(ARefID, f64)>,
+) -> Result<(), AdaptonError> {
+    for (id, val) in batch {
+        graph.update_aref(id, val);
+    }
+    graph.stabilize()?;
+    Ok(())
+}
+
+fn debounce(
+    updates: Receiver<(ARefID, f64)>,
+    batches: Sender<Vec<(ARefID, f64)>>,
+    quiet: Duration,
+) {
+    while let Ok(first) = updates.recv() {
+        let mut batch = vec![first];
+        let mut index: HashMap<ARefID, usize> = HashMap::new();
+        index.insert(first.0, 0);
+        let disconnected = loop {
+            match updates.recv_timeout(quiet) {
+                Ok((id, val)) => match index.get(&id) {
+                    Some(&i) => batch[i].1 = val,
+                    None => {
+                        index.insert(id, batch.len());
+                        batch.push((id, val));
+                    }
+                },
+                Err(RecvTimeoutError::Timeout) => break false,
+                Err(RecvTimeoutError::Disconnected) => break true,
+            }
+        };
+        if batches.send(batch).is_err() || disconnected {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Graph;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn stabilizer() {
+        let mut graph = Graph::new();
+
+        let r = graph.new_aref(1.0);
+        let a = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap() * 2.0
+        });
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let s = seen.clone();
+        graph
+            .observe(a, &[], move |v| s.borrow_mut().push(v))
+            .unwrap();
+
+        let stabilizer = Graph::spawn_stabilizer(Duration::from_millis(20));
+        let sender = stabilizer.sender();
+        thread::spawn(move || {
+            for i in 2..=5 {
+                sender.send((r, i as f64)).unwrap();
+            }
+        })
+        .join()
+        .unwrap();
+
+        stabilizer.apply_next(&mut graph).unwrap();
+        assert_eq!(vec![10.0], *seen.borrow());
+        assert_eq!(0, stabilizer.apply(&mut graph).unwrap());
+    }
+}
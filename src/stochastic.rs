@@ -0,0 +1,132 @@
+//! Thunks that make random choices, reproducibly.
+
+use crate::{ARefID, AThunkID, Graph, Handle, MemoHasher};
+
+/// A small, fast random number generator (SplitMix64). It's not suitable for cryptography.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl<S: MemoHasher> Graph<f64, S> {
+    /// A thunk that's handed an `Rng` to make its random choices with. The node is volatile, but
+    /// the RNG is seeded from the graph's seed, how many stochastic nodes were created before it,
+    /// `seed` and the arguments, so it computes the same values every time until the graph's seed
+    /// is changed with `set_seed`, which invalidates every stochastic node at once. Unlike the
+    /// node's ID, none of that changes when the graph is compacted.
+    pub fn new_stochastic_athunk(
+        &mut self,
+        seed: u64,
+        thunk: impl Fn(&mut Handle<f64, S>, &mut Rng) -> f64 + 'static,
+    ) -> AThunkID {
+        let seed_node = self.seed_node();
+        let stream = self.stochastic_nodes;
+        self.stochastic_nodes += 1;
+        let id = self.new_athunk(move |h: &mut Handle<f64, S>| {
+            h.add_edge(seed_node);
+            // Reading the seed node is what makes `set_seed` invalidate this one.
+            let _ = h.compute(seed_node, &[]);
+            let mut rng = Rng::new(h.graph.seed);
+            let mut mixed = rng.next_u64() ^ stream;
+            for x in std::iter::once(seed).chain(h.args.iter().map(|a| a.to_bits())) {
+                mixed = Rng::new(mixed ^ x).next_u64();
+            }
+            thunk(h, &mut Rng::new(mixed))
+        });
+        self.athunks[id.0].volatile = true;
+        id
+    }
+
+    /// Changes the graph's seed, so that every stochastic node draws new random numbers.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        let seed_node = self.seed_node();
+        let rotations = self.compute(seed_node, &[]).unwrap();
+        self.update_aref(seed_node, rotations + 1.0);
+    }
+
+    fn seed_node(&mut self) -> ARefID {
+        match self.seed_node {
+            Some(id) => id,
+            None => {
+                let id = self.new_aref(0.0);
+                self.seed_node = Some(id);
+                id
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Graph;
+
+    #[test]
+    fn stochastic() {
+        let mut graph = Graph::new();
+
+        let n = graph.new_aref(1000.0);
+        let estimate = graph.new_stochastic_athunk(0, move |h, rng| {
+            h.add_edge(n);
+            let n = h.compute(n, &[]).unwrap() as usize;
+            let inside = (0..n)
+                .filter(|_| {
+                    let (x, y) = (rng.next_f64(), rng.next_f64());
+                    x * x + y * y < 1.0
+                })
+                .count();
+            4.0 * inside as f64 / n as f64
+        });
+
+        let first = graph.compute(estimate, &[]).unwrap();
+        assert!((first - std::f64::consts::PI).abs() < 0.2);
+
+        // Recomputing for another reason draws the same numbers again.
+        graph.update_aref(n, 1000.0);
+        assert_eq!(Ok(first), graph.compute(estimate, &[]));
+
+        graph.set_seed(1);
+        let second = graph.compute(estimate, &[]).unwrap();
+        assert_ne!(first, second);
+        graph.set_seed(0);
+        assert_eq!(Ok(first), graph.compute(estimate, &[]));
+    }
+
+    #[test]
+    fn stochastic_nodes_survive_compaction() {
+        let mut graph = Graph::new();
+        let doomed = graph.new_aref(0.0);
+        let other = graph.new_stochastic_athunk(0, |_, rng| rng.next_f64());
+        // Last, so that compacting moves it into the hole that `doomed` leaves.
+        let draw = graph.new_stochastic_athunk(0, |_, rng| rng.next_f64());
+        let first = graph.compute(draw, &[]).unwrap();
+        assert_ne!(Ok(first), graph.compute(other, &[]));
+
+        graph.retain(|id, _| id != doomed.id());
+        let moved = graph.compact().get(draw);
+        assert_ne!(draw, moved);
+        let draw = moved;
+        assert_eq!(Ok(first), graph.compute(draw, &[]));
+        assert_eq!(Ok(first), graph.compute(draw, &[]));
+        assert!(graph.node_config(draw).volatile);
+    }
+}
@@ -0,0 +1,228 @@
+//! Helpers for testing that graphs are as incremental as they should be.
+
+use crate::{AThunkID, AdaptonError, Graph, MemoHasher, Scalar};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+impl<T: Scalar, S: MemoHasher> Graph<T, S> {
+    /// Runs `f` and panics unless exactly the `expected` nodes were re-executed, each exactly the
+    /// given number of times. Every node not listed must not have been re-executed at all.
+    #[track_caller]
+    pub fn assert_recomputes(
+        &mut self,
+        expected: &[(AThunkID, usize)],
+        f: impl FnOnce(&mut Graph<T, S>),
+    ) {
+        let before = self.versions();
+        f(self);
+        let after = self.versions();
+
+        let actual: BTreeMap<usize, usize> = after
+            .iter()
+            .map(|(&i, &v)| {
+                (
+                    i,
+                    v.saturating_sub(before.get(&i).copied().unwrap_or(0)) as usize,
+                )
+            })
+            .filter(|&(_, n)| n > 0)
+            .collect();
+        let mut wanted = BTreeMap::new();
+        for &(id, n) in expected {
+            *wanted.entry(id.0).or_insert(0) += n;
+        }
+        wanted.retain(|_, n| *n > 0);
+
+        if actual != wanted {
+            panic!(
+                "unexpected recomputations (node index => times re-executed)\n  expected: {:?}\n    actual: {:?}",
+                wanted, actual
+            );
+        }
+    }
+
+    /// Computes every root with the same arguments, for comparing with a snapshot taken some other
+    /// time, such as before an update or from a graph built from scratch.
+    pub fn values_snapshot(&self, roots: &[AThunkID], args: &[f64]) -> Snapshot<T> {
+        Snapshot {
+            values: roots
+                .iter()
+                .map(|&id| (id, self.compute(id, args)))
+                .collect(),
+        }
+    }
+
+    fn versions(&self) -> BTreeMap<usize, u64> {
+        self.athunks
+            .iter()
+            .map(|(i, athunk)| (i, athunk.version.get()))
+            .collect()
+    }
+}
+
+/// The values of a set of root nodes, as taken by `Graph::values_snapshot`. It displays as one
+/// line per root, which makes for a handy golden file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot<T = f64> {
+    values: BTreeMap<AThunkID, Result<T, AdaptonError>>,
+}
+
+impl<T: Scalar> Snapshot<T> {
+    pub fn get(&self, id: AThunkID) -> Option<&Result<T, AdaptonError>> {
+        self.values.get(&id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (AThunkID, &Result<T, AdaptonError>)> {
+        self.values.iter().map(|(&id, v)| (id, v))
+    }
+}
+
+impl<T: Scalar> fmt::Display for Snapshot<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (id, value) in self.values.iter() {
+            match value {
+                Ok(v) => writeln!(f, "{}: {:?}", id, v)?,
+                Err(e) => writeln!(f, "{}: error: {}", id, e)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Computes the node, then computes it again and panics if that ran any thunk at all, which would
+/// mean that something in the graph isn't being memoized. Returns the value.
+#[track_caller]
+pub fn assert_fully_cached<T: Scalar, S: MemoHasher>(
+    graph: &Graph<T, S>,
+    id: impl Into<AThunkID>,
+    args: &[f64],
+) -> T {
+    let id = id.into();
+    let first = graph.compute(id, args);
+    let runs = graph.runs.get();
+    let again = graph.compute(id, args);
+    let reran = graph.runs.get() - runs;
+    if reran > 0 {
+        panic!("computing {} again re-ran {} thunks", id, reran);
+    }
+    match (first, again) {
+        (Ok(v), Ok(w)) if v == w => v,
+        (first, again) => panic!("{} gave {:?} and then {:?}", id, first, again),
+    }
+}
+
+/// Panics unless both snapshots have the same roots with the same values, listing every root that
+/// differs.
+#[track_caller]
+pub fn assert_snapshot_eq<T: Scalar>(left: &Snapshot<T>, right: &Snapshot<T>) {
+    let mut diffs = String::new();
+    let roots: BTreeSet<AThunkID> = left
+        .values
+        .keys()
+        .chain(right.values.keys())
+        .copied()
+        .collect();
+    for id in roots {
+        let (l, r) = (left.get(id), right.get(id));
+        if l != r {
+            diffs += &format!("\n  {}: {:?} != {:?}", id, l, r);
+        }
+    }
+    if !diffs.is_empty() {
+        panic!("snapshots differ (left != right):{}", diffs);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assert_fully_cached, assert_snapshot_eq};
+    use crate::Graph;
+
+    #[test]
+    fn assert_recomputes() {
+        let mut graph = Graph::new();
+
+        let r = graph.new_aref(1.0);
+        let a = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap() + 1.0
+        });
+        graph.compute(a, &[]).unwrap();
+
+        graph.assert_recomputes(&[(r.id(), 1), (a, 1)], |g| {
+            g.update_aref(r, 2.0);
+            g.compute(a, &[]).unwrap();
+            g.compute(a, &[]).unwrap();
+        });
+        graph.assert_recomputes(&[], |g| {
+            g.compute(a, &[]).unwrap();
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "unexpected recomputations")]
+    fn assert_recomputes_fails() {
+        let mut graph = Graph::new();
+
+        let r = graph.new_aref(1.0);
+        graph.assert_recomputes(&[], |g| {
+            g.compute(r, &[]).unwrap();
+        });
+    }
+
+    #[test]
+    fn snapshots() {
+        let mut graph = Graph::new();
+
+        let r = graph.new_aref(2.0);
+        let a = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap() * h.args[0]
+        });
+        let before = graph.values_snapshot(&[a, r.id()], &[3.0]);
+        assert_eq!("node 0: 2.0\nnode 1: 6.0\n", before.to_string());
+
+        graph.update_aref(r, 4.0);
+        graph.update_aref(r, 2.0);
+        assert_snapshot_eq(&before, &graph.values_snapshot(&[r.id(), a], &[3.0]));
+        graph.update_aref(r, 1.0);
+        assert_eq!(Some(&Ok(3.0)), graph.values_snapshot(&[a], &[3.0]).get(a));
+    }
+
+    #[test]
+    #[should_panic(expected = "node 1: Some(Ok(6.0)) != Some(Ok(3.0))")]
+    fn assert_snapshot_eq_fails() {
+        let mut graph = Graph::new();
+
+        let r = graph.new_aref(2.0);
+        let a = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap() * 3.0
+        });
+        let before = graph.values_snapshot(&[a], &[]);
+        graph.update_aref(r, 1.0);
+        assert_snapshot_eq(&before, &graph.values_snapshot(&[a], &[]));
+    }
+
+    #[test]
+    fn fully_cached() {
+        let mut graph = Graph::new();
+
+        let r = graph.new_aref(2.0);
+        let a = graph.new_athunk(move |h| {
+            h.add_edge(r);
+            h.compute(r, &[]).unwrap() * h.args[0]
+        });
+        assert_eq!(6.0, assert_fully_cached(&graph, a, &[3.0]));
+    }
+
+    #[test]
+    #[should_panic(expected = "computing node 0 again re-ran 1 thunks")]
+    fn fully_cached_fails() {
+        let mut graph = Graph::new();
+
+        let a = graph.new_athunk(|_| 0.0);
+        graph.set_incremental(false);
+        assert_fully_cached(&graph, a, &[]);
+    }
+}
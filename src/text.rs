@@ -0,0 +1,298 @@
+//! Strings, with combinators that only recompute the pieces of text a change affects.
+//!
+//! Texts are ropes: `concat` only records which texts it joins, and the string itself is only
+//! built when it's asked for, so an edit costs a recomputation per concatenation above it rather
+//! than a copy of everything they hold. A node's value has to be a scalar, so a text node's value
+//! is a version number that goes up whenever its text may have changed. Arefs only move on to a
+//! new version when they're given a different string, concatenations when one of their parts
+//! does, and slices when the bytes in their range do, which means cutoff works as it does for any
+//! other node without ever mistaking a change for no change.
+
+use crate::{ARefID, AThunkID, AdaptonError, Graph, Handle, MemoHasher};
+use std::cell::RefCell;
+use std::ops::Range;
+use std::rc::Rc;
+
+/// A string-valued node, created by `new_atext` or built from others by `concat` and `slice`.
+#[derive(Clone, Debug)]
+pub struct AText {
+    id: AThunkID,
+    // Set for texts created by `new_atext`, which are the only ones that can be updated.
+    aref: Option<ARefID>,
+    rope: Rc<RefCell<Rope>>,
+}
+
+// A text as of its node's last run.
+#[derive(Debug)]
+struct Rope {
+    piece: Piece,
+    len: usize,
+    version: u64,
+    // The whole string, once it's been asked for.
+    built: Option<Rc<str>>,
+}
+
+#[derive(Debug)]
+enum Piece {
+    // The text of an aref or a slice.
+    Leaf(Rc<str>),
+    // Two texts one after another, along with the versions they were at.
+    Concat(AText, AText, Option<(u64, u64)>),
+}
+
+impl<S: MemoHasher> Graph<f64, S> {
+    pub fn new_atext(&mut self, text: &str) -> AText {
+        let aref = self.new_aref(0.0);
+        AText {
+            id: aref.id(),
+            aref: Some(aref),
+            rope: Rope::leaf(text.into()),
+        }
+    }
+
+    /// Panics unless `atext` was created by `new_atext`.
+    pub fn update_atext(&mut self, atext: &AText, text: &str) {
+        let id = atext
+            .aref
+            .expect("only texts from new_atext can be updated");
+        let version = {
+            let mut rope = atext.rope.borrow_mut();
+            if !rope.set_leaf(text) {
+                return;
+            }
+            rope.version
+        };
+        self.update_aref(id, version as f64);
+    }
+
+    /// The texts one after another. The concatenation is built as a balanced tree, so changing one
+    /// part only recomputes the concatenations on its way up to the root.
+    pub fn concat(&mut self, parts: &[AText]) -> AText {
+        match parts {
+            [] => self.new_atext(""),
+            [part] => part.clone(),
+            _ => {
+                let (left, right) = parts.split_at(parts.len() / 2);
+                let (left, right) = (self.concat(left), self.concat(right));
+                let rope = Rc::new(RefCell::new(Rope {
+                    piece: Piece::Concat(left, right, None),
+                    len: 0,
+                    version: 0,
+                    built: None,
+                }));
+                let cell = rope.clone();
+                let id = self.new_athunk(move |h: &mut Handle<f64, S>| {
+                    let (left, right) = match &cell.borrow().piece {
+                        Piece::Concat(left, right, _) => (left.clone(), right.clone()),
+                        Piece::Leaf(_) => unreachable!(),
+                    };
+                    let (l, r) = match (left.version(h), right.version(h)) {
+                        (Ok(l), Ok(r)) => (l, r),
+                        // The handle remembers the error, so this value is never seen.
+                        _ => return f64::NAN,
+                    };
+                    let len = left.rope.borrow().len + right.rope.borrow().len;
+                    let mut rope = cell.borrow_mut();
+                    rope.len = len;
+                    if let Piece::Concat(_, _, seen) = &mut rope.piece {
+                        if *seen != Some((l, r)) {
+                            *seen = Some((l, r));
+                            rope.version += 1;
+                            rope.built = None;
+                        }
+                    }
+                    rope.version as f64
+                });
+                AText {
+                    id,
+                    aref: None,
+                    rope,
+                }
+            }
+        }
+    }
+
+    /// The bytes of `atext` in `range`, cut short if the text is shorter. Like slicing a `str`,
+    /// this panics if the range doesn't fall on character boundaries. Only the pieces of `atext`
+    /// that overlap the range are looked at.
+    pub fn slice(&mut self, atext: &AText, range: Range<usize>) -> AText {
+        let atext = atext.clone();
+        let rope = Rope::leaf("".into());
+        let cell = rope.clone();
+        let id = self.new_athunk(move |h: &mut Handle<f64, S>| {
+            if atext.version(h).is_err() {
+                // The handle remembers the error, so this value is never seen.
+                return f64::NAN;
+            }
+            let mut text = String::new();
+            let end = range.end.min(atext.rope.borrow().len);
+            atext.write(range.start.min(end)..end, &mut text);
+            let mut rope = cell.borrow_mut();
+            rope.set_leaf(&text);
+            rope.version as f64
+        });
+        AText {
+            id,
+            aref: None,
+            rope,
+        }
+    }
+}
+
+impl Rope {
+    fn leaf(text: Rc<str>) -> Rc<RefCell<Rope>> {
+        Rc::new(RefCell::new(Rope {
+            len: text.len(),
+            piece: Piece::Leaf(text),
+            version: 0,
+            built: None,
+        }))
+    }
+
+    // Changes a leaf's text, and returns whether it's different.
+    fn set_leaf(&mut self, text: &str) -> bool {
+        if matches!(&self.piece, Piece::Leaf(old) if **old == *text) {
+            return false;
+        }
+        self.piece = Piece::Leaf(text.into());
+        self.len = text.len();
+        self.version += 1;
+        self.built = None;
+        true
+    }
+}
+
+impl AText {
+    pub fn id(&self) -> AThunkID {
+        self.id
+    }
+
+    pub fn len<S: MemoHasher>(&self, graph: &Graph<f64, S>) -> Result<usize, AdaptonError> {
+        graph.compute(self.id, &[])?;
+        Ok(self.rope.borrow().len)
+    }
+
+    pub fn is_empty<S: MemoHasher>(&self, graph: &Graph<f64, S>) -> Result<bool, AdaptonError> {
+        Ok(self.len(graph)? == 0)
+    }
+
+    /// Brings the text up to date and returns it. The string is built the first time it's asked
+    /// for after a change, and kept until the next one.
+    pub fn value<S: MemoHasher>(&self, graph: &Graph<f64, S>) -> Result<Rc<str>, AdaptonError> {
+        graph.compute(self.id, &[])?;
+        Ok(self.build())
+    }
+
+    /// Adds an edge to the text and returns it.
+    pub fn read<S: MemoHasher>(&self, h: &mut Handle<f64, S>) -> Result<Rc<str>, AdaptonError> {
+        self.version(h)?;
+        Ok(self.build())
+    }
+
+    // Adds an edge to the text, brings it up to date and returns its version.
+    fn version<S: MemoHasher>(&self, h: &mut Handle<f64, S>) -> Result<u64, AdaptonError> {
+        h.add_edge(self.id);
+        h.compute(self.id, &[])?;
+        Ok(self.rope.borrow().version)
+    }
+
+    fn build(&self) -> Rc<str> {
+        if let Some(built) = &self.rope.borrow().built {
+            return built.clone();
+        }
+        let mut text = String::with_capacity(self.rope.borrow().len);
+        self.write(0..self.rope.borrow().len, &mut text);
+        let built: Rc<str> = text.into();
+        self.rope.borrow_mut().built = Some(built.clone());
+        built
+    }
+
+    // Appends the bytes in `range`, which must be within the text, skipping pieces outside it.
+    fn write(&self, range: Range<usize>, out: &mut String) {
+        let rope = self.rope.borrow();
+        match &rope.piece {
+            Piece::Leaf(text) => out.push_str(&text[range]),
+            Piece::Concat(left, right, _) => {
+                let split = left.rope.borrow().len;
+                if range.start < split {
+                    left.write(range.start..range.end.min(split), out);
+                }
+                if range.end > split {
+                    right.write(range.start.max(split) - split..range.end - split, out);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AThunkID, Cutoff, Graph};
+
+    #[test]
+    fn concat_and_slice() {
+        let mut graph = Graph::new();
+        graph.set_cutoff(Cutoff::Exact);
+
+        let parts: Vec<_> = ["Hello", ", ", "world", "!"]
+            .iter()
+            .map(|s| graph.new_atext(s))
+            .collect();
+        let greeting = graph.concat(&parts);
+        let greeted = graph.slice(&greeting, 7..12);
+        let first = graph.concat(&parts[..2]);
+        assert_eq!("Hello, world!", &*greeting.value(&graph).unwrap());
+        assert_eq!("world", &*greeted.value(&graph).unwrap());
+        assert_eq!("Hello, ", &*first.value(&graph).unwrap());
+
+        // Only the concatenation of the changed part's half is recomputed, not the other half's.
+        let second_half = AThunkID(parts[3].id().0 + 2);
+        let expected = [
+            (parts[2].id(), 1),
+            (second_half, 1),
+            (greeting.id(), 1),
+            (greeted.id(), 1),
+        ];
+        graph.assert_recomputes(&expected, |g| {
+            g.update_atext(&parts[2], "there");
+            assert_eq!("Hello, there!", &*greeting.value(g).unwrap());
+            assert_eq!("there", &*greeted.value(g).unwrap());
+            assert_eq!("Hello, ", &*first.value(g).unwrap());
+        });
+    }
+
+    #[test]
+    fn unchanged_slices_cut_off() {
+        let mut graph = Graph::new();
+        graph.set_cutoff(Cutoff::Exact);
+
+        let parts: Vec<_> = ["ab", "cd", "ef", "gh"]
+            .iter()
+            .map(|s| graph.new_atext(s))
+            .collect();
+        let all = graph.concat(&parts);
+        let middle = graph.slice(&all, 1..5);
+        let m = middle.clone();
+        let shout = graph.new_athunk(move |h| m.read(h).unwrap().len() as f64);
+        assert_eq!(Ok(4.0), graph.compute(shout, &[]));
+        assert_eq!(Ok(8), all.len(&graph));
+
+        // The slice is re-run, but its bytes are the same, so nothing that reads it is.
+        let second_half = AThunkID(parts[3].id().0 + 2);
+        let expected = [
+            (parts[3].id(), 1),
+            (second_half, 1),
+            (all.id(), 1),
+            (middle.id(), 1),
+        ];
+        graph.assert_recomputes(&expected, |g| {
+            g.update_atext(&parts[3], "GH");
+            assert_eq!("bcde", &*middle.value(g).unwrap());
+        });
+        graph.update_atext(&parts[1], "üd");
+        assert_eq!("büd", &*middle.value(&graph).unwrap());
+        assert_eq!("abüdefGH", &*all.value(&graph).unwrap());
+        // Giving an aref the text it already has doesn't invalidate anything.
+        graph.assert_recomputes(&[], |g| g.update_atext(&parts[0], "ab"));
+    }
+}